@@ -188,7 +188,8 @@ impl Apu {
     pub fn read(&self, addr: u16) -> u8 {
         let reg: APUChannelReg = ((addr & 0xF) % 5).into();
         let or_value = if !(0xFF30..=0xFF3F).contains(&addr) {
-            READ_OR_VALUES[(addr as usize & 0xFF) - 0x10]
+            // 0xFF27-0xFF2F are unused and read as 0xFF
+            READ_OR_VALUES.get((addr as usize & 0xFF) - 0x10).copied().unwrap_or(0xFF)
         } else {
             0
         };
@@ -209,6 +210,7 @@ impl Apu {
                     | (if self.noise.is_running() { 0x8 } else { 0 })
             },
             0xFF30..=0xFF3F => self.wave_ch.read_wave_ram(addr as usize & 0xF),
+            0xFF27..=0xFF2F => 0xFF,
             _ => {
                 error!("Reading from invalid APU register {:04X}!", addr);
                 0xFF
@@ -12,7 +12,32 @@ use crate::joypad::Joypad;
 use crate::memory::cartridge::Cartridge;
 use crate::timers::Timer;
 use crate::memory::dma::{DmaController, HdmaController};
-use crate::memory::WRAM;
+use crate::memory::{WRAM, WRAM_BANKS};
+use crate::memory::search::MemorySnapshot;
+#[cfg(feature = "debugging")]
+use crate::debug::cdl::RomAccess;
+
+const SRAM_BANK_SIZE: usize = 0x2000;
+
+/// The CPU's view of the address space. Implemented by `BusController` for the real system;
+/// tests and fuzzers can plug in their own memory instead, see `Cpu::new`.
+pub trait MemoryInterface {
+    #[track_caller]
+    fn read(&self, addr: u16) -> u8;
+    #[track_caller]
+    fn write(&mut self, addr: u16, val: u8);
+
+    /// The ROM bank mapped at 0x4000-0x7FFF.
+    #[cfg(feature = "debugging")]
+    fn rom_bank(&self) -> usize { 1 }
+    /// Reads a byte, telling the code/data logger how a ROM byte is being accessed.
+    #[cfg(feature = "debugging")]
+    fn read_as(&self, addr: u16, _access: RomAccess) -> u8 { self.read(addr) }
+    /// Reads a byte for the debugger, without any effect on the emulation or the code/data log.
+    /// See `Bus::peek`.
+    #[cfg(feature = "debugging")]
+    fn peek(&self, addr: u16) -> u8 { self.read(addr) }
+}
 
 pub(crate) struct BusController(Weak<RefCell<Bus>>);
 
@@ -37,6 +62,27 @@ impl BusController {
         } else { 0xFF }
     }
 
+    #[cfg(feature = "debugging")]
+    pub fn rom_bank(&self) -> usize {
+        if let Some(b) = self.0.upgrade() {
+            (*b).borrow().rom_bank()
+        } else { 1 }
+    }
+
+    #[cfg(feature = "debugging")]
+    pub fn read_as(&self, addr: u16, access: RomAccess) -> u8 {
+        if let Some(b) = self.0.upgrade() {
+            (*b).borrow().read_as(addr, access)
+        } else { 0xFF }
+    }
+
+    #[cfg(feature = "debugging")]
+    pub fn peek(&self, addr: u16) -> u8 {
+        if let Some(b) = self.0.upgrade() {
+            (*b).borrow().peek(addr, None)
+        } else { 0xFF }
+    }
+
     pub fn dma_read(&self, addr: u16) -> u8 {
         if let Some(b) = self.0.upgrade() {
             (*b).borrow_mut().dma_read(addr)
@@ -50,6 +96,33 @@ impl BusController {
     }
 }
 
+impl MemoryInterface for BusController {
+    #[track_caller]
+    fn read(&self, addr: u16) -> u8 {
+        BusController::read(self, addr)
+    }
+
+    #[track_caller]
+    fn write(&mut self, addr: u16, val: u8) {
+        BusController::write(self, addr, val)
+    }
+
+    #[cfg(feature = "debugging")]
+    fn rom_bank(&self) -> usize {
+        BusController::rom_bank(self)
+    }
+
+    #[cfg(feature = "debugging")]
+    fn read_as(&self, addr: u16, access: RomAccess) -> u8 {
+        BusController::read_as(self, addr, access)
+    }
+
+    #[cfg(feature = "debugging")]
+    fn peek(&self, addr: u16) -> u8 {
+        BusController::peek(self, addr)
+    }
+}
+
 pub(crate) struct Bus {
     timer: Rc<RefCell<Timer>>,
     joypad: Rc<RefCell<Joypad>>,
@@ -88,11 +161,6 @@ impl Bus {
         }))
     }
 
-    pub fn reset(&mut self) {
-        self.hram = vec![0; 0x7F];
-        self.iospace = vec![0; 0x80];
-    }
-
     pub fn get_controller(this: &Rc<RefCell<Self>>) -> BusController {
         BusController::new(Rc::downgrade(this))
     }
@@ -139,7 +207,7 @@ impl Bus {
                     }
                 }
             },
-            0xFF40..=0xFF4F | 0xFF68..=0xFF6B => (*self.ppu).borrow_mut().write(addr, val, false),
+            0xFF40..=0xFF4F | 0xFF68..=0xFF6C => (*self.ppu).borrow_mut().write(addr, val, false),
             0xFF70 => self.wram.switch_bank(val as usize & 0b111),
             _ => {
                 warn!("Write of value 0x{:X} to I/O port 0x{:X} unhandled", val, addr);
@@ -174,7 +242,7 @@ impl Bus {
                     res
                 }
             }, 
-            0xFF40..=0xFF4F | 0xFF68..=0xFF6B => (*self.ppu).borrow_mut().read(addr, false),
+            0xFF40..=0xFF4F | 0xFF68..=0xFF6C => (*self.ppu).borrow_mut().read(addr, false),
             0xFF50 => 1,
             0xFF51..=0xFF55 => if let Some(hdma) = self.hdma.as_ref() { 
                 hdma.borrow().read(addr) 
@@ -219,9 +287,90 @@ impl Bus {
         }
     }
 
+    #[cfg(feature = "debugging")]
+    pub fn rom_bank(&self) -> usize {
+        (*self.cartridge).borrow().rom_bank()
+    }
+
+    /// Copies every WRAM and cartridge RAM bank, mapped or not, and HRAM.
+    pub fn ram_snapshot(&self) -> MemorySnapshot {
+        let mut snapshot = MemorySnapshot::new();
+        let copy = |bank: usize, addrs: std::ops::Range<u16>| addrs.map(|addr| self.peek(addr, Some(bank))).collect();
+        snapshot.add_region(0, 0xC000, copy(0, 0xC000..0xD000));
+        for bank in 1..WRAM_BANKS {
+            snapshot.add_region(bank, 0xD000, copy(bank, 0xD000..0xE000));
+        }
+        let ram_size = (*self.cartridge).borrow().ram_size();
+        for bank in 0..ram_size.div_ceil(SRAM_BANK_SIZE) {
+            // Smaller RAMs, like the 512 bytes of MBC2, are mirrored over the rest of the bank
+            let len = (ram_size - bank * SRAM_BANK_SIZE).min(SRAM_BANK_SIZE) as u16;
+            snapshot.add_region(bank, 0xA000, copy(bank, 0xA000..0xA000 + len));
+        }
+        snapshot.add_region(0, 0xFF80, self.hram.clone());
+        snapshot
+    }
+
+    /// Reads ROM through the cartridge so the access is recorded in the code/data log.
+    #[cfg(feature = "debugging")]
+    pub fn read_as(&self, addr: u16, access: RomAccess) -> u8 {
+        if addr < 0x8000 && (*self.dma.as_ref().unwrap()).borrow().is_addr_accessible(addr) {
+            (*self.cartridge).borrow().read_as(addr, access)
+        } else {
+            self.read(addr)
+        }
+    }
+
+    /// Reads any address without the blocking, DMA checks and code/data logging of `read`.
+    /// `bank` selects the ROM, VRAM, cartridge RAM or WRAM bank, `None` reads the mapped one.
+    pub fn peek(&self, addr: u16, bank: Option<usize>) -> u8 {
+        match addr {
+            0x0000..=0x7FFF | 0xA000..=0xBFFF => (*self.cartridge).borrow().peek(addr, bank),
+            0x8000..=0x9FFF | 0xFE00..=0xFE9F => (*self.ppu).borrow().peek(addr, bank),
+            0xC000..=0xDFFF => self.wram.peek(addr, bank),
+            0xE000..=0xFDFF => self.wram.peek(addr - 0x2000, bank),
+            0xFEA0..=0xFEFF => 0x00,
+            0xFF00..=0xFF7F => self.peek_io(addr),
+            0xFF80..=0xFFFE => self.hram[addr as usize - 0xFF80],
+            0xFFFF => (*self.interrupts).borrow().get_interrupt_enable(),
+        }
+    }
+
+    /// Reads an I/O register like `read_io`, without logging unhandled ports. The CPU is only
+    /// looked at if it isn't running an instruction, KEY1 reads 0xFF otherwise.
+    fn peek_io(&self, addr: u16) -> u8 {
+        match addr {
+            0xFF4D => self.cpu.as_ref()
+                .and_then(|cpu| cpu.try_borrow().ok())
+                .map_or(0xFF, |cpu| {
+                    let speed = if matches!(cpu.speed(), Speed::Double) { 0x80 } else { 0x00 };
+                    speed | cpu.is_speed_switching() as u8
+                }),
+            0xFF00 | 0xFF02..=0xFF07 | 0xFF0F..=0xFF4C | 0xFF4E..=0xFF55 | 0xFF68..=0xFF6C | 0xFF70 => self.read_io(addr),
+            _ => self.iospace[addr as usize - 0xFF00]
+        }
+    }
+
+    /// Writes any address without the blocking and DMA checks of `write`, and without going through
+    /// the MBC. I/O registers have no storage of their own and are written like the CPU would.
+    pub fn poke(&mut self, addr: u16, bank: Option<usize>, val: u8) {
+        match addr {
+            0x0000..=0x7FFF | 0xA000..=0xBFFF => (*self.cartridge).borrow_mut().poke(addr, bank, val),
+            0x8000..=0x9FFF | 0xFE00..=0xFE9F => (*self.ppu).borrow_mut().poke(addr, bank, val),
+            0xC000..=0xDFFF => self.wram.poke(addr, bank, val),
+            0xE000..=0xFDFF => self.wram.poke(addr - 0x2000, bank, val),
+            0xFEA0..=0xFEFF => {},
+            0xFF00..=0xFF7F => self.write_io(addr, val),
+            0xFF80..=0xFFFE => self.hram[addr as usize - 0xFF80] = val,
+            0xFFFF => (*self.interrupts).borrow_mut().set_interrupt_enable(val),
+        }
+    }
+
     pub(crate) fn dma_read(&self, addr: u16) -> u8 {
         match addr {
             0x8000..=0x9FFF | 0xFE00..=0xFE9F => (*self.ppu).borrow_mut().read(addr, true),
+            #[cfg(feature = "debugging")]
+            0x0000..=0x7FFF | 0xA000..=0xBFFF => (*self.cartridge).borrow().read_as(addr, RomAccess::Dma),
+            #[cfg(not(feature = "debugging"))]
             0x0000..=0x7FFF | 0xA000..=0xBFFF => (*self.cartridge).borrow().read(addr),
             0xC000..=0xDFFF => self.wram.read(addr),
             0xE000..=0xFDFF => self.wram.read(addr - 0x2000),
@@ -0,0 +1,284 @@
+// Copyright Antonio Porsia 2025. Licensed under the EUPL-1.2 or later.
+
+use std::collections::HashMap;
+use strfmt::strfmt;
+use super::instructions::{MNEMONICS, NARGS};
+use super::prefixed_insts::PREFIXED_MNEMONICS;
+
+const ROM_BANK_SIZE: usize = 0x4000;
+/// Longest instruction encoding of the SM83 (opcode + 16-bit operand).
+const MAX_INSTRUCTION_LEN: usize = 3;
+
+/// A single decoded SM83 instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    /// The ROM bank the instruction was decoded from. Always 0 below 0x4000.
+    pub bank: usize,
+    /// The address of the opcode in the CPU address space.
+    pub addr: u16,
+    /// The raw bytes of the instruction, opcode included.
+    pub bytes: Vec<u8>,
+    /// The formatted instruction, e.g. `JR NZ, $0150`.
+    pub text: String,
+    /// The absolute destination of jumps, calls and restarts, if statically known.
+    pub target: Option<u16>,
+}
+
+impl Instruction {
+    /// Returns the size of the instruction in bytes.
+    pub fn size(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Returns the address of the instruction that follows this one.
+    pub fn next_addr(&self) -> u16 {
+        self.addr.wrapping_add(self.size() as u16)
+    }
+
+    /// Returns `true` for instructions that push a return address (CALL and RST).
+    pub fn is_call(&self) -> bool {
+        matches!(self.bytes[0], 0xC4 | 0xCC | 0xCD | 0xD4 | 0xDC) || is_rst(self.bytes[0])
+    }
+}
+
+fn is_rst(opcode: u8) -> bool {
+    opcode & 0xC7 == 0xC7
+}
+
+/// Decodes the instruction at `addr`.
+///
+/// # Arguments
+///
+/// * `bank` - The ROM bank mapped at 0x4000-0x7FFF.
+/// * `addr` - The address of the opcode.
+/// * `read` - Used to fetch the instruction bytes, given their address.
+///
+/// # Returns
+///
+/// The decoded instruction.
+pub fn decode<F: Fn(u16) -> u8>(bank: usize, addr: u16, read: F) -> Instruction {
+    let opcode = read(addr);
+    let mut bytes = vec![opcode];
+    for i in 0..NARGS[opcode as usize] {
+        bytes.push(read(addr.wrapping_add(1 + i as u16)));
+    }
+    let next_addr = addr.wrapping_add(bytes.len() as u16);
+
+    let mut vars = HashMap::new();
+    let mut target = None;
+    let format = match (opcode, bytes.len()) {
+        (0xCB, _) => PREFIXED_MNEMONICS[bytes[1] as usize],
+        (0x18 | 0x20 | 0x28 | 0x30 | 0x38, _) => {
+            let dest = next_addr.wrapping_add(bytes[1] as i8 as u16);
+            vars.insert("arg8".to_string(), format!("${:04X}", dest));
+            target = Some(dest);
+            MNEMONICS[opcode as usize]
+        },
+        (_, 2) => {
+            vars.insert("arg8".to_string(), format!("${:02X}", bytes[1]));
+            MNEMONICS[opcode as usize]
+        },
+        (_, 3) => {
+            let arg = (bytes[2] as u16) << 8 | bytes[1] as u16;
+            vars.insert("arg16".to_string(), format!("${:04X}", arg));
+            if matches!(opcode, 0xC2 | 0xC3 | 0xCA | 0xD2 | 0xDA | 0xC4 | 0xCC | 0xCD | 0xD4 | 0xDC) {
+                target = Some(arg);
+            }
+            MNEMONICS[opcode as usize]
+        },
+        _ => {
+            if is_rst(opcode) {
+                target = Some((opcode & 0x38) as u16);
+            }
+            MNEMONICS[opcode as usize]
+        }
+    };
+
+    Instruction {
+        bank: if addr < 0x4000 { 0 } else { bank },
+        addr,
+        bytes,
+        text: strfmt(format, &vars).unwrap_or_else(|_| format.to_string()),
+        target,
+    }
+}
+
+/// Decodes `count` consecutive instructions starting at `addr`, with `bank` mapped at 0x4000-0x7FFF.
+/// Stops early if the address space wraps around.
+pub fn decode_forward<F: Fn(u16) -> u8>(bank: usize, addr: u16, count: usize, read: F) -> Vec<Instruction> {
+    let mut instructions = Vec::with_capacity(count);
+    let mut cur = addr;
+    while instructions.len() < count {
+        let inst = decode(bank, cur, &read);
+        let next = inst.next_addr();
+        instructions.push(inst);
+        if next < cur {
+            break;
+        }
+        cur = next;
+    }
+    instructions
+}
+
+/// Decodes up to `count` instructions ending right before `addr`.
+///
+/// Since instructions have variable length, there is no way to know for sure where the preceding
+/// instructions start. This tries every start address within reach and keeps the earliest one
+/// whose instruction stream lands exactly on `addr`, which is usually the one that is aligned
+/// with the real code.
+///
+/// # Arguments
+///
+/// * `bank` - The ROM bank mapped at 0x4000-0x7FFF.
+/// * `addr` - The address of the instruction the result should lead up to.
+/// * `count` - The maximum number of instructions to return.
+/// * `read` - Used to fetch the instruction bytes, given their address.
+///
+/// # Returns
+///
+/// The decoded instructions, in address order. May be shorter than `count`.
+pub fn decode_backwards<F: Fn(u16) -> u8>(bank: usize, addr: u16, count: usize, read: F) -> Vec<Instruction> {
+    // No further back than the start of the address space
+    let window = count.saturating_mul(MAX_INSTRUCTION_LEN).min(addr as usize) as u16;
+    let first = addr - window;
+    for start in first..addr {
+        let mut instructions = Vec::new();
+        let mut cur = start;
+        while cur < addr {
+            let inst = decode(bank, cur, &read);
+            cur = cur.saturating_add(inst.size() as u16);
+            instructions.push(inst);
+        }
+        if cur == addr {
+            let skip = instructions.len().saturating_sub(count);
+            return instructions.split_off(skip);
+        }
+    }
+    Vec::new()
+}
+
+/// A disassembler working directly on a ROM image, independent of the current MBC state.
+pub struct Disassembler<'a> {
+    rom: &'a [u8],
+    /// Code/data log flags, one byte per ROM byte.
+    #[cfg(feature = "debugging")]
+    cdl: Option<&'a [u8]>,
+}
+
+impl<'a> Disassembler<'a> {
+    /// Creates a disassembler for the given ROM image.
+    pub fn new(rom: &'a [u8]) -> Self {
+        Self {
+            rom,
+            #[cfg(feature = "debugging")]
+            cdl: None,
+        }
+    }
+
+    /// Uses a code/data log to tell code apart from data: bytes that have been read as data or DMA'd
+    /// but never executed are shown as `DB` instead of being decoded.
+    #[cfg(feature = "debugging")]
+    pub fn with_code_data_log(mut self, flags: &'a [u8]) -> Self {
+        self.cdl = Some(flags);
+        self
+    }
+
+    #[cfg(feature = "debugging")]
+    fn is_data(&self, bank: usize, addr: u16) -> bool {
+        use crate::debug::cdl::{CODE, DATA, DMA, OPERAND};
+        let flags = self.cdl
+            .zip(self.rom_offset(bank, addr))
+            .and_then(|(cdl, offset)| cdl.get(offset).copied())
+            .unwrap_or(0);
+        flags & (DATA | DMA) != 0 && flags & (CODE | OPERAND) == 0
+    }
+
+    /// Returns the number of 16 KiB banks in the ROM image.
+    pub fn num_banks(&self) -> usize {
+        self.rom.len().div_ceil(ROM_BANK_SIZE)
+    }
+
+    /// Maps a CPU address to an offset in the ROM image, as if `bank` were mapped at 0x4000-0x7FFF.
+    ///
+    /// # Returns
+    ///
+    /// The offset in the ROM image, or `None` if the address is outside of ROM.
+    pub fn rom_offset(&self, bank: usize, addr: u16) -> Option<usize> {
+        let offset = match addr {
+            0x0000..=0x3FFF => addr as usize,
+            0x4000..=0x7FFF => bank * ROM_BANK_SIZE + (addr as usize - ROM_BANK_SIZE),
+            _ => return None
+        };
+        (offset < self.rom.len()).then_some(offset)
+    }
+
+    /// Reads a byte from the ROM image, as if `bank` were mapped at 0x4000-0x7FFF.
+    /// Addresses outside of ROM read as 0xFF.
+    pub fn read(&self, bank: usize, addr: u16) -> u8 {
+        self.rom_offset(bank, addr).map_or(0xFF, |offset| self.rom[offset])
+    }
+
+    /// Decodes the instruction at `addr` in `bank`.
+    pub fn decode(&self, bank: usize, addr: u16) -> Instruction {
+        #[cfg(feature = "debugging")]
+        if self.is_data(bank, addr) {
+            let byte = self.read(bank, addr);
+            return Instruction {
+                bank: if addr < 0x4000 { 0 } else { bank },
+                addr,
+                bytes: vec![byte],
+                text: format!("DB ${:02X}", byte),
+                target: None,
+            };
+        }
+        decode(bank, addr, |a| self.read(bank, a))
+    }
+
+    /// Disassembles up to `count` instructions starting at `addr` in `bank`.
+    /// Stops at the end of the ROM address space.
+    pub fn disassemble(&self, bank: usize, addr: u16, count: usize) -> Vec<Instruction> {
+        let mut instructions = Vec::with_capacity(count);
+        let mut cur = addr;
+        while instructions.len() < count && self.rom_offset(bank, cur).is_some() {
+            let inst = self.decode(bank, cur);
+            cur = inst.next_addr();
+            instructions.push(inst);
+        }
+        instructions
+    }
+
+    /// Disassembles up to `count` instructions leading up to `addr` in `bank`.
+    /// See [`decode_backwards`] for how instruction boundaries are found.
+    pub fn disassemble_backwards(&self, bank: usize, addr: u16, count: usize) -> Vec<Instruction> {
+        decode_backwards(bank, addr, count, |a| self.read(bank, a))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_backwards_over_multi_byte_instructions() {
+        let mut memory = vec![0u8; 0x200];
+        // JP $0150 and LD A, $12 after a NOP
+        memory[0x101..0x106].copy_from_slice(&[0xC3, 0x50, 0x01, 0x3E, 0x12]);
+        let read = |addr: u16| memory.get(addr as usize).copied().unwrap_or(0);
+
+        let instructions = decode_backwards(0, 0x106, 2, read);
+        let addrs: Vec<u16> = instructions.iter().map(|inst| inst.addr).collect();
+        assert_eq!(addrs, [0x101, 0x104]);
+        assert_eq!(instructions[0].target, Some(0x0150));
+        assert_eq!(instructions[1].size(), 2);
+    }
+
+    #[test]
+    fn decodes_backwards_near_the_start_of_the_address_space() {
+        let read = |_: u16| 0x00;
+        let addrs: Vec<u16> = decode_backwards(0, 0x0002, 10, read).iter().map(|inst| inst.addr).collect();
+        assert_eq!(addrs, [0x0000, 0x0001]);
+        assert!(decode_backwards(0, 0x0000, 10, read).is_empty());
+        // A count too large for the window arithmetic
+        assert_eq!(decode_backwards(0, 0x8000, usize::MAX, read).len(), 0x8000);
+    }
+}
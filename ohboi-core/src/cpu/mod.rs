@@ -4,6 +4,9 @@ mod instructions;
 mod prefixed_insts;
 mod registers;
 pub mod interrupts;
+pub mod disassembler;
+#[cfg(test)]
+mod sm83_tests;
 
 pub use registers::*;
 
@@ -14,7 +17,7 @@ use std::fmt::Debug;
 use std::rc::Rc;
 use log::{debug, trace, warn};
 
-use crate::bus::BusController;
+use crate::bus::MemoryInterface;
 
 use registers::Register8::*;
 use registers::Register16::*;
@@ -24,10 +27,10 @@ use prefixed_insts::PREFIXED_INSTS;
 
 cfg_if! { 
     if #[cfg(feature = "debugging")] {
-        use std::collections::HashMap;
-        use strfmt::strfmt;
-        use instructions::{MNEMONICS, NARGS};
-        use prefixed_insts::PREFIXED_MNEMONICS;
+        use disassembler::Instruction;
+        use crate::debug::trace::Tracer;
+        use crate::debug::callstack::{CallStack, StackFrame};
+        use crate::debug::cdl::RomAccess;
     }
 }
 
@@ -101,9 +104,9 @@ impl CpuState {
     }
 }
 
-pub(crate) struct Cpu {
+pub struct Cpu {
     interrupt_controller: Rc<RefCell<InterruptController>>,
-    bus: BusController,
+    bus: Box<dyn MemoryInterface>,
     state: CpuState,
 
     registers: Registers,
@@ -121,11 +124,19 @@ pub(crate) struct Cpu {
     _cgb: bool,
     speed_switch_armed: bool,
     #[cfg(feature = "debugging")]
-    current_inst_pc: u16
+    current_inst_pc: u16,
+    #[cfg(feature = "debugging")]
+    tracer: Option<Tracer>,
+    #[cfg(feature = "debugging")]
+    call_stack: CallStack,
+    #[cfg(feature = "debugging")]
+    instruction_count: u64
 }
 
 impl Cpu {
-    pub fn new(interrupt_controller: Rc<RefCell<InterruptController>>, bus: BusController, cgb: bool) -> Self {
+    /// Builds a CPU in its post-boot state, with PC at 0x0100, running against `bus`.
+    /// Outside of a `GameBoy`, any `MemoryInterface` can stand in for the bus, e.g. to fuzz the CPU.
+    pub fn new(interrupt_controller: Rc<RefCell<InterruptController>>, bus: Box<dyn MemoryInterface>, cgb: bool) -> Self {
         trace!("Building CPU");
         Self {
             interrupt_controller,
@@ -143,40 +154,66 @@ impl Cpu {
             _cgb: cgb,
             speed_switch_armed: false,
             #[cfg(feature = "debugging")]
-            current_inst_pc: 0x0100
+            current_inst_pc: 0x0100,
+            #[cfg(feature = "debugging")]
+            tracer: None,
+            #[cfg(feature = "debugging")]
+            call_stack: CallStack::new(),
+            #[cfg(feature = "debugging")]
+            instruction_count: 0
         }
     }
 
-    pub fn reset(&mut self) {
-        debug!("Resetting CPU");
-        self.sp = 0xFFFE;
-        self.pc = 0x0100;
-        self.state = CpuState::Fetching { halt_bug: false };
+    pub fn registers(&self) -> &Registers {
+        &self.registers
+    }
 
-        self.speed = Speed::Normal;
-        self.speed_switch_armed = false;
-        self.cycles = 0;
-        self.registers.reset();
-        (*self.interrupt_controller).borrow_mut().reset();
-        
-        #[cfg(feature = "debugging")] {
-            self.current_inst_pc = 0x0100;
-        }
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.registers
     }
-    
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    pub fn sp(&self) -> u16 {
+        self.sp
+    }
+
+    pub fn set_sp(&mut self, sp: u16) {
+        self.sp = sp;
+    }
+
     pub fn speed(&self) -> Speed {
         self.speed
     }
 
     fn fetch(&mut self, halt_bug: bool) {
+        #[cfg(feature = "debugging")]
+        let opcode = self.bus.read_as(self.pc, RomAccess::Code);
+        #[cfg(not(feature = "debugging"))]
         let opcode = self.bus.read(self.pc);
         #[cfg(feature = "debugging")] {
             self.current_inst_pc = self.pc;
+            self.instruction_count += 1;
+            self.trace();
         }
         if !halt_bug { self.pc += 1; }
         self.state = CpuState::Decoding(opcode);
     }
 
+    /// Reads the operand byte at PC.
+    fn read_operand(&self) -> u8 {
+        #[cfg(feature = "debugging")]
+        return self.bus.read_as(self.pc, RomAccess::Operand);
+        #[cfg(not(feature = "debugging"))]
+        self.bus.read(self.pc)
+    }
+
     fn decode(&mut self, opcode: u8) {
         self.instruction = INSTRUCTIONS[opcode as usize];
         self.state = CpuState::StartedExecution;
@@ -254,7 +291,11 @@ impl Cpu {
                     CpuState::InterruptUpdatePC
                 },
                 CpuState::InterruptUpdatePC => {
+                    #[cfg(feature = "debugging")]
+                    let return_addr = self.pc;
                     self.pc = self.interrupts_to_handle.pop_front().unwrap();
+                    #[cfg(feature = "debugging")]
+                    self.enter_subroutine(return_addr, return_addr, true);
                     debug!("Finished servicing interrupt at cycle {}", self.cycles + self.elapsed);
                     self.cycles += self.elapsed;
                     self.elapsed = 0;
@@ -299,45 +340,59 @@ impl Cpu {
     }
     
     #[cfg(feature = "debugging")]
-    pub fn get_current_instructions(&self, window_size: Option<i32>) -> Vec<(usize, String)> {
-        let window_size = window_size.unwrap_or(16);
-        let mut instructions = Vec::new();
-        let mut i = 0;
-        let mut cur_inst_addr = self.current_inst_pc;
-        while i < window_size {
-            let opcode = self.bus.read(cur_inst_addr);
-            let mut vars = HashMap::new();
-            let mut next_inst_addr = cur_inst_addr.wrapping_add(1);
-            let mut format = String::from(MNEMONICS[opcode as usize]);
-            match NARGS[opcode as usize] {
-                1 => {
-                    let arg = self.bus.read(cur_inst_addr.wrapping_add(1));
-                    if opcode != 0xCB {
-                        vars.insert("arg8".to_string(), format!("${:02X}", arg));
-                    } else {
-                        format = String::from(PREFIXED_MNEMONICS[arg as usize]);
-                    }
-                    next_inst_addr = cur_inst_addr.wrapping_add(2);
-                },
-                2 => {
-                    let arg = self.bus.read(cur_inst_addr.wrapping_add(1));
-                    let arg2 = self.bus.read(cur_inst_addr.wrapping_add(2));
-                    let complete_arg = (arg2 as u16) << 8 | arg as u16;
-                    vars.insert("arg16".to_string(), format!("${:04X}", complete_arg));
-                    next_inst_addr = cur_inst_addr.wrapping_add(3);
-                },
-                _ => {}
-            }
-            instructions.push((cur_inst_addr as usize, strfmt(&format, &vars).unwrap()));
-            if next_inst_addr < cur_inst_addr {
-                break;
-            }
-            cur_inst_addr = next_inst_addr;
-            i += 1;
-        }
+    pub fn get_current_instructions(&self, before: usize, after: usize) -> Vec<Instruction> {
+        let bank = self.bus.rom_bank();
+        let read = |addr| self.bus.peek(addr);
+        let mut instructions = disassembler::decode_backwards(bank, self.current_inst_pc, before, read);
+        instructions.append(&mut disassembler::decode_forward(bank, self.current_inst_pc, after, read));
         instructions
     }
     
+    #[cfg(feature = "debugging")]
+    fn trace(&mut self) {
+        if let Some(ref mut tracer) = self.tracer {
+            if tracer.wants(self.pc, self.bus.rom_bank()) {
+                let pcmem = [0, 1, 2, 3].map(|i| self.bus.peek(self.pc.wrapping_add(i)));
+                tracer.record(Tracer::format_line(&self.registers, self.sp, self.pc, pcmem));
+            }
+        }
+    }
+
+    /// Records a new call stack frame. Must be called right after PC is set to the target.
+    #[cfg(feature = "debugging")]
+    fn enter_subroutine(&mut self, call_site: u16, return_addr: u16, interrupt: bool) {
+        let frame = StackFrame {
+            call_site,
+            target: self.pc,
+            return_addr,
+            sp: self.sp,
+            bank: self.bus.rom_bank(),
+            interrupt
+        };
+        self.call_stack.push(frame);
+    }
+
+    #[cfg(feature = "debugging")]
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) -> Option<Tracer> {
+        std::mem::replace(&mut self.tracer, tracer)
+    }
+
+    #[cfg(feature = "debugging")]
+    pub fn is_tracing(&self) -> bool {
+        self.tracer.is_some()
+    }
+
+    #[cfg(feature = "debugging")]
+    pub fn call_stack(&self) -> &CallStack {
+        &self.call_stack
+    }
+
+    /// Number of instructions fetched since the last reset.
+    #[cfg(feature = "debugging")]
+    pub fn instruction_count(&self) -> u64 {
+        self.instruction_count
+    }
+
     #[cfg(feature = "debugging")]
     pub fn get_current_inst_pc(&self) -> u16 {
         self.current_inst_pc
@@ -358,7 +413,7 @@ impl Cpu {
         }
         self.state = CpuState::ServicingInterrupts;
     }
-    pub fn state(&self) -> &CpuState {
+    pub(crate) fn state(&self) -> &CpuState {
         &self.state
     }
 
@@ -383,12 +438,12 @@ impl Cpu {
             match self.state {
                 CpuState::StartedExecution => CpuState::ReadArgLo,
                 CpuState::ReadArgLo => {
-                    self.registers.set_reg8(lo, self.bus.read(self.pc));
+                    self.registers.set_reg8(lo, self.read_operand());
                     self.pc += 1;
                     CpuState::ReadArgHi(0)
                 },
                 CpuState::ReadArgHi(_) => {
-                    self.registers.set_reg8(hi, self.bus.read(self.pc));
+                    self.registers.set_reg8(hi, self.read_operand());
                     self.pc += 1;
                     CpuState::FinishedExecution
                 },
@@ -401,12 +456,12 @@ impl Cpu {
             match self.state {
                 CpuState::StartedExecution => CpuState::ReadArgLo,
                 CpuState::ReadArgLo => {
-                    self.sp = (self.sp & 0xFF00) | self.bus.read(self.pc) as u16;
+                    self.sp = (self.sp & 0xFF00) | self.read_operand() as u16;
                     self.pc += 1;
                     CpuState::ReadArgHi(0xFF)
                 },
                 CpuState::ReadArgHi(_) => {
-                    self.sp = ((self.bus.read(self.pc) as u16) << 8) | (self.sp & 0xFF);
+                    self.sp = ((self.read_operand() as u16) << 8) | (self.sp & 0xFF);
                     self.pc += 1;
                     CpuState::FinishedExecution
                 },
@@ -506,12 +561,12 @@ impl Cpu {
             match self.state  {
                 CpuState::StartedExecution => CpuState::ReadArgLo,
                 CpuState::ReadArgLo => {
-                    let lo = self.bus.read(self.pc);
+                    let lo = self.read_operand();
                     self.pc += 1;
                     CpuState::ReadArgHi(lo)
                 },
                 CpuState::ReadArgHi(lo) => {
-                    let hi = self.bus.read(self.pc);
+                    let hi = self.read_operand();
                     self.pc += 1;
                     CpuState::WriteMemoryLo(((hi as u16) << 8) | (lo as u16), (self.sp & 0xFF) as u8)
                 },
@@ -627,7 +682,7 @@ impl Cpu {
             match self.state {
                 CpuState::StartedExecution => CpuState::ReadArg,
                 CpuState::ReadArg => {
-                    let val = self.bus.read(self.pc);
+                    let val = self.read_operand();
                     self.pc += 1;
                     let addr = self.registers.get_reg16(HL);
                     CpuState::WriteMemory(addr, val)
@@ -680,7 +735,7 @@ impl Cpu {
         self.state = match self.state {
             CpuState::StartedExecution => CpuState::ReadArgLo,
             CpuState::ReadArgLo => {
-                let arg = self.bus.read(self.pc);
+                let arg = self.read_operand();
                 self.pc += 1;
                 f(self, arg);
                 CpuState::FinishedExecution
@@ -788,7 +843,7 @@ impl Cpu {
         self.state = match self.state {
             CpuState::StartedExecution => CpuState::ReadArg,
             CpuState::ReadArg => {
-                let arg = self.bus.read(self.pc);
+                let arg = self.read_operand();
                 debug!("Executing CB instruction {:02X}", arg);
                 self.pc += 1;
                 self.instruction_arg = InstArg::Byte(arg);
@@ -898,7 +953,7 @@ impl Cpu {
             match self.state {
                 CpuState::StartedExecution => CpuState::ReadArg,
                 CpuState::ReadArg => {
-                    let offset = self.bus.read(self.pc) as i8;
+                    let offset = self.read_operand() as i8;
                     self.pc += 1;
                     if condition {
                         CpuState::UpdatePC(self.pc.wrapping_add(offset as u16))
@@ -924,12 +979,12 @@ impl Cpu {
             match self.state {
                 CpuState::StartedExecution => CpuState::ReadArgLo,
                 CpuState::ReadArgLo => {
-                    let lo = self.bus.read(self.pc);
+                    let lo = self.read_operand();
                     self.pc += 1;
                     CpuState::ReadArgHi(lo)
                 },
                 CpuState::ReadArgHi(lo) => {
-                    let addr = ((self.bus.read(self.pc) as u16) << 8) | (lo as u16);
+                    let addr = ((self.read_operand() as u16) << 8) | (lo as u16);
                     self.pc += 1;
                     if condition {
                         CpuState::UpdatePC(addr)
@@ -963,6 +1018,8 @@ impl Cpu {
                 },
                 CpuState::UpdatePC(new_pc) => {
                     self.pc = new_pc;
+                    #[cfg(feature = "debugging")]
+                    self.call_stack.unwind(self.sp);
                     CpuState::FinishedExecution
                 },
                 _ => unreachable!()
@@ -1047,7 +1104,11 @@ impl Cpu {
                 CpuState::PushLo => {
                     self.sp = self.sp.wrapping_sub(1);
                     self.bus.write(self.sp, (self.pc & 0xFF) as u8);
+                    #[cfg(feature = "debugging")]
+                    let return_addr = self.pc;
                     self.pc = addr;
+                    #[cfg(feature = "debugging")]
+                    self.enter_subroutine(self.current_inst_pc, return_addr, false);
                     CpuState::FinishedExecution
                 },
                 _ => unreachable!()
@@ -1059,12 +1120,12 @@ impl Cpu {
             match self.state {
                 CpuState::StartedExecution => CpuState::ReadArgLo,
                 CpuState::ReadArgLo => {
-                    let lo = self.bus.read(self.pc);
+                    let lo = self.read_operand();
                     self.pc += 1;
                     CpuState::ReadArgHi(lo)
                 },
                 CpuState::ReadArgHi(lo) => {
-                    self.instruction_arg = InstArg::Word(self.bus.read(self.pc), lo);
+                    self.instruction_arg = InstArg::Word(self.read_operand(), lo);
                     self.pc += 1;
                     if condition {
                         CpuState::Internal
@@ -1083,7 +1144,11 @@ impl Cpu {
                 CpuState::PushLo => {
                     self.sp -= 1;
                     self.bus.write(self.sp, (self.pc & 0xFF) as u8);
+                    #[cfg(feature = "debugging")]
+                    let return_addr = self.pc;
                     self.pc = self.instruction_arg.word();
+                    #[cfg(feature = "debugging")]
+                    self.enter_subroutine(self.current_inst_pc, return_addr, false);
 
                     CpuState::FinishedExecution
                 },
@@ -1101,7 +1166,7 @@ impl Cpu {
             match self.state {
                 CpuState::StartedExecution => CpuState::ReadArg,
                 CpuState::ReadArg => {
-                    let arg = self.bus.read(self.pc) as u16;
+                    let arg = self.read_operand() as u16;
                     self.pc += 1;
                     CpuState::WriteMemory(0xFF00 + arg, self.registers.get_reg8(A))
                 },
@@ -1133,7 +1198,7 @@ impl Cpu {
             match self.state {
                 CpuState::StartedExecution => CpuState::ReadArg,
                 CpuState::ReadArg => {
-                    let arg = self.bus.read(self.pc) as u16;
+                    let arg = self.read_operand() as u16;
                     self.pc += 1;
                     CpuState::ReadMemory(0xFF00 + arg)
                 },
@@ -1165,12 +1230,12 @@ impl Cpu {
             match self.state {
                 CpuState::StartedExecution => CpuState::ReadArgLo,
                 CpuState::ReadArgLo => {
-                    let lo = self.bus.read(self.pc);
+                    let lo = self.read_operand();
                     self.pc += 1;
                     CpuState::ReadArgHi(lo)
                 },
                 CpuState::ReadArgHi(lo) => {
-                    let addr = ((self.bus.read(self.pc) as u16) << 8) | (lo as u16);
+                    let addr = ((self.read_operand() as u16) << 8) | (lo as u16);
                     self.pc += 1;
                     CpuState::WriteMemory(addr, self.registers.get_reg8(A))
                 },
@@ -1187,12 +1252,12 @@ impl Cpu {
             match self.state {
                 CpuState::StartedExecution => CpuState::ReadArgLo,
                 CpuState::ReadArgLo => {
-                    let lo = self.bus.read(self.pc);
+                    let lo = self.read_operand();
                     self.pc += 1;
                     CpuState::ReadArgHi(lo)
                 },
                 CpuState::ReadArgHi(lo) => {
-                    let addr = ((self.bus.read(self.pc) as u16) << 8) | (lo as u16);
+                    let addr = ((self.read_operand() as u16) << 8) | (lo as u16);
                     self.pc += 1;
                     CpuState::ReadMemory(addr)
                 },
@@ -1209,7 +1274,7 @@ impl Cpu {
             match self.state {
                 CpuState::StartedExecution => CpuState::ReadArg,
                 CpuState::ReadArg => {
-                    let arg = self.bus.read(self.pc);
+                    let arg = self.read_operand();
                     self.pc += 1;
                     CpuState::ALU16AddSPSignedLo(arg)
                 },
@@ -1240,7 +1305,7 @@ impl Cpu {
             match self.state {
                 CpuState::StartedExecution => CpuState::ReadArg,
                 CpuState::ReadArg => {
-                    let arg = self.bus.read(self.pc);
+                    let arg = self.read_operand();
                     self.pc += 1;
                     CpuState::LoadHLSPOffset(arg)
                 },
@@ -0,0 +1,219 @@
+// Copyright Antonio Porsia 2025. Licensed under the EUPL-1.2 or later.
+
+// Harness for the SM83 single-step tests (https://github.com/SingleStepTests/sm83).
+//
+// The test files aren't part of the repository: point SM83_TESTS_DIR to the directory holding
+// the JSON files to run them. SM83_TESTS_FILTER restricts the run to the files whose name starts
+// with the given prefix (e.g. "cb"). Each test starts with PC pointing to the opcode, and its
+// cycle list covers the opcode fetch but not the fetch of the following instruction.
+
+use std::cell::RefCell;
+use std::fs;
+use std::rc::Rc;
+use serde_json::Value;
+use crate::bus::MemoryInterface;
+use super::{Cpu, CpuState};
+use super::interrupts::InterruptController;
+use super::registers::Register8::{self, *};
+
+const REGISTERS: [(&str, Register8); 8] = [("a", A), ("b", B), ("c", C), ("d", D), ("e", E), ("f", F), ("h", H), ("l", L)];
+/// Print at most this many failures per file, to keep the output readable.
+const MAX_REPORTED_FAILURES: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BusActivity {
+    Idle,
+    Read(u16, u8),
+    Write(u16, u8),
+}
+
+/// A flat 64 KiB address space recording every access together with the M-cycle it happened in.
+struct FlatMemory {
+    ram: Vec<u8>,
+    cycle: usize,
+    activity: Vec<(usize, BusActivity)>,
+}
+
+struct MockBus(Rc<RefCell<FlatMemory>>);
+
+impl MemoryInterface for MockBus {
+    fn read(&self, addr: u16) -> u8 {
+        let mut memory = self.0.borrow_mut();
+        let val = memory.ram[addr as usize];
+        let cycle = memory.cycle;
+        memory.activity.push((cycle, BusActivity::Read(addr, val)));
+        val
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        let mut memory = self.0.borrow_mut();
+        memory.ram[addr as usize] = val;
+        let cycle = memory.cycle;
+        memory.activity.push((cycle, BusActivity::Write(addr, val)));
+    }
+}
+
+fn field(state: &Value, name: &str) -> Result<u64, String> {
+    state[name].as_u64().ok_or_else(|| format!("missing field {}", name))
+}
+
+fn ram_entries(state: &Value) -> Result<Vec<(u16, u8)>, String> {
+    state["ram"].as_array().ok_or("missing field ram")?
+        .iter()
+        .map(|entry| match (entry[0].as_u64(), entry[1].as_u64()) {
+            (Some(addr), Some(val)) => Ok((addr as u16, val as u8)),
+            _ => Err(format!("malformed ram entry {}", entry))
+        })
+        .collect()
+}
+
+/// Cycles are either `null` or `[addr, value, pins]`, where pins is e.g. "r-m" for a read,
+/// "-wm" for a write and "---" when the bus is idle.
+fn bus_activity(cycles: &Value) -> Result<Vec<BusActivity>, String> {
+    cycles.as_array().ok_or("missing field cycles")?
+        .iter()
+        .map(|cycle| {
+            let pins = cycle[2].as_str().unwrap_or("---").as_bytes();
+            let (addr, val) = (cycle[0].as_u64().unwrap_or(0) as u16, cycle[1].as_u64().unwrap_or(0) as u8);
+            match pins.first().zip(pins.get(1)) {
+                Some((b'r', _)) => Ok(BusActivity::Read(addr, val)),
+                Some((_, b'w')) => Ok(BusActivity::Write(addr, val)),
+                _ => Ok(BusActivity::Idle)
+            }
+        })
+        .collect()
+}
+
+fn run_test(test: &Value) -> Result<(), String> {
+    let initial = &test["initial"];
+    let expected = &test["final"];
+    let cycles = bus_activity(&test["cycles"])?;
+
+    let memory = Rc::new(RefCell::new(FlatMemory { ram: vec![0; 0x10000], cycle: 0, activity: Vec::new() }));
+    for (addr, val) in ram_entries(initial)? {
+        memory.borrow_mut().ram[addr as usize] = val;
+    }
+    let interrupts = Rc::new(RefCell::new(InterruptController::new()));
+    {
+        let mut interrupts = interrupts.borrow_mut();
+        interrupts.ime = field(initial, "ime")? != 0;
+        interrupts.set_interrupt_enable(initial["ie"].as_u64().unwrap_or(0) as u8);
+        interrupts.set_interrupt_request(0);
+    }
+
+    let mut cpu = Cpu::new(Rc::clone(&interrupts), Box::new(MockBus(Rc::clone(&memory))), false);
+    for (name, reg) in REGISTERS {
+        cpu.registers_mut().set_reg8(reg, field(initial, name)? as u8);
+    }
+    cpu.set_pc(field(initial, "pc")? as u16);
+    cpu.set_sp(field(initial, "sp")? as u16);
+    cpu.state = CpuState::Fetching { halt_bug: false };
+
+    for cycle in 0..cycles.len() {
+        memory.borrow_mut().cycle = cycle;
+        cpu.clock();
+    }
+    if !matches!(cpu.state, CpuState::Fetching { .. } | CpuState::Halted | CpuState::Stopped(_)) {
+        return Err(format!("still executing after {} cycles ({:?})", cycles.len(), cpu.state));
+    }
+
+    let memory = memory.borrow();
+    for (cycle, expected_activity) in cycles.iter().enumerate() {
+        let actual: Vec<BusActivity> = memory.activity.iter()
+            .filter(|(c, _)| *c == cycle)
+            .map(|(_, activity)| *activity)
+            .collect();
+        let matches = match expected_activity {
+            BusActivity::Idle => actual.is_empty(),
+            activity => actual == [*activity]
+        };
+        if !matches {
+            return Err(format!("cycle {}: expected {:X?}, got {:X?}", cycle, expected_activity, actual));
+        }
+    }
+    for (name, reg) in REGISTERS {
+        let (want, got) = (field(expected, name)? as u8, cpu.registers().get_reg8(reg));
+        if want != got {
+            return Err(format!("{}: expected {:02X}, got {:02X}", name, want, got));
+        }
+    }
+    for (name, got) in [("pc", cpu.pc()), ("sp", cpu.sp())] {
+        let want = field(expected, name)? as u16;
+        if want != got {
+            return Err(format!("{}: expected {:04X}, got {:04X}", name, want, got));
+        }
+    }
+    let ime = interrupts.borrow().ime;
+    if (field(expected, "ime")? != 0) != ime {
+        return Err(format!("ime: expected {}, got {}", !ime, ime));
+    }
+    for (addr, want) in ram_entries(expected)? {
+        let got = memory.ram[addr as usize];
+        if want != got {
+            return Err(format!("ram[{:04X}]: expected {:02X}, got {:02X}", addr, want, got));
+        }
+    }
+    Ok(())
+}
+
+fn run_tests(json: &str) -> Vec<String> {
+    let tests: Value = match serde_json::from_str(json) {
+        Ok(tests) => tests,
+        Err(e) => return vec![format!("invalid JSON: {}", e)]
+    };
+    tests.as_array().map(|tests| tests.as_slice()).unwrap_or_default()
+        .iter()
+        .filter_map(|test| run_test(test).err().map(|e| format!("{}: {}", test["name"].as_str().unwrap_or("?"), e)))
+        .collect()
+}
+
+#[test]
+fn builtin_cases() {
+    let tests = r#"[
+        {"name": "00 NOP",
+         "initial": {"pc": 256, "sp": 65534, "a": 1, "b": 0, "c": 19, "d": 0, "e": 216, "f": 176, "h": 1, "l": 77, "ime": 0, "ie": 0, "ram": [[256, 0]]},
+         "final": {"pc": 257, "sp": 65534, "a": 1, "b": 0, "c": 19, "d": 0, "e": 216, "f": 176, "h": 1, "l": 77, "ime": 0, "ram": [[256, 0]]},
+         "cycles": [[256, 0, "r-m"]]},
+        {"name": "01 LD BC, d16",
+         "initial": {"pc": 256, "sp": 65534, "a": 1, "b": 0, "c": 19, "d": 0, "e": 216, "f": 176, "h": 1, "l": 77, "ime": 0, "ie": 0, "ram": [[256, 1], [257, 52], [258, 18]]},
+         "final": {"pc": 259, "sp": 65534, "a": 1, "b": 18, "c": 52, "d": 0, "e": 216, "f": 176, "h": 1, "l": 77, "ime": 0, "ram": [[256, 1], [257, 52], [258, 18]]},
+         "cycles": [[256, 1, "r-m"], [257, 52, "r-m"], [258, 18, "r-m"]]},
+        {"name": "c5 PUSH BC",
+         "initial": {"pc": 256, "sp": 65534, "a": 1, "b": 18, "c": 52, "d": 0, "e": 216, "f": 176, "h": 1, "l": 77, "ime": 0, "ie": 0, "ram": [[256, 197]]},
+         "final": {"pc": 257, "sp": 65532, "a": 1, "b": 18, "c": 52, "d": 0, "e": 216, "f": 176, "h": 1, "l": 77, "ime": 0, "ram": [[256, 197], [65533, 18], [65532, 52]]},
+         "cycles": [[256, 197, "r-m"], null, [65533, 18, "-wm"], [65532, 52, "-wm"]]},
+        {"name": "cd CALL a16",
+         "initial": {"pc": 256, "sp": 65534, "a": 1, "b": 0, "c": 19, "d": 0, "e": 216, "f": 176, "h": 1, "l": 77, "ime": 0, "ie": 0, "ram": [[256, 205], [257, 0], [258, 32]]},
+         "final": {"pc": 8192, "sp": 65532, "a": 1, "b": 0, "c": 19, "d": 0, "e": 216, "f": 176, "h": 1, "l": 77, "ime": 0, "ram": [[65533, 1], [65532, 3]]},
+         "cycles": [[256, 205, "r-m"], [257, 0, "r-m"], [258, 32, "r-m"], [258, 32, "---"], [65533, 1, "-wm"], [65532, 3, "-wm"]]}
+    ]"#;
+    let failures = run_tests(tests);
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+fn single_step_json_tests() {
+    let Ok(dir) = std::env::var("SM83_TESTS_DIR") else {
+        eprintln!("SM83_TESTS_DIR not set, skipping the SM83 single-step tests");
+        return;
+    };
+    let filter = std::env::var("SM83_TESTS_FILTER").unwrap_or_default();
+    let mut files: Vec<_> = fs::read_dir(&dir).expect("Failed to read SM83_TESTS_DIR")
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|e| e == "json"))
+        .filter(|path| path.file_name().and_then(|n| n.to_str()).is_some_and(|n| n.starts_with(&filter)))
+        .collect();
+    files.sort();
+
+    let mut failed_files = Vec::new();
+    for path in files {
+        let json = fs::read_to_string(&path).expect("Failed to read test file");
+        let failures = run_tests(&json);
+        if !failures.is_empty() {
+            eprintln!("{:?}: {} failures", path, failures.len());
+            failures.iter().take(MAX_REPORTED_FAILURES).for_each(|f| eprintln!("    {}", f));
+            failed_files.push(path);
+        }
+    }
+    assert!(failed_files.is_empty(), "{} test files had failures", failed_files.len());
+}
@@ -0,0 +1,117 @@
+// Copyright Antonio Porsia 2025. Licensed under the EUPL-1.2 or later.
+
+/// A subroutine or interrupt handler the CPU is currently executing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackFrame {
+    /// Address of the CALL/RST instruction, or of the interrupted instruction.
+    pub call_site: u16,
+    /// Entry point of the subroutine.
+    pub target: u16,
+    /// The address pushed on the stack.
+    pub return_addr: u16,
+    /// The value of SP right after the return address was pushed.
+    pub sp: u16,
+    /// ROM bank mapped at 0x4000-0x7FFF when the call happened.
+    pub bank: usize,
+    /// Whether the frame was entered by servicing an interrupt.
+    pub interrupt: bool,
+}
+
+/// Tracks CALL/RST/interrupt entries and RET/RETI exits.
+///
+/// Frames are matched to returns through the stack pointer rather than strictly one by one,
+/// so code that drops return addresses from the stack (e.g. by popping them and jumping) doesn't
+/// leave stale frames behind.
+#[derive(Debug, Clone, Default)]
+pub struct CallStack {
+    frames: Vec<StackFrame>,
+}
+
+impl CallStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn push(&mut self, frame: StackFrame) {
+        // Anything at or below the new return address has been overwritten
+        self.frames.retain(|f| f.sp > frame.sp);
+        self.frames.push(frame);
+    }
+
+    /// Drops every frame whose return address lies below `sp`, i.e. has been popped.
+    pub(crate) fn unwind(&mut self, sp: u16) {
+        while self.frames.last().is_some_and(|f| f.sp < sp) {
+            self.frames.pop();
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Returns the frames, outermost first.
+    pub fn frames(&self) -> &[StackFrame] {
+        &self.frames
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(target: u16, sp: u16) -> StackFrame {
+        StackFrame { call_site: 0x0150, target, return_addr: 0x0153, sp, bank: 1, interrupt: false }
+    }
+
+    fn targets(stack: &CallStack) -> Vec<u16> {
+        stack.frames().iter().map(|f| f.target).collect()
+    }
+
+    #[test]
+    fn returns_pop_one_frame_each() {
+        let mut stack = CallStack::new();
+        stack.push(frame(0x0200, 0xFFFC));
+        stack.push(frame(0x0210, 0xFFFA));
+        stack.push(frame(0x0040, 0xFFF8));
+        assert_eq!(targets(&stack), [0x0200, 0x0210, 0x0040]);
+
+        // Returning from the innermost frame leaves SP 2 bytes above it
+        stack.unwind(0xFFFA);
+        assert_eq!(targets(&stack), [0x0200, 0x0210]);
+        stack.unwind(0xFFFC);
+        stack.unwind(0xFFFE);
+        assert_eq!(stack.depth(), 0);
+    }
+
+    #[test]
+    fn popped_return_addresses_drop_their_frames() {
+        let mut stack = CallStack::new();
+        stack.push(frame(0x0200, 0xFFFC));
+        stack.push(frame(0x0210, 0xFFFA));
+        stack.push(frame(0x0220, 0xFFF8));
+        // A RET after the inner return addresses were popped from the stack
+        stack.unwind(0xFFFE);
+        assert_eq!(stack.depth(), 0);
+    }
+
+    #[test]
+    fn pushes_overwrite_frames_at_or_below_their_sp() {
+        let mut stack = CallStack::new();
+        stack.push(frame(0x0200, 0xFFFC));
+        stack.push(frame(0x0210, 0xFFFA));
+        stack.push(frame(0x0220, 0xFFF8));
+        // SP was moved back up without returning, then a new call reuses the same slot
+        stack.push(frame(0x0300, 0xFFFA));
+        assert_eq!(targets(&stack), [0x0200, 0x0300]);
+    }
+
+    #[test]
+    fn unwind_keeps_frames_whose_return_address_is_still_on_the_stack() {
+        let mut stack = CallStack::new();
+        stack.push(frame(0x0200, 0xFFFC));
+        // e.g. PUSH inside the subroutine, or a RET that only pops a pushed value
+        stack.unwind(0xFFFA);
+        stack.unwind(0xFFFC);
+        assert_eq!(targets(&stack), [0x0200]);
+    }
+}
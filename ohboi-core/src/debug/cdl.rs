@@ -0,0 +1,159 @@
+// Copyright Antonio Porsia 2025. Licensed under the EUPL-1.2 or later.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// The byte was fetched as the opcode of an instruction.
+pub const CODE: u8 = 0x01;
+/// The byte was read as the operand of an instruction.
+pub const OPERAND: u8 = 0x02;
+/// The byte was read as data, e.g. through `LD A,(HL)`.
+pub const DATA: u8 = 0x04;
+/// The byte was the source of an OAM DMA or CGB HDMA transfer.
+pub const DMA: u8 = 0x08;
+
+const ROM_BANK_SIZE: usize = 0x4000;
+
+/// How a ROM byte was accessed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RomAccess {
+    Code,
+    Operand,
+    Data,
+    Dma,
+}
+
+impl RomAccess {
+    fn flag(self) -> u8 {
+        match self {
+            RomAccess::Code => CODE,
+            RomAccess::Operand => OPERAND,
+            RomAccess::Data => DATA,
+            RomAccess::Dma => DMA,
+        }
+    }
+}
+
+/// Number of bytes of a ROM region marked with each kind of access.
+///
+/// A byte can be counted in more than one category, e.g. code that is also copied to RAM.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Coverage {
+    pub total: usize,
+    pub code: usize,
+    pub operand: usize,
+    pub data: usize,
+    pub dma: usize,
+    /// Bytes that weren't accessed at all.
+    pub untouched: usize,
+}
+
+/// Records how every byte of the ROM has been accessed.
+///
+/// The log is indexed by offset in the ROM image, so the bank of an access is implied.
+/// It is saved as a CDL file: one byte of flags (`CODE`, `OPERAND`, `DATA`, `DMA`) per ROM byte.
+#[derive(Debug, Clone)]
+pub struct CodeDataLogger {
+    flags: Vec<u8>,
+}
+
+impl CodeDataLogger {
+    /// Creates an empty log for a ROM of `rom_size` bytes.
+    pub fn new(rom_size: usize) -> Self {
+        Self { flags: vec![0; rom_size] }
+    }
+
+    /// Loads a CDL file, so coverage accumulates over several sessions.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the CDL file.
+    /// * `rom_size` - The size of the ROM the log belongs to.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the log or an error if the file couldn't be read or doesn't match the ROM size.
+    pub fn load(path: &Path, rom_size: usize) -> io::Result<Self> {
+        let flags = fs::read(path)?;
+        if flags.len() != rom_size {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      format!("CDL file is {} bytes, expected {}", flags.len(), rom_size)));
+        }
+        Ok(Self { flags })
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, &self.flags)
+    }
+
+    pub(crate) fn log(&mut self, offset: usize, access: RomAccess) {
+        if let Some(flags) = self.flags.get_mut(offset) {
+            *flags |= access.flag();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.flags.fill(0);
+    }
+
+    /// Returns the flags of the byte at `offset` in the ROM image.
+    pub fn flags(&self, offset: usize) -> u8 {
+        self.flags.get(offset).copied().unwrap_or(0)
+    }
+
+    /// Returns the flags of the byte at `addr` in `bank`, as reported by the disassembler.
+    pub fn flags_at(&self, bank: usize, addr: u16) -> u8 {
+        self.flags(bank * ROM_BANK_SIZE + (addr as usize) % ROM_BANK_SIZE)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.flags
+    }
+
+    fn coverage_of(flags: &[u8]) -> Coverage {
+        flags.iter().fold(Coverage { total: flags.len(), ..Coverage::default() }, |mut c, f| {
+            c.code += (f & CODE != 0) as usize;
+            c.operand += (f & OPERAND != 0) as usize;
+            c.data += (f & DATA != 0) as usize;
+            c.dma += (f & DMA != 0) as usize;
+            c.untouched += (*f == 0) as usize;
+            c
+        })
+    }
+
+    /// Returns the coverage of the whole ROM.
+    pub fn coverage(&self) -> Coverage {
+        Self::coverage_of(&self.flags)
+    }
+
+    /// Returns the coverage of each 16 KiB bank.
+    pub fn bank_coverage(&self) -> Vec<Coverage> {
+        self.flags.chunks(ROM_BANK_SIZE).map(Self::coverage_of).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_accumulate_per_bank() {
+        let mut cdl = CodeDataLogger::new(4 * ROM_BANK_SIZE);
+        cdl.log(0x0100, RomAccess::Code);
+        cdl.log(0x0101, RomAccess::Operand);
+        cdl.log(2 * ROM_BANK_SIZE + 0x10, RomAccess::Data);
+        cdl.log(2 * ROM_BANK_SIZE + 0x10, RomAccess::Dma);
+        cdl.log(usize::MAX, RomAccess::Code);
+
+        assert_eq!(cdl.flags_at(0, 0x0100), CODE);
+        assert_eq!(cdl.flags_at(2, 0x4010), DATA | DMA);
+        assert_eq!(cdl.flags_at(1, 0x4010), 0);
+
+        let banks = cdl.bank_coverage();
+        assert_eq!(banks.len(), 4);
+        assert_eq!(banks[0], Coverage { total: ROM_BANK_SIZE, code: 1, operand: 1, data: 0, dma: 0, untouched: ROM_BANK_SIZE - 2 });
+        assert_eq!(banks[2].data, 1);
+        assert_eq!(cdl.coverage().untouched, 4 * ROM_BANK_SIZE - 3);
+    }
+}
@@ -0,0 +1,7 @@
+// Copyright Antonio Porsia 2025. Licensed under the EUPL-1.2 or later.
+
+pub mod callstack;
+pub mod cdl;
+pub mod profiler;
+pub mod symbols;
+pub mod trace;
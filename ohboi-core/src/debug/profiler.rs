@@ -0,0 +1,180 @@
+// Copyright Antonio Porsia 2025. Licensed under the EUPL-1.2 or later.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+use crate::debug::callstack::StackFrame;
+use crate::debug::symbols::SymbolTable;
+
+/// Returns the bank an address belongs to when `rom_bank` is mapped at 0x4000-0x7FFF.
+/// Everything outside of switchable ROM is reported as bank 0.
+fn bank_of(addr: u16, rom_bank: usize) -> usize {
+    if (0x4000..=0x7FFF).contains(&addr) { rom_bank } else { 0 }
+}
+
+/// Cycles spent in a routine, identified by its entry point.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FunctionProfile {
+    pub bank: usize,
+    pub addr: u16,
+    /// Cycles spent in the routine itself.
+    pub self_cycles: u64,
+    /// Cycles spent in the routine and everything it called.
+    pub total_cycles: u64,
+}
+
+/// Counts the M-cycles spent per instruction address and per routine.
+///
+/// Routines are the entries of the call stack tracked by the CPU, so code reached through JP or
+/// before the first CALL is attributed to the routine that contains it, or to no routine at all.
+#[derive(Debug, Clone, Default)]
+pub struct Profiler {
+    by_address: HashMap<(usize, u16), u64>,
+    by_function: HashMap<(usize, u16), FunctionProfile>,
+    total_cycles: u64,
+    halted_cycles: u64,
+    interrupt_cycles: u64,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accounts M-cycles spent in the current state.
+    ///
+    /// # Arguments
+    ///
+    /// * `cycles` - The number of M-cycles to account.
+    /// * `pc` - The address of the instruction being executed.
+    /// * `rom_bank` - The ROM bank mapped at 0x4000-0x7FFF.
+    /// * `halted` - Whether the CPU is halted.
+    /// * `frames` - The current call stack, outermost frame first.
+    pub(crate) fn sample(&mut self, cycles: u64, pc: u16, rom_bank: usize, halted: bool, frames: &[StackFrame]) {
+        self.total_cycles += cycles;
+        if halted {
+            self.halted_cycles += cycles;
+        }
+        if frames.iter().any(|f| f.interrupt) {
+            self.interrupt_cycles += cycles;
+        }
+        *self.by_address.entry((bank_of(pc, rom_bank), pc)).or_default() += cycles;
+
+        for (i, frame) in frames.iter().enumerate() {
+            let key = (bank_of(frame.target, frame.bank), frame.target);
+            // Recursive routines appear more than once, count them only for their outermost frame
+            let outermost = frames[..i].iter()
+                .all(|f| (bank_of(f.target, f.bank), f.target) != key);
+            let profile = self.by_function.entry(key).or_insert(FunctionProfile {
+                bank: key.0,
+                addr: key.1,
+                self_cycles: 0,
+                total_cycles: 0,
+            });
+            if outermost {
+                profile.total_cycles += cycles;
+            }
+            if i == frames.len() - 1 {
+                profile.self_cycles += cycles;
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn total_cycles(&self) -> u64 {
+        self.total_cycles
+    }
+
+    pub fn halted_cycles(&self) -> u64 {
+        self.halted_cycles
+    }
+
+    pub fn interrupt_cycles(&self) -> u64 {
+        self.interrupt_cycles
+    }
+
+    /// Returns the cycles spent per (bank, address), hottest first.
+    pub fn hotspots(&self) -> Vec<((usize, u16), u64)> {
+        let mut hotspots: Vec<_> = self.by_address.iter().map(|(k, v)| (*k, *v)).collect();
+        hotspots.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        hotspots
+    }
+
+    /// Returns the per-routine statistics, sorted by inclusive cycles, hottest first.
+    pub fn functions(&self) -> Vec<FunctionProfile> {
+        let mut functions: Vec<_> = self.by_function.values().copied().collect();
+        functions.sort_by(|a, b| b.total_cycles.cmp(&a.total_cycles).then((a.bank, a.addr).cmp(&(b.bank, b.addr))));
+        functions
+    }
+
+    fn percent(&self, cycles: u64) -> f64 {
+        if self.total_cycles == 0 { 0.0 } else { cycles as f64 * 100.0 / self.total_cycles as f64 }
+    }
+
+    /// Formats a plain text report with the `top` hottest routines and addresses.
+    pub fn report(&self, symbols: &SymbolTable, top: usize) -> String {
+        let name = |bank: usize, addr: u16| symbols.describe(bank, addr).unwrap_or_default();
+        let mut report = String::new();
+        let running = self.total_cycles - self.halted_cycles;
+        let _ = writeln!(report, "Total: {} M-cycles", self.total_cycles);
+        let _ = writeln!(report, "Running: {} ({:.2}%)", running, self.percent(running));
+        let _ = writeln!(report, "Halted: {} ({:.2}%)", self.halted_cycles, self.percent(self.halted_cycles));
+        let _ = writeln!(report, "In interrupt handlers: {} ({:.2}%)", self.interrupt_cycles, self.percent(self.interrupt_cycles));
+
+        let _ = writeln!(report, "\nHottest routines:");
+        let _ = writeln!(report, "{:>7} {:>12} {:>8} {:>12} {:>8}  Name", "Address", "Total", "%", "Self", "%");
+        for f in self.functions().iter().take(top) {
+            let _ = writeln!(report, "{:02X}:{:04X} {:>12} {:>7.2}% {:>12} {:>7.2}%  {}",
+                             f.bank, f.addr, f.total_cycles, self.percent(f.total_cycles),
+                             f.self_cycles, self.percent(f.self_cycles), name(f.bank, f.addr));
+        }
+
+        let _ = writeln!(report, "\nHottest addresses:");
+        let _ = writeln!(report, "{:>7} {:>12} {:>8}  Location", "Address", "Cycles", "%");
+        for ((bank, addr), cycles) in self.hotspots().iter().take(top) {
+            let _ = writeln!(report, "{:02X}:{:04X} {:>12} {:>7.2}%  {}",
+                             bank, addr, cycles, self.percent(*cycles), name(*bank, *addr));
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(target: u16, bank: usize, interrupt: bool) -> StackFrame {
+        StackFrame { call_site: 0x0150, target, return_addr: 0x0153, sp: 0xFFFC, bank, interrupt }
+    }
+
+    #[test]
+    fn attributes_cycles_to_routines_and_addresses() {
+        let mut profiler = Profiler::new();
+        let outer = frame(0x4000, 2, false);
+        let inner = frame(0x0200, 2, false);
+        profiler.sample(4, 0x0150, 2, false, &[]);
+        profiler.sample(3, 0x4001, 2, false, &[outer]);
+        profiler.sample(2, 0x0201, 2, false, &[outer, inner]);
+        // A recursive call only counts once towards the inclusive cycles
+        profiler.sample(5, 0x4002, 2, false, &[outer, inner, outer]);
+        profiler.sample(6, 0x0040, 2, true, &[outer, frame(0x0040, 2, true)]);
+
+        assert_eq!(profiler.total_cycles(), 20);
+        assert_eq!(profiler.halted_cycles(), 6);
+        assert_eq!(profiler.interrupt_cycles(), 6);
+
+        let functions = profiler.functions();
+        assert_eq!(functions[0], FunctionProfile { bank: 2, addr: 0x4000, self_cycles: 8, total_cycles: 16 });
+        assert_eq!(functions[1], FunctionProfile { bank: 0, addr: 0x0200, self_cycles: 2, total_cycles: 7 });
+        assert_eq!(functions[2], FunctionProfile { bank: 0, addr: 0x0040, self_cycles: 6, total_cycles: 6 });
+
+        assert_eq!(profiler.hotspots()[0], ((0, 0x0040), 6));
+        assert!(profiler.hotspots().contains(&((2, 0x4002), 5)));
+
+        profiler.clear();
+        assert_eq!(profiler.total_cycles(), 0);
+        assert!(profiler.functions().is_empty());
+    }
+}
@@ -0,0 +1,225 @@
+// Copyright Antonio Porsia 2025. Licensed under the EUPL-1.2 or later.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::Path;
+use log::{info, warn};
+use crate::cpu::disassembler::Instruction;
+
+/// Labels loaded from the `.sym` and `.map` files produced by RGBDS.
+///
+/// Addresses are stored together with their bank. For 0x4000-0x7FFF the bank is the ROM bank;
+/// for the other banked regions (VRAM, SRAM, WRAMX) it's the bank of that region, which the
+/// debugger doesn't track, so any bank matches there.
+#[derive(Debug, Default, Clone)]
+pub struct SymbolTable {
+    /// Labels keyed by address first, so that all the banks of an address are adjacent.
+    by_addr: BTreeMap<(u16, usize), String>,
+    by_name: HashMap<String, (usize, u16)>,
+}
+
+/// Returns the first address of the memory region `addr` belongs to.
+/// Labels are never used to describe addresses in a different region.
+fn region_start(addr: u16) -> u16 {
+    match addr {
+        0x0000..=0x3FFF => 0x0000,
+        0x4000..=0x7FFF => 0x4000,
+        0x8000..=0x9FFF => 0x8000,
+        0xA000..=0xBFFF => 0xA000,
+        0xC000..=0xCFFF => 0xC000,
+        0xD000..=0xDFFF => 0xD000,
+        0xFF80..=0xFFFE => 0xFF80,
+        _ => addr
+    }
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Looks for a `.sym` or `.map` file next to the ROM and loads it.
+    ///
+    /// # Arguments
+    ///
+    /// * `rom_path` - The path to the ROM file.
+    ///
+    /// # Returns
+    ///
+    /// The loaded symbols, or an empty table if no symbol file could be loaded.
+    pub fn for_rom(rom_path: &Path) -> Self {
+        for extension in ["sym", "map"] {
+            let path = rom_path.with_extension(extension);
+            if !path.exists() {
+                continue;
+            }
+            match Self::load(&path) {
+                Ok(table) => {
+                    info!("Loaded {} symbols from {:?}", table.len(), path);
+                    return table;
+                },
+                Err(e) => warn!("Failed to load symbols from {:?}: {}", path, e)
+            }
+        }
+        Self::new()
+    }
+
+    /// Loads a symbol file. Files with a `.map` extension are parsed as RGBDS map files,
+    /// anything else as a `.sym` file.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to the symbol file.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the loaded symbols or an error.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let mut table = Self::new();
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(e) if e.eq_ignore_ascii_case("map") => table.parse_map(&text),
+            _ => table.parse_sym(&text)
+        }
+        Ok(table)
+    }
+
+    /// Parses the contents of a `.sym` file: one `BB:AAAA Label` entry per line, with `;`
+    /// starting a comment.
+    pub fn parse_sym(&mut self, text: &str) {
+        for line in text.lines() {
+            let line = line.split(';').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let Some((location, name)) = line.split_once(char::is_whitespace) else {
+                warn!("Ignoring malformed symbol line: {}", line);
+                continue;
+            };
+            let (bank, addr) = match location.split_once(':') {
+                Some((bank, addr)) => (usize::from_str_radix(bank, 16), u16::from_str_radix(addr, 16)),
+                None => (Ok(0), u16::from_str_radix(location, 16))
+            };
+            match (bank, addr) {
+                (Ok(bank), Ok(addr)) => self.insert(bank, addr, name.trim()),
+                _ => warn!("Ignoring malformed symbol line: {}", line)
+            }
+        }
+    }
+
+    /// Parses the contents of an RGBDS `.map` file, where symbols are listed as `$AAAA = Label`
+    /// below `<REGION> bank #N:` headers.
+    pub fn parse_map(&mut self, text: &str) {
+        let mut bank = 0;
+        for line in text.lines() {
+            let line = line.trim();
+            if let Some((_, n)) = line.strip_suffix(':').and_then(|h| h.split_once(" bank #")) {
+                bank = n.trim().parse().unwrap_or(0);
+                continue;
+            }
+            let Some((addr, name)) = line.split_once('=') else {
+                continue;
+            };
+            let Some(addr) = addr.trim().strip_prefix('$') else {
+                continue;
+            };
+            if let Ok(addr) = u16::from_str_radix(addr, 16) {
+                self.insert(bank, addr, name.trim());
+            }
+        }
+    }
+
+    /// Adds a label. If there already is one at the same location, the first one is kept.
+    pub fn insert(&mut self, bank: usize, addr: u16, name: &str) {
+        self.by_name.entry(name.to_string()).or_insert((bank, addr));
+        self.by_addr.entry((addr, bank)).or_insert_with(|| name.to_string());
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_addr.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_addr.is_empty()
+    }
+
+    /// Returns the bank and address of the label with the given name.
+    pub fn lookup(&self, name: &str) -> Option<(usize, u16)> {
+        self.by_name.get(name).copied()
+    }
+
+    fn bank_matches(rom_bank: usize, addr: u16, bank: usize) -> bool {
+        !(0x4000..=0x7FFF).contains(&addr) || bank == rom_bank
+    }
+
+    /// Returns the label at exactly `addr`.
+    ///
+    /// # Arguments
+    ///
+    /// * `rom_bank` - The ROM bank mapped at 0x4000-0x7FFF.
+    /// * `addr` - The address to look up.
+    pub fn label(&self, rom_bank: usize, addr: u16) -> Option<&str> {
+        self.by_addr.range((addr, 0)..=(addr, usize::MAX))
+            .find(|((a, b), _)| Self::bank_matches(rom_bank, *a, *b))
+            .map(|(_, name)| name.as_str())
+    }
+
+    /// Describes `addr` relative to the closest label at or before it in the same memory region,
+    /// e.g. `Main.loop+$3`.
+    ///
+    /// # Arguments
+    ///
+    /// * `rom_bank` - The ROM bank mapped at 0x4000-0x7FFF.
+    /// * `addr` - The address to describe.
+    pub fn describe(&self, rom_bank: usize, addr: u16) -> Option<String> {
+        let start = region_start(addr);
+        self.by_addr.range((start, 0)..=(addr, usize::MAX))
+            .rev()
+            .find(|((a, b), _)| Self::bank_matches(rom_bank, *a, *b))
+            .map(|((a, _), name)| if *a == addr { name.clone() } else { format!("{}+${:X}", name, addr - a) })
+    }
+
+    /// Returns the text of `inst`, with its jump target or 16-bit address operand replaced by
+    /// a label where one exists.
+    ///
+    /// # Arguments
+    ///
+    /// * `inst` - The instruction to label.
+    /// * `rom_bank` - The ROM bank mapped at 0x4000-0x7FFF, used for targets outside of `inst`'s bank.
+    pub fn annotate(&self, inst: &Instruction, rom_bank: usize) -> String {
+        let bank = if inst.addr >= 0x4000 { inst.bank } else { rom_bank };
+        let operand = inst.target
+            .or_else(|| (inst.size() == 3).then(|| u16::from_le_bytes([inst.bytes[1], inst.bytes[2]])));
+        match operand.and_then(|addr| self.label(bank, addr).map(|label| (addr, label))) {
+            Some((addr, label)) => inst.text.replace(&format!("${:04X}", addr), label),
+            None => inst.text.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sym_files() {
+        let mut table = SymbolTable::new();
+        table.parse_sym("; File generated by rgblink\n00:0150 Main\n01:4123 Main.loop\n00:c000 wCounter ; comment\n");
+        assert_eq!(table.len(), 3);
+        assert_eq!(table.label(1, 0x4123), Some("Main.loop"));
+        assert_eq!(table.label(2, 0x4123), None);
+        assert_eq!(table.lookup("wCounter"), Some((0, 0xC000)));
+        assert_eq!(table.describe(0, 0x0153).as_deref(), Some("Main+$3"));
+        assert_eq!(table.describe(0, 0x4003), None);
+    }
+
+    #[test]
+    fn parses_map_files() {
+        let mut table = SymbolTable::new();
+        table.parse_map("SUMMARY:\n\tROM0: 337 bytes used / 16047 free\n\nROM0 bank #0:\n\tSECTION: $0000-$0002 ($0003 bytes) [\"Header\"]\n\t         $0000 = EntryPoint\n\nROMX bank #2:\n\tSECTION: $4000-$4010 ($0011 bytes) [\"Code\"]\n\t         $4000 = Main\n");
+        assert_eq!(table.label(0, 0x0000), Some("EntryPoint"));
+        assert_eq!(table.label(2, 0x4000), Some("Main"));
+        assert_eq!(table.label(1, 0x4000), None);
+    }
+}
@@ -0,0 +1,175 @@
+// Copyright Antonio Porsia 2025. Licensed under the EUPL-1.2 or later.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+use log::warn;
+use crate::cpu::{Register8, Registers};
+
+/// Where trace lines end up.
+enum TraceSink {
+    File(BufWriter<File>),
+    /// Keeps only the most recent `capacity` lines.
+    Ring { lines: VecDeque<String>, capacity: usize },
+}
+
+/// Restricts tracing to part of the program. Both conditions must hold for a line to be written.
+#[derive(Debug, Clone, Default)]
+pub struct TraceFilter {
+    /// Only trace instructions whose address is in this range.
+    pub pc_range: Option<RangeInclusive<u16>>,
+    /// Only trace instructions in this ROM bank. Code running from RAM never matches.
+    pub bank: Option<usize>,
+}
+
+impl TraceFilter {
+    fn matches(&self, pc: u16, rom_bank: usize) -> bool {
+        if let Some(ref range) = self.pc_range {
+            if !range.contains(&pc) {
+                return false;
+            }
+        }
+        match self.bank {
+            Some(bank) => match pc {
+                0x0000..=0x3FFF => bank == 0,
+                0x4000..=0x7FFF => bank == rom_bank,
+                _ => false
+            },
+            None => true
+        }
+    }
+}
+
+/// Logs one line per executed instruction in the format used by Gameboy Doctor:
+///
+/// `A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02`
+///
+/// The registers are the ones right before the instruction at PC is executed.
+pub struct Tracer {
+    sink: TraceSink,
+    filter: TraceFilter,
+}
+
+impl Tracer {
+    /// Creates a tracer writing to a file.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the log file. It is truncated if it exists.
+    /// * `filter` - Which instructions to trace.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the tracer or an error if the file couldn't be created.
+    pub fn to_file(path: &Path, filter: TraceFilter) -> io::Result<Self> {
+        let file = File::create(path)?;
+        Ok(Self { sink: TraceSink::File(BufWriter::new(file)), filter })
+    }
+
+    /// Creates a tracer that keeps the last `capacity` lines in memory.
+    pub fn ring_buffer(capacity: usize, filter: TraceFilter) -> Self {
+        Self { sink: TraceSink::Ring { lines: VecDeque::with_capacity(capacity), capacity }, filter }
+    }
+
+    pub fn filter(&self) -> &TraceFilter {
+        &self.filter
+    }
+
+    pub(crate) fn wants(&self, pc: u16, rom_bank: usize) -> bool {
+        self.filter.matches(pc, rom_bank)
+    }
+
+    /// Formats a line in Gameboy Doctor format.
+    pub fn format_line(registers: &Registers, sp: u16, pc: u16, pcmem: [u8; 4]) -> String {
+        use Register8::*;
+        format!("A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+                registers.get_reg8(A), registers.get_reg8(F), registers.get_reg8(B), registers.get_reg8(C),
+                registers.get_reg8(D), registers.get_reg8(E), registers.get_reg8(H), registers.get_reg8(L),
+                sp, pc, pcmem[0], pcmem[1], pcmem[2], pcmem[3])
+    }
+
+    pub(crate) fn record(&mut self, line: String) {
+        match self.sink {
+            TraceSink::File(ref mut f) => {
+                if let Err(e) = writeln!(f, "{}", line) {
+                    warn!("Failed to write trace line: {}", e);
+                }
+            },
+            TraceSink::Ring { ref mut lines, capacity } => {
+                if lines.len() == capacity {
+                    lines.pop_front();
+                }
+                if capacity > 0 {
+                    lines.push_back(line);
+                }
+            }
+        }
+    }
+
+    /// Returns the lines held in memory, oldest first. Always empty when tracing to a file.
+    pub fn lines(&self) -> impl Iterator<Item = &str> {
+        let lines = match self.sink {
+            TraceSink::Ring { ref lines, .. } => Some(lines.iter().map(String::as_str)),
+            TraceSink::File(_) => None
+        };
+        lines.into_iter().flatten()
+    }
+
+    /// Writes the lines held in memory to `out`, or flushes the log file.
+    pub fn dump<W: Write>(&mut self, out: &mut W) -> io::Result<()> {
+        match self.sink {
+            TraceSink::File(ref mut f) => f.flush(),
+            TraceSink::Ring { ref lines, .. } => {
+                for line in lines {
+                    writeln!(out, "{}", line)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring_buffer_keeps_the_most_recent_lines() {
+        let mut tracer = Tracer::ring_buffer(2, TraceFilter::default());
+        for line in ["a", "b", "c"] {
+            tracer.record(line.to_string());
+        }
+        assert_eq!(tracer.lines().collect::<Vec<_>>(), ["b", "c"]);
+
+        let mut out = Vec::new();
+        tracer.dump(&mut out).unwrap();
+        assert_eq!(out, b"b\nc\n");
+
+        let mut empty = Tracer::ring_buffer(0, TraceFilter::default());
+        empty.record("a".to_string());
+        assert_eq!(empty.lines().count(), 0);
+    }
+
+    #[test]
+    fn filter_checks_pc_range_and_rom_bank() {
+        let tracer = Tracer::ring_buffer(1, TraceFilter { pc_range: Some(0x0100..=0x4FFF), bank: Some(3) });
+        assert!(tracer.wants(0x4000, 3));
+        assert!(!tracer.wants(0x4000, 2));
+        assert!(!tracer.wants(0x0150, 3));
+        assert!(!tracer.wants(0x5000, 3));
+
+        let home = Tracer::ring_buffer(1, TraceFilter { pc_range: None, bank: Some(0) });
+        assert!(home.wants(0x0150, 5));
+        assert!(!home.wants(0xC000, 0));
+        assert!(Tracer::ring_buffer(1, TraceFilter::default()).wants(0xC000, 0));
+    }
+
+    #[test]
+    fn formats_lines_like_gameboy_doctor() {
+        let registers = Registers::new(false);
+        assert_eq!(Tracer::format_line(&registers, 0xFFFE, 0x0100, [0x00, 0xC3, 0x13, 0x02]),
+                   "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02");
+    }
+}
@@ -3,6 +3,7 @@
 use std::cell::RefCell;
 use std::rc::Rc;
 use crate::cpu::interrupts::{Interrupt, InterruptController};
+use crate::sgb::Sgb;
 
 mod key_masks {
     pub const RIGHT_A: u8      = 0b00000001;
@@ -38,7 +39,8 @@ pub struct Joypad {
     key_state_buttons: u8,
     key_state_dir: u8,
     key_select: u8,
-    interrupt_controller: Rc<RefCell<InterruptController>>
+    interrupt_controller: Rc<RefCell<InterruptController>>,
+    sgb: Option<Rc<RefCell<Sgb>>>
 }
 
 impl Joypad {
@@ -47,18 +49,35 @@ impl Joypad {
             key_state_buttons: 0xF,
             key_state_dir: 0xF,
             key_select: 0xF0,
-            interrupt_controller
+            interrupt_controller,
+            sgb: None
         }
     }
 
     pub fn get_key_register(&self) -> u8 {
         let mut res = (self.key_select & 0xF0) | 0xC0;
-        if self.buttons_enabled() { res |= self.key_state_buttons; }
-        if self.directional_enabled() { res |= self.key_state_dir; }
+        let (multiplayer, player) = self.sgb.as_ref()
+            .map_or((false, 0), |sgb| ((**sgb).borrow().multiplayer(), (**sgb).borrow().current_player()));
+        // Only the first controller is connected, the others read as released
+        if player == 0 {
+            if self.buttons_enabled() { res |= self.key_state_buttons; }
+            if self.directional_enabled() { res |= self.key_state_dir; }
+        } else {
+            res |= 0x0F;
+        }
+        // With multiple controllers on a Super Game Boy, the low nibble is the ID of the current one
+        if multiplayer && !self.buttons_enabled() && !self.directional_enabled() {
+            res = (res & 0xF0) | (0x0F - player);
+        }
 
         res
     }
 
+    /// Connects the Super Game Boy that listens to the packets sent through this register.
+    pub fn set_sgb(&mut self, sgb: Option<Rc<RefCell<Sgb>>>) {
+        self.sgb = sgb;
+    }
+
     fn buttons_enabled(&self) -> bool {
         self.key_select & key_masks::BUTTON == 0
     }
@@ -98,6 +117,9 @@ impl Joypad {
 
     pub fn select_key_group(&mut self, val: u8) {
         self.key_select = (val & 0xF0) | 0xC0;
+        if let Some(ref sgb) = self.sgb {
+            (**sgb).borrow_mut().write_joypad(val);
+        }
     }
 
     #[inline]
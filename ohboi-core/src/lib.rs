@@ -9,5 +9,8 @@ pub mod audio;
 pub mod utils;
 pub mod ppu;
 pub mod ohboi;
+pub mod sgb;
+#[cfg(feature = "debugging")]
+pub mod debug;
 
 pub use ohboi::GameBoy;
@@ -0,0 +1,151 @@
+// Copyright Antonio Porsia 2025. Licensed under the EUPL-1.2 or later.
+
+use std::fs::File;
+use std::io::{self, ErrorKind, Read};
+use std::path::Path;
+
+/// Width of the picture captured by the Game Boy Camera sensor, in pixels.
+pub const CAMERA_WIDTH: usize = 128;
+/// Height of the picture captured by the Game Boy Camera sensor, in pixels.
+pub const CAMERA_HEIGHT: usize = 112;
+
+/// A grayscale frame, one byte per pixel from 0 (black) to 255 (white), row by row.
+pub type CameraFrame = [u8; CAMERA_WIDTH * CAMERA_HEIGHT];
+
+/// Supplies the pictures seen by the Game Boy Camera sensor.
+///
+/// A frame is requested every time the game starts a capture.
+pub trait CameraSource {
+    /// Fills `frame` with the picture currently in front of the lens.
+    fn capture(&mut self, frame: &mut CameraFrame);
+}
+
+/// Moving gray bars, for machines without a webcam.
+#[derive(Debug, Default)]
+pub struct TestPattern {
+    offset: usize,
+}
+
+impl TestPattern {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl CameraSource for TestPattern {
+    fn capture(&mut self, frame: &mut CameraFrame) {
+        for (i, pixel) in frame.iter_mut().enumerate() {
+            let (x, y) = (i % CAMERA_WIDTH, i / CAMERA_WIDTH);
+            // Four vertical bars sliding to the right, with a diagonal gradient on top
+            let bar = ((x + self.offset) / (CAMERA_WIDTH / 4)) % 4;
+            let gradient = (x + y) * 64 / (CAMERA_WIDTH + CAMERA_HEIGHT);
+            *pixel = (bar * 64 + gradient).min(255) as u8;
+        }
+        self.offset = (self.offset + 1) % CAMERA_WIDTH;
+    }
+}
+
+/// A still picture, scaled to the size of the sensor.
+#[derive(Debug, Clone)]
+pub struct StaticImage {
+    frame: Box<CameraFrame>,
+}
+
+impl StaticImage {
+    /// Creates a source from 8-bit grayscale pixels, stored row by row.
+    ///
+    /// # Arguments
+    ///
+    /// * `width` - The width of the picture.
+    /// * `height` - The height of the picture.
+    /// * `pixels` - The pixels, from 0 (black) to 255 (white).
+    ///
+    /// # Returns
+    ///
+    /// The source, or `None` if `pixels` doesn't hold `width * height` pixels.
+    pub fn from_luma(width: usize, height: usize, pixels: &[u8]) -> Option<Self> {
+        if width == 0 || height == 0 || pixels.len() != width * height {
+            return None;
+        }
+        let mut frame = Box::new([0; CAMERA_WIDTH * CAMERA_HEIGHT]);
+        for (i, pixel) in frame.iter_mut().enumerate() {
+            let (x, y) = (i % CAMERA_WIDTH, i / CAMERA_WIDTH);
+            *pixel = pixels[(y * height / CAMERA_HEIGHT) * width + x * width / CAMERA_WIDTH];
+        }
+        Some(Self { frame })
+    }
+
+    /// Loads a binary (P5) PGM picture.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to the picture.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the source or an error.
+    pub fn open_pgm(path: &Path) -> io::Result<Self> {
+        let mut data = Vec::new();
+        File::open(path)?.read_to_end(&mut data)?;
+        Self::parse_pgm(&data)
+    }
+
+    fn parse_pgm(data: &[u8]) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(ErrorKind::InvalidData, format!("Invalid PGM: {}", msg));
+        let mut pos = 0;
+        let mut fields = [0; 3];
+        if !data.starts_with(b"P5") {
+            return Err(invalid("not a binary graymap"));
+        }
+        pos += 2;
+        for field in fields.iter_mut() {
+            // Skip whitespace and comments up to the next number
+            loop {
+                match data.get(pos) {
+                    Some(b'#') => while data.get(pos).is_some_and(|&c| c != b'\n') { pos += 1 },
+                    Some(c) if c.is_ascii_whitespace() => pos += 1,
+                    Some(_) => break,
+                    None => return Err(invalid("truncated header")),
+                }
+            }
+            let start = pos;
+            while data.get(pos).is_some_and(u8::is_ascii_digit) {
+                pos += 1;
+            }
+            *field = std::str::from_utf8(&data[start..pos]).ok()
+                .and_then(|s| s.parse::<usize>().ok())
+                .ok_or_else(|| invalid("bad header field"))?;
+        }
+        let [width, height, max] = fields;
+        if max == 0 || max > 255 {
+            return Err(invalid("only 8-bit graymaps are supported"));
+        }
+        // A single whitespace character separates the header from the pixels
+        let pixels = data.get(pos + 1..pos + 1 + width * height).ok_or_else(|| invalid("truncated pixels"))?;
+        let pixels: Vec<u8> = pixels.iter().map(|&p| (p as usize * 255 / max) as u8).collect();
+        Self::from_luma(width, height, &pixels).ok_or_else(|| invalid("empty picture"))
+    }
+}
+
+impl CameraSource for StaticImage {
+    fn capture(&mut self, frame: &mut CameraFrame) {
+        frame.copy_from_slice(self.frame.as_ref());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pgm_is_scaled_to_sensor() {
+        let mut pgm = b"P5\n# 2x1 picture\n2 1\n255\n".to_vec();
+        pgm.extend_from_slice(&[0, 255]);
+        let mut image = StaticImage::parse_pgm(&pgm).unwrap();
+        let mut frame = [0; CAMERA_WIDTH * CAMERA_HEIGHT];
+        image.capture(&mut frame);
+        assert_eq!(frame[0], 0);
+        assert_eq!(frame[CAMERA_WIDTH - 1], 255);
+        assert_eq!(frame[(CAMERA_HEIGHT - 1) * CAMERA_WIDTH + CAMERA_WIDTH / 2], 255);
+    }
+}
@@ -0,0 +1,90 @@
+// Copyright Antonio Porsia 2025. Licensed under the EUPL-1.2 or later.
+
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::{Duration, SystemTime};
+
+/// Dots (T-cycles at normal speed) per second.
+const DOTS_PER_SECOND: u64 = 4_194_304;
+
+/// Supplies the current time to cartridge clocks (MBC3 and HuC3 RTCs).
+pub trait ClockSource {
+    /// The time elapsed since the origin of the clock. For the system clock that is the Unix epoch,
+    /// which is also the timestamp stored in save files.
+    fn now(&self) -> Duration;
+
+    /// Called as the emulation advances, every 4 dots. Clocks that follow real time ignore it.
+    fn advance(&mut self, _dots: u64) {}
+
+    /// Whether the clock follows the host's wall clock, so that the time the emulator was closed
+    /// for, as told by the timestamp in save files, counts on loading.
+    fn is_wall_clock(&self) -> bool {
+        false
+    }
+}
+
+/// The wall clock of the host. The RTC keeps running while the emulator is closed.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl ClockSource for SystemClock {
+    fn now(&self) -> Duration {
+        SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default()
+    }
+
+    fn is_wall_clock(&self) -> bool {
+        true
+    }
+}
+
+/// A clock driven by the emulated cycles, so runs are reproducible whatever the host speed.
+#[derive(Debug, Default, Clone)]
+pub struct EmulatedClock {
+    start: Duration,
+    dots: u64,
+}
+
+impl EmulatedClock {
+    /// Creates a clock that starts counting at `start`.
+    pub fn new(start: Duration) -> Self {
+        Self { start, dots: 0 }
+    }
+}
+
+impl ClockSource for EmulatedClock {
+    fn now(&self) -> Duration {
+        self.start + Duration::from_secs(self.dots / DOTS_PER_SECOND)
+            + Duration::from_nanos((self.dots % DOTS_PER_SECOND) * 1_000_000_000 / DOTS_PER_SECOND)
+    }
+
+    fn advance(&mut self, dots: u64) {
+        self.dots += dots;
+    }
+}
+
+/// A clock that only moves when told to. Clones share the same time, so one can be handed
+/// to the emulator and the other kept to set the time, e.g. in tests.
+#[derive(Debug, Default, Clone)]
+pub struct FixedClock {
+    time: Rc<Cell<Duration>>,
+}
+
+impl FixedClock {
+    pub fn new(time: Duration) -> Self {
+        Self { time: Rc::new(Cell::new(time)) }
+    }
+
+    pub fn set(&self, time: Duration) {
+        self.time.set(time);
+    }
+
+    pub fn advance_by(&self, duration: Duration) {
+        self.time.set(self.time.get() + duration);
+    }
+}
+
+impl ClockSource for FixedClock {
+    fn now(&self) -> Duration {
+        self.time.get()
+    }
+}
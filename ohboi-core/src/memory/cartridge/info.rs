@@ -0,0 +1,434 @@
+// Copyright Antonio Porsia 2025. Licensed under the EUPL-1.2 or later.
+
+use std::fs::File;
+use std::io::{self, ErrorKind, Read};
+use std::path::Path;
+use crate::memory::cartridge::CartridgeType;
+use crate::memory::cartridge::mbc::has_mapper;
+
+/// The logo every cartridge has at 0x104-0x133, checked by the boot ROM.
+pub const NINTENDO_LOGO: [u8; 48] = [
+    0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D,
+    0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99,
+    0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E,
+];
+
+/// The header ends at 0x14F, smaller files can't be cartridges.
+const HEADER_END: usize = 0x150;
+
+/// Where the 32 KiB block holding the header of the whole cartridge starts. That is the start of
+/// the ROM, except for MMM01 dumps: they start with the first game, and the menu, with the
+/// header for the whole cartridge, is in the last 32 KiB.
+pub(crate) fn header_offset(rom: &[u8]) -> usize {
+    if rom.len() > 0x8000 && (0x0B..=0x0D).contains(&rom[rom.len() - 0x8000 + 0x147]) {
+        rom.len() - 0x8000
+    } else {
+        0
+    }
+}
+
+/// How a cartridge supports the Game Boy Color, from the flag at 0x143.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CgbSupport {
+    /// A Game Boy game.
+    None,
+    /// Works on both the Game Boy and the Game Boy Color, with color enhancements.
+    Supported,
+    /// Refuses to run on anything but a Game Boy Color.
+    Only,
+}
+
+/// The market a cartridge was sold in, from the byte at 0x14A.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Destination {
+    Japan,
+    Overseas,
+}
+
+/// The parsed cartridge header, as found at 0x100-0x14F of the ROM, with the results of its integrity checks.
+#[derive(Debug, Clone)]
+pub struct CartridgeInfo {
+    /// The title, without trailing padding.
+    pub title: String,
+    /// The manufacturer code of later cartridges, empty for older ones.
+    pub manufacturer_code: String,
+    pub cgb: CgbSupport,
+    pub sgb: bool,
+    pub cart_type: CartridgeType,
+    /// The raw cartridge type byte, for types ohBoi doesn't know.
+    pub cart_type_code: u8,
+    /// The ROM size in bytes, as reported by the header.
+    pub rom_size: usize,
+    /// The RAM size in bytes, as reported by the header.
+    pub ram_size: usize,
+    pub destination: Destination,
+    pub old_licensee_code: u8,
+    /// Only meaningful when `old_licensee_code` is 0x33.
+    pub new_licensee_code: String,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+    /// The header checksum matches the header. The boot ROM locks up otherwise.
+    pub header_checksum_valid: bool,
+    /// The global checksum matches the ROM. Real hardware never checks it.
+    pub global_checksum_valid: bool,
+    /// The logo matches the Nintendo logo. The boot ROM locks up otherwise.
+    pub logo_valid: bool,
+}
+
+impl CartridgeInfo {
+    /// Parses the header of a ROM image, the one of the menu for MMM01 dumps.
+    ///
+    /// # Arguments
+    ///
+    /// * `rom` - The whole ROM image.
+    ///
+    /// # Returns
+    ///
+    /// The header, or `None` if the image is too small to have one.
+    pub fn from_rom(rom: &[u8]) -> Option<Self> {
+        if rom.len() < HEADER_END {
+            return None;
+        }
+        let offset = header_offset(rom);
+        let header = &rom[offset..];
+        let cgb = match header[0x143] {
+            0xC0 => CgbSupport::Only,
+            flag if flag & 0x80 != 0 => CgbSupport::Supported,
+            _ => CgbSupport::None,
+        };
+        // Later cartridges shortened the title to make room for the manufacturer code and the CGB flag
+        let (title_end, manufacturer_code) = match cgb {
+            CgbSupport::None => (0x144, String::new()),
+            _ if header[0x13F..0x143].iter().all(u8::is_ascii_uppercase) =>
+                (0x13F, String::from_utf8_lossy(&header[0x13F..0x143]).into_owned()),
+            _ => (0x143, String::new()),
+        };
+        let title = header[0x134..title_end].split(|&c| c == 0).next().unwrap_or_default();
+
+        let header_checksum = header[0x134..=0x14C].iter().fold(0u8, |x, &b| x.wrapping_sub(b).wrapping_sub(1));
+        let global_checksum = rom.iter().enumerate()
+            .filter(|&(i, _)| i != offset + 0x14E && i != offset + 0x14F)
+            .fold(0u16, |sum, (_, &b)| sum.wrapping_add(b as u16));
+        let stored_global_checksum = u16::from_be_bytes([header[0x14E], header[0x14F]]);
+
+        Some(Self {
+            title: String::from_utf8_lossy(title).trim_end().to_owned(),
+            manufacturer_code,
+            cgb,
+            // The SGB flag is only honoured along with the old licensee code 0x33
+            sgb: header[0x146] == 0x03 && header[0x14B] == 0x33,
+            cart_type: CartridgeType::from_code(header[0x147]).unwrap_or(CartridgeType::None),
+            cart_type_code: header[0x147],
+            rom_size: match header[0x148] {
+                val @ 0x00..=0x08 => 0x8000 << val,
+                0x52 => 72 * 0x4000,
+                0x53 => 80 * 0x4000,
+                0x54 => 96 * 0x4000,
+                _ => 0,
+            },
+            ram_size: match header[0x149] {
+                0x02 => 0x2000,
+                0x03 => 4 * 0x2000,
+                0x04 => 16 * 0x2000,
+                0x05 => 8 * 0x2000,
+                _ => 0,
+            },
+            destination: if header[0x14A] == 0x00 { Destination::Japan } else { Destination::Overseas },
+            old_licensee_code: header[0x14B],
+            new_licensee_code: String::from_utf8_lossy(&header[0x144..=0x145]).into_owned(),
+            version: header[0x14C],
+            header_checksum: header[0x14D],
+            global_checksum: stored_global_checksum,
+            header_checksum_valid: header_checksum == header[0x14D],
+            global_checksum_valid: global_checksum == stored_global_checksum,
+            logo_valid: header[0x104..0x134] == NINTENDO_LOGO,
+        })
+    }
+
+    /// Reads the header of a ROM file.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to the ROM file.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the header or an error.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut rom = Vec::new();
+        File::open(path)?.read_to_end(&mut rom)?;
+        Self::from_rom(&rom).ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "File too small to be a Game Boy ROM"))
+    }
+
+    /// Whether the boot ROM would accept the cartridge. The global checksum isn't required.
+    pub fn is_bootable(&self) -> bool {
+        self.header_checksum_valid && self.logo_valid
+    }
+
+    /// Whether ohBoi has a mapper for the cartridge type. Unsupported cartridges run as if they had none.
+    pub fn mapper_supported(&self) -> bool {
+        CartridgeType::from_code(self.cart_type_code).is_some_and(has_mapper)
+    }
+
+    /// The name of the publisher, from the new licensee code if the old one says so.
+    pub fn licensee(&self) -> &'static str {
+        if self.old_licensee_code == 0x33 {
+            new_licensee_name(&self.new_licensee_code)
+        } else {
+            old_licensee_name(self.old_licensee_code)
+        }
+    }
+}
+
+fn new_licensee_name(code: &str) -> &'static str {
+    match code {
+        "00" => "None",
+        "01" => "Nintendo Research & Development 1",
+        "08" => "Capcom",
+        "13" => "EA (Electronic Arts)",
+        "18" => "Hudson Soft",
+        "19" => "B-AI",
+        "20" => "KSS",
+        "22" => "Planning Office WADA",
+        "24" => "PCM Complete",
+        "25" => "San-X",
+        "28" => "Kemco",
+        "29" => "SETA Corporation",
+        "30" => "Viacom",
+        "31" => "Nintendo",
+        "32" => "Bandai",
+        "33" => "Ocean Software/Acclaim Entertainment",
+        "34" => "Konami",
+        "35" => "HectorSoft",
+        "37" => "Taito",
+        "38" => "Hudson Soft",
+        "39" => "Banpresto",
+        "41" => "Ubi Soft",
+        "42" => "Atlus",
+        "44" => "Malibu Interactive",
+        "46" => "Angel",
+        "47" => "Bullet-Proof Software",
+        "49" => "Irem",
+        "50" => "Absolute",
+        "51" => "Acclaim Entertainment",
+        "52" => "Activision",
+        "53" => "Sammy USA Corporation",
+        "54" => "Konami",
+        "55" => "Hi Tech Expressions",
+        "56" => "LJN",
+        "57" => "Matchbox",
+        "58" => "Mattel",
+        "59" => "Milton Bradley Company",
+        "60" => "Titus Interactive",
+        "61" => "Virgin Games Ltd.",
+        "64" => "Lucasfilm Games",
+        "67" => "Ocean Software",
+        "69" => "EA (Electronic Arts)",
+        "70" => "Infogrames",
+        "71" => "Interplay Entertainment",
+        "72" => "Broderbund",
+        "73" => "Sculptured Software",
+        "75" => "The Sales Curve Limited",
+        "78" => "THQ",
+        "79" => "Accolade",
+        "80" => "Misawa Entertainment",
+        "83" => "LOZC G.",
+        "86" => "Tokuma Shoten",
+        "87" => "Tsukuda Original",
+        "91" => "Chunsoft Co.",
+        "92" => "Video System",
+        "93" => "Ocean Software/Acclaim Entertainment",
+        "95" => "Varie",
+        "96" => "Yonezawa/S'Pal",
+        "97" => "Kaneko",
+        "99" => "Pack-In-Video",
+        "9H" => "Bottom Up",
+        "A4" => "Konami (Yu-Gi-Oh!)",
+        "BL" => "MTO",
+        "DK" => "Kodansha",
+        _ => "Unknown",
+    }
+}
+
+fn old_licensee_name(code: u8) -> &'static str {
+    match code {
+        0x00 => "None",
+        0x01 | 0x31 => "Nintendo",
+        0x08 | 0x38 => "Capcom",
+        0x09 => "HOT-B",
+        0x0A | 0xE0 => "Jaleco",
+        0x0B => "Coconuts Japan",
+        0x0C | 0x6E => "Elite Systems",
+        0x13 | 0x69 => "EA (Electronic Arts)",
+        0x18 => "Hudson Soft",
+        0x19 => "ITC Entertainment",
+        0x1A => "Yanoman",
+        0x1D => "Japan Clary",
+        0x1F | 0x4A | 0x61 => "Virgin Games Ltd.",
+        0x24 => "PCM Complete",
+        0x25 => "San-X",
+        0x28 | 0x7F | 0x97 | 0xC2 => "Kemco",
+        0x29 => "SETA Corporation",
+        0x30 | 0x70 => "Infogrames",
+        0x32 | 0xA2 | 0xB2 => "Bandai",
+        0x34 | 0xA4 => "Konami",
+        0x35 => "HectorSoft",
+        0x39 | 0x9D | 0xD9 => "Banpresto",
+        0x3C => "Entertainment Interactive",
+        0x3E => "Gremlin",
+        0x41 => "Ubi Soft",
+        0x42 | 0xEB => "Atlus",
+        0x44 | 0x4D => "Malibu Interactive",
+        0x46 | 0xCF => "Angel",
+        0x47 => "Spectrum HoloByte",
+        0x49 => "Irem",
+        0x4F => "U.S. Gold",
+        0x50 => "Absolute",
+        0x51 | 0xB0 => "Acclaim Entertainment",
+        0x52 => "Activision",
+        0x53 => "Sammy USA Corporation",
+        0x54 => "GameTek",
+        0x55 => "Park Place",
+        0x56 | 0xDB | 0xFF => "LJN",
+        0x57 => "Matchbox",
+        0x59 => "Milton Bradley Company",
+        0x5A => "Mindscape",
+        0x5B => "Romstar",
+        0x5C | 0xD6 => "Naxat Soft",
+        0x5D => "Tradewest",
+        0x60 => "Titus Interactive",
+        0x67 => "Ocean Software",
+        0x6F => "Electro Brain",
+        0x71 => "Interplay Entertainment",
+        0x72 | 0xAA => "Broderbund",
+        0x73 => "Sculptured Software",
+        0x75 => "The Sales Curve Limited",
+        0x78 => "THQ",
+        0x79 => "Accolade",
+        0x7A => "Triffix Entertainment",
+        0x7C => "MicroProse",
+        0x80 => "Misawa Entertainment",
+        0x83 => "LOZC G.",
+        0x86 | 0xC4 => "Tokuma Shoten",
+        0x8B => "Bullet-Proof Software",
+        0x8C => "Vic Tokai Corp.",
+        0x8E => "Ape Inc.",
+        0x8F => "I'Max",
+        0x91 => "Chunsoft Co.",
+        0x92 => "Video System",
+        0x93 => "Tsubaraya Productions",
+        0x95 | 0xE3 => "Varie",
+        0x96 => "Yonezawa/S'Pal",
+        0x99 => "Arc",
+        0x9A => "Nihon Bussan",
+        0x9B => "Tecmo",
+        0x9C => "Imagineer",
+        0x9F => "Nova",
+        0xA1 => "Hori Electric",
+        0xA6 => "Kawada",
+        0xA7 => "Takara",
+        0xA9 => "Technos Japan",
+        0xAC => "Toei Animation",
+        0xAD => "Toho",
+        0xAF => "Namco",
+        0xB1 => "ASCII Corporation or Nexsoft",
+        0xB4 => "Square Enix",
+        0xB6 => "HAL Laboratory",
+        0xB7 => "SNK",
+        0xB9 | 0xCE => "Pony Canyon",
+        0xBA => "Culture Brain",
+        0xBB => "Sunsoft",
+        0xBD => "Sony Imagesoft",
+        0xBF => "Sammy Corporation",
+        0xC0 | 0xD0 => "Taito",
+        0xC3 => "Square",
+        0xC5 => "Data East",
+        0xC6 => "Tonkin House",
+        0xC8 => "Koei",
+        0xC9 => "UFL",
+        0xCA => "Ultra Games",
+        0xCB => "VAP, Inc.",
+        0xCC => "Use Corporation",
+        0xCD => "Meldac",
+        0xD1 => "SOFEL",
+        0xD2 => "Quest",
+        0xD3 => "Sigma Enterprises",
+        0xD4 => "ASK Kodansha Co.",
+        0xD7 => "Copya System",
+        0xDA => "Tomy",
+        0xDD => "Nippon Computer Systems",
+        0xDE => "Human Ent.",
+        0xDF => "Altron",
+        0xE1 => "Towa Chiki",
+        0xE2 => "Yutaka",
+        0xE5 => "Epoch",
+        0xE7 => "Athena",
+        0xE8 => "Asmik Ace Entertainment",
+        0xE9 => "Natsume",
+        0xEA => "King Records",
+        0xEC => "Epic/Sony Records",
+        0xEE => "IGS",
+        0xF0 => "A Wave",
+        0xF3 => "Extreme Entertainment",
+        _ => "Unknown",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_rom() -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x104..0x134].copy_from_slice(&NINTENDO_LOGO);
+        rom[0x134..0x13B].copy_from_slice(b"POKEMON");
+        rom[0x13F..0x143].copy_from_slice(b"AAXE");
+        rom[0x143] = 0x80;
+        rom[0x144..0x146].copy_from_slice(b"01");
+        rom[0x147] = 0x10;
+        rom[0x148] = 0x06;
+        rom[0x149] = 0x03;
+        rom[0x14A] = 0x01;
+        rom[0x14B] = 0x33;
+        rom[0x14D] = rom[0x134..=0x14C].iter().fold(0u8, |x, &b| x.wrapping_sub(b).wrapping_sub(1));
+        let sum = rom.iter().fold(0u16, |sum, &b| sum.wrapping_add(b as u16));
+        rom[0x14E..0x150].copy_from_slice(&sum.to_be_bytes());
+        rom
+    }
+
+    #[test]
+    fn reads_the_menu_header_of_mmm01_dumps() {
+        let mut rom = vec![0; 0x10000];
+        rom[0x134..0x139].copy_from_slice(b"GAME1");
+        rom[0x147] = 0x01;
+        let menu = &mut rom[0x8000..];
+        menu[0x134..0x138].copy_from_slice(b"MENU");
+        menu[0x147] = 0x0B;
+        menu[0x148] = 0x01;
+        let info = CartridgeInfo::from_rom(&rom).unwrap();
+        assert_eq!(info.title, "MENU");
+        assert_eq!(info.cart_type, CartridgeType::Mmm01);
+        assert_eq!(info.rom_size, 0x10000);
+    }
+
+    #[test]
+    fn parses_and_verifies_header() {
+        let info = CartridgeInfo::from_rom(&test_rom()).unwrap();
+        assert_eq!(info.title, "POKEMON");
+        assert_eq!(info.manufacturer_code, "AAXE");
+        assert_eq!(info.cgb, CgbSupport::Supported);
+        assert_eq!(info.cart_type, CartridgeType::Mbc3TimerRamBattery);
+        assert_eq!((info.rom_size, info.ram_size), (0x200000, 0x8000));
+        assert_eq!(info.destination, Destination::Overseas);
+        assert_eq!(info.licensee(), "Nintendo Research & Development 1");
+        assert!(info.header_checksum_valid && info.global_checksum_valid && info.logo_valid);
+
+        let mut corrupt = test_rom();
+        corrupt[0x4000] = 0xFF;
+        corrupt[0x104] = 0x00;
+        let info = CartridgeInfo::from_rom(&corrupt).unwrap();
+        assert!(info.header_checksum_valid);
+        assert!(!info.global_checksum_valid && !info.logo_valid && !info.is_bootable());
+    }
+}
@@ -0,0 +1,269 @@
+// Copyright Antonio Porsia 2025. Licensed under the EUPL-1.2 or later.
+
+use log::debug;
+use crate::memory::cartridge::camera::{CameraFrame, CameraSource, TestPattern, CAMERA_HEIGHT, CAMERA_WIDTH};
+use crate::memory::cartridge::CartridgeHeader;
+use super::{Mbc, RAM_BANK_SIZE, ROM_BANK_SIZE};
+
+/// Selecting a RAM bank with this bit set maps the sensor registers at 0xA000-0xBFFF.
+const REGISTERS_BANK: usize = 0x10;
+const N_REGISTERS: usize = 0x36;
+/// The captured picture is written as 2bpp tiles at this offset in RAM bank 0.
+const PICTURE_OFFSET: usize = 0x100;
+
+/// Sensor registers, see the M64282FP datasheet.
+const REG_CAPTURE: usize = 0x00;
+/// N (bit 7), VH edge mode (bits 5-6), gain (bits 0-4).
+const REG_EDGE_GAIN: usize = 0x01;
+const REG_EXPOSURE_HI: usize = 0x02;
+const REG_EXPOSURE_LO: usize = 0x03;
+/// Edge enhancement ratio (bits 4-6), invert (bit 3), output reference voltage (bits 0-2).
+const REG_EDGE_RATIO_INVERT: usize = 0x04;
+/// The 4x4 dithering matrix, three thresholds for each pixel position.
+const REG_DITHER_MATRIX: usize = 0x06;
+
+/// Exposure at which a frame reaches the sensor unchanged.
+const NOMINAL_EXPOSURE: f32 = 0x1000 as f32;
+const EDGE_RATIOS: [f32; 8] = [0.5, 0.75, 1.0, 1.25, 2.0, 3.0, 4.0, 5.0];
+
+/// The Game Boy Camera (Pocket Camera) mapper, with 128 KiB of RAM for the photo album
+/// and a Mitsubishi M64282FP image sensor.
+pub(crate) struct PocketCamera {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    rom_bank: usize,
+    ram_bank: usize,
+    n_rom_banks: usize,
+    n_ram_banks: usize,
+    registers: [u8; N_REGISTERS],
+    source: Box<dyn CameraSource>,
+    /// M-cycles until the capture in progress is written to RAM.
+    busy_cycles: u32,
+    /// The processed picture of the capture in progress, 2 bits per pixel (0 is white).
+    picture: Box<[u8; CAMERA_WIDTH * CAMERA_HEIGHT]>,
+}
+
+impl PocketCamera {
+    pub fn new(rom: Vec<u8>, cart_header: &CartridgeHeader, sav: Option<Vec<u8>>) -> Self {
+        let ram_size = cart_header.ram_size.max(RAM_BANK_SIZE);
+        let mut ram = sav.unwrap_or_default();
+        ram.resize(ram_size, 0);
+        Self {
+            rom,
+            ram,
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            n_rom_banks: cart_header.rom_size / ROM_BANK_SIZE,
+            n_ram_banks: ram_size / RAM_BANK_SIZE,
+            registers: [0; N_REGISTERS],
+            source: Box::new(TestPattern::new()),
+            busy_cycles: 0,
+            picture: Box::new([0; CAMERA_WIDTH * CAMERA_HEIGHT]),
+        }
+    }
+
+    fn registers_mapped(&self) -> bool {
+        self.ram_bank & REGISTERS_BANK != 0
+    }
+
+    fn ram_addr(&self, addr: u16) -> usize {
+        (self.ram_bank % self.n_ram_banks) * RAM_BANK_SIZE + (addr as usize - 0xA000)
+    }
+
+    fn exposure(&self) -> u32 {
+        (self.registers[REG_EXPOSURE_HI] as u32) << 8 | self.registers[REG_EXPOSURE_LO] as u32
+    }
+
+    /// Starts a capture: takes a frame from the source and runs it through the sensor
+    /// with the current register values. The result lands in RAM once the exposure is over.
+    fn start_capture(&mut self) {
+        let mut frame: CameraFrame = [0; CAMERA_WIDTH * CAMERA_HEIGHT];
+        self.source.capture(&mut frame);
+
+        let edge_gain = self.registers[REG_EDGE_GAIN];
+        let n = edge_gain & 0x80 != 0;
+        let (horizontal, vertical) = match (edge_gain >> 5) & 0x3 {
+            0b01 => (true, false),
+            0b10 => (false, true),
+            0b11 => (true, true),
+            _ => (false, false)
+        };
+        let ratio_invert = self.registers[REG_EDGE_RATIO_INVERT];
+        let edge_ratio = EDGE_RATIOS[(ratio_invert as usize >> 4) & 0x7];
+        let invert = ratio_invert & 0x08 != 0;
+        // The gain register covers roughly 14 to 44 dB, 14 dB being the usual setting
+        let gain = 1.0 + (edge_gain & 0x1F) as f32 / 8.0;
+        let exposure = self.exposure() as f32 / NOMINAL_EXPOSURE;
+
+        let sensor = |x: isize, y: isize| -> f32 {
+            let x = x.clamp(0, CAMERA_WIDTH as isize - 1) as usize;
+            let y = y.clamp(0, CAMERA_HEIGHT as isize - 1) as usize;
+            frame[y * CAMERA_WIDTH + x] as f32 * gain * exposure
+        };
+        for y in 0..CAMERA_HEIGHT {
+            for x in 0..CAMERA_WIDTH {
+                let (xi, yi) = (x as isize, y as isize);
+                let mut value = sensor(xi, yi);
+                if horizontal {
+                    value += edge_ratio * (2.0 * sensor(xi, yi) - sensor(xi - 1, yi) - sensor(xi + 1, yi));
+                }
+                if vertical {
+                    value += edge_ratio * (2.0 * sensor(xi, yi) - sensor(xi, yi - 1) - sensor(xi, yi + 1));
+                }
+                let mut value = value.clamp(0.0, 255.0) as u8;
+                if invert {
+                    value = 255 - value;
+                }
+                let thresholds = REG_DITHER_MATRIX + ((y & 3) * 4 + (x & 3)) * 3;
+                let thresholds = &self.registers[thresholds..thresholds + 3];
+                self.picture[y * CAMERA_WIDTH + x] = match value {
+                    v if v < thresholds[0] => 3,
+                    v if v < thresholds[1] => 2,
+                    v if v < thresholds[2] => 1,
+                    _ => 0
+                };
+            }
+        }
+        self.busy_cycles = 32446 + if n { 0 } else { 512 } + 16 * self.exposure();
+        debug!("Camera capture started, {} cycles", self.busy_cycles);
+    }
+
+    /// Writes the processed picture to RAM bank 0 as 16x14 tiles.
+    fn finish_capture(&mut self) {
+        for y in 0..CAMERA_HEIGHT {
+            for x in (0..CAMERA_WIDTH).step_by(8) {
+                let tile = (y / 8) * (CAMERA_WIDTH / 8) + x / 8;
+                let addr = PICTURE_OFFSET + tile * 16 + (y % 8) * 2;
+                let (mut lo, mut hi) = (0u8, 0u8);
+                for &color in &self.picture[y * CAMERA_WIDTH + x..y * CAMERA_WIDTH + x + 8] {
+                    lo = lo << 1 | (color & 1);
+                    hi = hi << 1 | (color >> 1);
+                }
+                self.ram[addr] = lo;
+                self.ram[addr + 1] = hi;
+            }
+        }
+        self.registers[REG_CAPTURE] &= !1;
+    }
+}
+
+impl Mbc for PocketCamera {
+    fn read(&self, addr: u16) -> u8 {
+        self.rom[self.rom_offset(addr)]
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        let val = val as usize;
+        match addr {
+            0..=0x1FFF => self.ram_enabled = val & 0xF == 0xA,
+            0x2000..=0x3FFF => self.rom_bank = val & 0x3F,
+            0x4000..=0x5FFF => self.ram_bank = val & 0x1F,
+            _ => debug!("Invalid write to cartridge at address {:04X}", addr)
+        }
+    }
+
+    fn read_ext_ram(&self, addr: u16) -> u8 {
+        if self.registers_mapped() {
+            // Only the capture register can be read back
+            return match addr as usize & 0x7F {
+                REG_CAPTURE => self.registers[REG_CAPTURE],
+                _ => 0x00
+            };
+        }
+        self.ram[self.ram_addr(addr)]
+    }
+
+    fn write_ext_ram(&mut self, addr: u16, val: u8) {
+        if self.registers_mapped() {
+            match addr as usize & 0x7F {
+                REG_CAPTURE => {
+                    let start = val & 1 != 0 && self.registers[REG_CAPTURE] & 1 == 0;
+                    self.registers[REG_CAPTURE] = val & 0x07;
+                    if start {
+                        self.start_capture();
+                    }
+                },
+                reg if reg < N_REGISTERS => self.registers[reg] = val,
+                _ => {}
+            }
+        } else if self.ram_enabled {
+            let addr = self.ram_addr(addr);
+            self.ram[addr] = val;
+        }
+    }
+
+    fn clock(&mut self, m_cycles: u32) {
+        if self.busy_cycles == 0 {
+            return;
+        }
+        self.busy_cycles = self.busy_cycles.saturating_sub(m_cycles);
+        if self.busy_cycles == 0 {
+            self.finish_capture();
+        }
+    }
+
+    fn set_camera_source(&mut self, source: Box<dyn CameraSource>) {
+        self.source = source;
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank % self.n_rom_banks
+    }
+
+    fn has_battery(&self) -> bool {
+        true
+    }
+    fn has_ram(&self) -> bool {
+        true
+    }
+
+    fn ram_bank(&self) -> usize {
+        (self.ram_bank & !REGISTERS_BANK) % self.n_ram_banks
+    }
+
+    fn ram(&self) -> Option<&Vec<u8>> {
+        Some(&self.ram)
+    }
+
+    fn ram_mut(&mut self) -> Option<&mut Vec<u8>> {
+        Some(&mut self.ram)
+    }
+
+    fn rom(&self) -> &Vec<u8> {
+        &self.rom
+    }
+
+    fn rom_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.rom
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::cartridge::camera::StaticImage;
+
+    #[test]
+    fn capture_writes_picture_after_exposure() {
+        let header = CartridgeHeader::new(&vec![0; 0x8000]);
+        let mut camera = PocketCamera::new(vec![0; 0x8000], &header, None);
+        let white = [255; CAMERA_WIDTH * CAMERA_HEIGHT];
+        camera.set_camera_source(Box::new(StaticImage::from_luma(CAMERA_WIDTH, CAMERA_HEIGHT, &white).unwrap()));
+        camera.write(0x4000, REGISTERS_BANK as u8);
+        camera.write_ext_ram(0xA002, 0x10);
+        // A white frame at nominal exposure stays above every threshold
+        for reg in 0..48 {
+            camera.write_ext_ram(0xA006 + reg, 0x80);
+        }
+        camera.ram[PICTURE_OFFSET] = 0xAA;
+        camera.write_ext_ram(0xA000, 0x01);
+        assert_eq!(camera.read_ext_ram(0xA000) & 1, 1);
+
+        camera.clock(32446 + 512 + 16 * 0x1000);
+        assert_eq!(camera.read_ext_ram(0xA000) & 1, 0);
+        camera.write(0x4000, 0);
+        assert_eq!(camera.read_ext_ram(0xA000 + PICTURE_OFFSET as u16), 0);
+    }
+}
@@ -0,0 +1,145 @@
+// Copyright Antonio Porsia 2025. Licensed under the EUPL-1.2 or later.
+
+use log::debug;
+use crate::memory::cartridge::CartridgeHeader;
+use super::{Mbc, RAM_BANK_SIZE, ROM_BANK_SIZE};
+
+/// Value of the IR register when no light is received.
+const IR_NO_LIGHT: u8 = 0xC0;
+
+pub(crate) struct HuC1 {
+    rom: Vec<u8>,
+    ram: Option<Vec<u8>>,
+    /// 0xA000-0xBFFF maps the infrared port instead of RAM.
+    ir_mode: bool,
+    battery: bool,
+    rom_bank: usize,
+    ram_bank: usize,
+    n_rom_banks: usize,
+    n_ram_banks: usize,
+}
+
+impl HuC1 {
+    pub fn new(rom: Vec<u8>, cart_header: &CartridgeHeader, sav: Option<Vec<u8>>, battery: bool) -> Self {
+        Self {
+            rom,
+            ram: match sav {
+                Some(sav) => Some(sav),
+                None if cart_header.ram_size != 0 => Some(vec![0; cart_header.ram_size]),
+                _ => None
+            },
+            ir_mode: false,
+            battery,
+            rom_bank: 1,
+            ram_bank: 0,
+            n_rom_banks: cart_header.rom_size / ROM_BANK_SIZE,
+            n_ram_banks: cart_header.ram_size / RAM_BANK_SIZE,
+        }
+    }
+
+    fn ram_addr(&self, addr: u16) -> usize {
+        (self.ram_bank % self.n_ram_banks.max(1)) * RAM_BANK_SIZE + (addr as usize - 0xA000)
+    }
+}
+
+impl Mbc for HuC1 {
+    fn read(&self, addr: u16) -> u8 {
+        self.rom[self.rom_offset(addr)]
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        let val = val as usize;
+        match addr {
+            // There is no RAM enable, the register selects between RAM and the IR port
+            0..=0x1FFF => self.ir_mode = val & 0xF == 0xE,
+            0x2000..=0x3FFF => self.rom_bank = val & 0x3F,
+            0x4000..=0x5FFF => self.ram_bank = val & 0x3,
+            _ => debug!("Invalid write to cartridge at address {:04X}", addr)
+        }
+    }
+
+    fn read_ext_ram(&self, addr: u16) -> u8 {
+        if self.ir_mode {
+            // Nothing ever shines at the sensor
+            return IR_NO_LIGHT;
+        }
+        match self.ram {
+            Some(ref ram) => ram[self.ram_addr(addr)],
+            None => 0xFF
+        }
+    }
+
+    fn write_ext_ram(&mut self, addr: u16, val: u8) {
+        if self.ir_mode {
+            // Bit 0 drives the IR LED, there is no other device to see it
+            return;
+        }
+        let addr = self.ram_addr(addr);
+        if let Some(ref mut ram) = self.ram {
+            ram[addr] = val;
+        }
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank % self.n_rom_banks
+    }
+
+    fn has_battery(&self) -> bool {
+        self.battery
+    }
+    fn has_ram(&self) -> bool {
+        self.ram.is_some()
+    }
+
+    fn ram_bank(&self) -> usize {
+        self.ram_bank
+    }
+
+    fn ram(&self) -> Option<&Vec<u8>> {
+        self.ram.as_ref()
+    }
+
+    fn ram_mut(&mut self) -> Option<&mut Vec<u8>> {
+        self.ram.as_mut()
+    }
+
+    fn rom(&self) -> &Vec<u8> {
+        &self.rom
+    }
+
+    fn rom_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.rom
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn banks_ram_and_switches_to_the_ir_port() {
+        // 64 ROM banks, each filled with its number, and 4 RAM banks
+        let mut rom: Vec<u8> = (0..64).flat_map(|bank| vec![bank as u8; ROM_BANK_SIZE]).collect();
+        rom[0x148] = 0x05;
+        rom[0x149] = 0x03;
+        let header = CartridgeHeader::new(&rom);
+        let mut mbc = HuC1::new(rom, &header, None, true);
+
+        mbc.write(0x2000, 0x45);
+        assert_eq!(mbc.read(0x4000), 0x05);
+        mbc.write(0x2000, 0x3F);
+        assert_eq!(mbc.read(0x7FFF), 0x3F);
+
+        // RAM needs no enable
+        mbc.write(0x4000, 0x02);
+        mbc.write_ext_ram(0xA010, 0x99);
+        assert_eq!(mbc.ram().unwrap()[2 * RAM_BANK_SIZE + 0x10], 0x99);
+
+        // In IR mode the sensor sees no light and writes don't reach the RAM
+        mbc.write(0x0000, 0x0E);
+        assert_eq!(mbc.read_ext_ram(0xA010), IR_NO_LIGHT);
+        mbc.write_ext_ram(0xA010, 0x01);
+        mbc.write(0x0000, 0x00);
+        assert_eq!(mbc.read_ext_ram(0xA010), 0x99);
+    }
+}
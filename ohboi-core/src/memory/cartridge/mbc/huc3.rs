@@ -0,0 +1,426 @@
+// Copyright Antonio Porsia 2025. Licensed under the EUPL-1.2 or later.
+
+use std::time::Duration;
+use log::{debug, info, warn};
+use crate::memory::cartridge::CartridgeHeader;
+use crate::memory::cartridge::clock::{ClockSource, SystemClock};
+use super::{Mbc, RAM_BANK_SIZE, ROM_BANK_SIZE};
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+/// Value of the IR register when no light is received.
+const IR_NO_LIGHT: u8 = 0xC0;
+
+/// Locations in the RTC memory, in nibbles.
+const TIME_MINUTES: usize = 0x00;
+const TIME_DAYS: usize = 0x03;
+const ALARM_MINUTES: usize = 0x10;
+const ALARM_DAYS: usize = 0x13;
+const ALARM_ENABLED: usize = 0x16;
+
+/// Size of the clock state saved after the RAM: the 256 nibbles of RTC memory, two per byte
+/// with the lower address in the low nibble, followed by the Unix time of the save as a
+/// 64-bit little endian word.
+const RTC_FOOTER_SIZE: usize = 0x80 + 8;
+/// How many M-cycles pass between updates of the clock, about a second, so the alarm goes off
+/// even while the game doesn't read the time.
+const UPDATE_INTERVAL: u32 = 1 << 20;
+
+/// What 0xA000-0xBFFF maps to, selected by writes to 0x0000-0x1FFF.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Huc3Mode {
+    RamReadOnly,
+    RamReadWrite,
+    RtcCommand,
+    RtcResponse,
+    RtcSemaphore,
+    Ir,
+}
+
+impl From<u8> for Huc3Mode {
+    fn from(value: u8) -> Self {
+        match value & 0xF {
+            0xA => Self::RamReadWrite,
+            0xB => Self::RtcCommand,
+            0xC => Self::RtcResponse,
+            0xD => Self::RtcSemaphore,
+            0xE => Self::Ir,
+            _ => Self::RamReadOnly,
+        }
+    }
+}
+
+/// The HuC3 clock, accessed through a small command interface to 256 nibbles of memory.
+///
+/// It only counts minutes and days. The time is copied to and from memory with
+/// extended commands, and an alarm can be set that beeps through the cartridge speaker.
+struct Huc3Rtc {
+    memory: [u8; 0x100],
+    addr: u8,
+    /// The last command and its result, as read back in response mode.
+    response: u8,
+    minutes: u32,
+    days: u32,
+    last_update: Duration,
+    tone: bool,
+    clock: Box<dyn ClockSource>,
+}
+
+impl Huc3Rtc {
+    fn new(clock: Box<dyn ClockSource>) -> Self {
+        Self {
+            memory: [0; 0x100],
+            addr: 0,
+            response: 0,
+            minutes: 0,
+            days: 0,
+            last_update: clock.now(),
+            tone: false,
+            clock,
+        }
+    }
+
+    fn read_nibbles(&self, addr: usize, count: usize) -> u32 {
+        (0..count).fold(0, |acc, i| acc | (self.memory[addr + i] as u32 & 0xF) << (4 * i))
+    }
+
+    fn write_nibbles(&mut self, addr: usize, count: usize, val: u32) {
+        for i in 0..count {
+            self.memory[addr + i] = ((val >> (4 * i)) & 0xF) as u8;
+        }
+    }
+
+    /// Advances the clock by the minutes elapsed since the last update.
+    fn update_time(&mut self) {
+        let now = self.clock.now();
+        let Some(elapsed) = now.checked_sub(self.last_update) else {
+            self.last_update = now;
+            return;
+        };
+        let minutes = (elapsed.as_secs() / 60) as u32;
+        if minutes == 0 {
+            return;
+        }
+        let before = (self.days, self.minutes);
+        let total = self.minutes + minutes;
+        self.minutes = total % MINUTES_PER_DAY;
+        self.days = (self.days + total / MINUTES_PER_DAY) & 0xFFF;
+        self.last_update += Duration::from_secs(minutes as u64 * 60);
+        self.check_alarm(before);
+    }
+
+    /// Starts the tone if the alarm time was reached since `before`.
+    fn check_alarm(&mut self, before: (u32, u32)) {
+        if self.memory[ALARM_ENABLED] & 1 == 0 {
+            return;
+        }
+        let alarm = (self.read_nibbles(ALARM_DAYS, 3), self.read_nibbles(ALARM_MINUTES, 3));
+        if before < alarm && alarm <= (self.days, self.minutes) {
+            info!("HuC3 alarm went off");
+            self.tone = true;
+        }
+    }
+
+    /// Returns the clock state to append to the `.sav`, see `RTC_FOOTER_SIZE`.
+    fn save_footer(&mut self) -> Vec<u8> {
+        self.update_time();
+        self.write_nibbles(TIME_MINUTES, 3, self.minutes);
+        self.write_nibbles(TIME_DAYS, 3, self.days);
+        let mut buf: Vec<u8> = self.memory.chunks(2)
+            .map(|nibbles| (nibbles[0] & 0xF) | (nibbles[1] << 4))
+            .collect();
+        buf.extend_from_slice(&SystemClock.now().as_secs().to_le_bytes());
+        buf
+    }
+
+    /// Restores the clock from a footer written by `save_footer`.
+    fn load_footer(&mut self, buf: &[u8]) {
+        for (i, byte) in buf[..0x80].iter().enumerate() {
+            self.memory[2 * i] = byte & 0xF;
+            self.memory[2 * i + 1] = byte >> 4;
+        }
+        self.minutes = self.read_nibbles(TIME_MINUTES, 3) % MINUTES_PER_DAY;
+        self.days = self.read_nibbles(TIME_DAYS, 3);
+        if self.clock.is_wall_clock() {
+            // Count the time the game was closed for
+            self.last_update = Duration::from_secs(u64::from_le_bytes(buf[0x80..RTC_FOOTER_SIZE].try_into().unwrap()));
+            self.update_time();
+        } else {
+            self.last_update = self.clock.now();
+        }
+    }
+
+    fn command(&mut self, val: u8) {
+        let (command, arg) = ((val >> 4) & 0x7, val & 0xF);
+        let mut result = arg;
+        match command {
+            // Read and increment the address
+            0x1 => {
+                result = self.memory[self.addr as usize] & 0xF;
+                self.addr = self.addr.wrapping_add(1);
+            },
+            // Write and increment the address
+            0x3 => {
+                self.memory[self.addr as usize] = arg;
+                self.addr = self.addr.wrapping_add(1);
+            },
+            0x4 => self.addr = (self.addr & 0xF0) | arg,
+            0x5 => self.addr = (self.addr & 0x0F) | (arg << 4),
+            0x6 => result = self.extended_command(arg),
+            _ => debug!("Unknown HuC3 RTC command {:X}", command)
+        }
+        self.response = (command << 4) | (result & 0xF);
+    }
+
+    fn extended_command(&mut self, arg: u8) -> u8 {
+        match arg {
+            // Copy the current time to memory
+            0x0 => {
+                self.update_time();
+                self.write_nibbles(TIME_MINUTES, 3, self.minutes);
+                self.write_nibbles(TIME_DAYS, 3, self.days);
+            },
+            // Set the current time from memory
+            0x1 => {
+                self.minutes = self.read_nibbles(TIME_MINUTES, 3) % MINUTES_PER_DAY;
+                self.days = self.read_nibbles(TIME_DAYS, 3);
+                self.last_update = self.clock.now();
+            },
+            // Status, always ready
+            0x2 => return 0x1,
+            // Play or stop the tone generator
+            0xE => {
+                self.tone = !self.tone;
+                info!("HuC3 tone generator {}", if self.tone { "on" } else { "off" });
+            },
+            _ => debug!("Unknown HuC3 extended RTC command {:X}", arg)
+        }
+        arg
+    }
+}
+
+pub(crate) struct HuC3 {
+    rom: Vec<u8>,
+    ram: Option<Vec<u8>>,
+    mode: Huc3Mode,
+    rtc: Huc3Rtc,
+    rom_bank: usize,
+    ram_bank: usize,
+    n_rom_banks: usize,
+    n_ram_banks: usize,
+    /// M-cycles since the clock was last brought up to date.
+    cycles_since_update: u32,
+}
+
+impl HuC3 {
+    pub fn new(rom: Vec<u8>, cart_header: &CartridgeHeader, mut sav: Option<Vec<u8>>, clock: Box<dyn ClockSource>) -> Self {
+        let mut rtc = Huc3Rtc::new(clock);
+        // The clock is saved after the RAM, if at all
+        if let Some(ref mut sav) = sav {
+            let footer = sav.len().saturating_sub(cart_header.ram_size);
+            if footer == RTC_FOOTER_SIZE {
+                rtc.load_footer(&sav[cart_header.ram_size..]);
+                sav.truncate(cart_header.ram_size);
+            } else if footer != 0 {
+                warn!("Ignoring {} unexpected bytes at the end of the save file", footer);
+            }
+        }
+        Self {
+            rom,
+            ram: match sav {
+                Some(sav) if !sav.is_empty() => Some(sav),
+                _ if cart_header.ram_size != 0 => Some(vec![0; cart_header.ram_size]),
+                _ => None
+            },
+            mode: Huc3Mode::RamReadOnly,
+            rtc,
+            rom_bank: 1,
+            ram_bank: 0,
+            n_rom_banks: cart_header.rom_size / ROM_BANK_SIZE,
+            n_ram_banks: cart_header.ram_size / RAM_BANK_SIZE,
+            cycles_since_update: 0,
+        }
+    }
+
+    fn ram_addr(&self, addr: u16) -> usize {
+        (self.ram_bank % self.n_ram_banks.max(1)) * RAM_BANK_SIZE + (addr as usize - 0xA000)
+    }
+}
+
+impl Mbc for HuC3 {
+    fn read(&self, addr: u16) -> u8 {
+        self.rom[self.rom_offset(addr)]
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0..=0x1FFF => self.mode = Huc3Mode::from(val),
+            0x2000..=0x3FFF => self.rom_bank = val as usize & 0x7F,
+            0x4000..=0x5FFF => self.ram_bank = val as usize & 0x3,
+            _ => debug!("Invalid write to cartridge at address {:04X}", addr)
+        }
+    }
+
+    fn read_ext_ram(&self, addr: u16) -> u8 {
+        match self.mode {
+            Huc3Mode::RamReadOnly | Huc3Mode::RamReadWrite => match self.ram {
+                Some(ref ram) => ram[self.ram_addr(addr)],
+                None => 0xFF
+            },
+            Huc3Mode::RtcCommand | Huc3Mode::RtcResponse => 0x80 | self.rtc.response,
+            // Commands execute immediately, the semaphore always reads as ready
+            Huc3Mode::RtcSemaphore => 0xFF,
+            Huc3Mode::Ir => IR_NO_LIGHT,
+        }
+    }
+
+    fn write_ext_ram(&mut self, addr: u16, val: u8) {
+        match self.mode {
+            Huc3Mode::RamReadWrite => {
+                let addr = self.ram_addr(addr);
+                if let Some(ref mut ram) = self.ram {
+                    ram[addr] = val;
+                }
+            },
+            Huc3Mode::RtcCommand => self.rtc.command(val),
+            _ => {}
+        }
+    }
+
+    fn clock(&mut self, m_cycles: u32) {
+        self.rtc.clock.advance(4 * m_cycles as u64);
+        self.cycles_since_update += m_cycles;
+        if self.cycles_since_update >= UPDATE_INTERVAL {
+            self.cycles_since_update -= UPDATE_INTERVAL;
+            self.rtc.update_time();
+        }
+    }
+
+    fn set_clock_source(&mut self, clock: Box<dyn ClockSource>) {
+        self.rtc.last_update = clock.now();
+        self.rtc.clock = clock;
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank % self.n_rom_banks
+    }
+
+    fn has_battery(&self) -> bool {
+        true
+    }
+    fn has_rtc(&self) -> bool {
+        true
+    }
+    fn rtc_save_footer(&mut self) -> Option<Vec<u8>> {
+        Some(self.rtc.save_footer())
+    }
+    fn tone(&self) -> bool {
+        self.rtc.tone
+    }
+    fn has_ram(&self) -> bool {
+        self.ram.is_some()
+    }
+
+    fn ram_bank(&self) -> usize {
+        self.ram_bank
+    }
+
+    fn ram(&self) -> Option<&Vec<u8>> {
+        self.ram.as_ref()
+    }
+
+    fn ram_mut(&mut self) -> Option<&mut Vec<u8>> {
+        self.ram.as_mut()
+    }
+
+    fn rom(&self) -> &Vec<u8> {
+        &self.rom
+    }
+
+    fn rom_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.rom
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::cartridge::clock::{EmulatedClock, FixedClock};
+
+    fn huc3(sav: Option<Vec<u8>>, clock: Box<dyn ClockSource>) -> HuC3 {
+        let mut rom = vec![0; 0x8000];
+        rom[0x149] = 0x02;
+        let header = CartridgeHeader::new(&rom);
+        HuC3::new(rom, &header, sav, clock)
+    }
+
+    /// Writes `nibbles` to the RTC memory from `addr` on.
+    fn write_rtc(mbc: &mut HuC3, addr: u8, nibbles: &[u8]) {
+        mbc.write(0x0000, 0x0B);
+        mbc.write_ext_ram(0xA000, 0x40 | (addr & 0xF));
+        mbc.write_ext_ram(0xA000, 0x50 | (addr >> 4));
+        for nibble in nibbles {
+            mbc.write_ext_ram(0xA000, 0x30 | nibble);
+        }
+    }
+
+    /// Reads the current time, in minutes and days, through the command interface.
+    fn read_time(mbc: &mut HuC3) -> (u32, u32) {
+        mbc.write(0x0000, 0x0B);
+        mbc.write_ext_ram(0xA000, 0x60);
+        mbc.write_ext_ram(0xA000, 0x40);
+        mbc.write_ext_ram(0xA000, 0x50);
+        let nibbles: Vec<u32> = (0..6).map(|_| {
+            mbc.write(0x0000, 0x0B);
+            mbc.write_ext_ram(0xA000, 0x10);
+            mbc.write(0x0000, 0x0C);
+            mbc.read_ext_ram(0xA000) as u32 & 0xF
+        }).collect();
+        let value = |n: &[u32]| n.iter().rev().fold(0, |acc, &n| acc << 4 | n);
+        (value(&nibbles[..3]), value(&nibbles[3..]))
+    }
+
+    #[test]
+    fn alarm_starts_the_tone_as_time_passes() {
+        let clock = FixedClock::new(Duration::ZERO);
+        let mut mbc = huc3(None, Box::new(clock.clone()));
+        // Alarm at 5 minutes past midnight of day 0
+        write_rtc(&mut mbc, ALARM_MINUTES as u8, &[5, 0, 0, 0, 0, 0, 1]);
+
+        clock.advance_by(Duration::from_secs(4 * 60));
+        mbc.clock(UPDATE_INTERVAL);
+        assert!(!mbc.tone());
+        clock.advance_by(Duration::from_secs(60));
+        mbc.clock(UPDATE_INTERVAL);
+        assert!(mbc.tone());
+
+        // The game stops it, it doesn't go off again until the next time the alarm is reached
+        mbc.write(0x0000, 0x0B);
+        mbc.write_ext_ram(0xA000, 0x6E);
+        clock.advance_by(Duration::from_secs(60));
+        mbc.clock(UPDATE_INTERVAL);
+        assert!(!mbc.tone());
+        assert_eq!(read_time(&mut mbc), (6, 0));
+    }
+
+    #[test]
+    fn clock_is_saved_after_the_ram() {
+        let clock = FixedClock::new(Duration::ZERO);
+        let mut mbc = huc3(None, Box::new(clock.clone()));
+        // Set the time to day 2, 01:40
+        write_rtc(&mut mbc, TIME_MINUTES as u8, &[4, 6, 0, 2, 0, 0]);
+        mbc.write_ext_ram(0xA000, 0x61);
+        mbc.write(0x0000, 0x0A);
+        mbc.write_ext_ram(0xA123, 0x42);
+        clock.advance_by(Duration::from_secs(3 * 60));
+
+        let mut sav = mbc.ram().unwrap().clone();
+        sav.extend_from_slice(&mbc.rtc_save_footer().unwrap());
+        assert_eq!(sav.len(), 0x2000 + RTC_FOOTER_SIZE);
+
+        // Without the wall clock, the time the game was closed for doesn't count
+        let mut restored = huc3(Some(sav), Box::new(EmulatedClock::default()));
+        assert_eq!(restored.ram().unwrap().len(), 0x2000);
+        assert_eq!(restored.ram().unwrap()[0x123], 0x42);
+        assert_eq!(read_time(&mut restored), (103, 2));
+    }
+}
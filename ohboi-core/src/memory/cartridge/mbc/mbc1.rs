@@ -1,6 +1,6 @@
 // Copyright Antonio Porsia 2025. Licensed under the EUPL-1.2 or later.
 
-use log::warn;
+use log::{info, warn};
 use crate::memory::cartridge::CartridgeHeader;
 use super::{Mbc, BankingMode, RAM_BANK_SIZE, ROM_BANK_SIZE};
 
@@ -13,10 +13,29 @@ pub(crate) struct Mbc1 {
     rom_bank_hi: usize,
     rom_bank_lo: usize,
     n_rom_banks: usize,
+    /// MBC1M multicarts only wire the lower 4 bits of the ROM bank register,
+    /// so the upper bits select one of four 256 KiB games.
+    multicart: bool,
+}
+
+/// Multicarts are 1 MiB compilations of 256 KiB games, each with its own header.
+const MULTICART_SIZE: usize = 0x100000;
+const MULTICART_GAME_SIZE: usize = 0x40000;
+const LOGO: std::ops::Range<usize> = 0x104..0x134;
+
+/// Detects MBC1M multicarts by looking for the Nintendo logo of the games after the first one.
+fn is_multicart(rom: &[u8], cart_header: &CartridgeHeader) -> bool {
+    rom.len() == MULTICART_SIZE && (1..MULTICART_SIZE / MULTICART_GAME_SIZE)
+        .map(|game| game * MULTICART_GAME_SIZE)
+        .any(|base| rom[base + LOGO.start..base + LOGO.end] == cart_header.logo[..])
 }
 
 impl Mbc1 {
     pub fn new(rom: Vec<u8>, cart_header: &CartridgeHeader, sav: Option<Vec<u8>>, battery: bool) -> Self {
+        let multicart = is_multicart(&rom, cart_header);
+        if multicart {
+            info!("Detected MBC1M multicart");
+        }
         Self {
             rom,
             ram: match sav {
@@ -30,21 +49,19 @@ impl Mbc1 {
             rom_bank_hi: 0,
             rom_bank_lo: 1,
             n_rom_banks: cart_header.rom_size / ROM_BANK_SIZE,
+            multicart,
         }
     }
+
+    /// Returns how far the upper bank register is shifted, i.e. the number of wired lower bits.
+    fn bank_hi_shift(&self) -> usize {
+        if self.multicart { 4 } else { 5 }
+    }
 }
 
 impl Mbc for Mbc1 {
     fn read(&self, addr: u16) -> u8 {
-        let mut bank_number = if addr < 0x4000 { 0 } else { ((self.rom_bank_hi << 5) | self.rom_bank_lo) & 0x7F };
-        if bank_number == 0 && matches!(self.banking_mode, BankingMode::RAM) {
-            bank_number = self.rom_bank_hi << 5;
-        }
-        bank_number %= self.n_rom_banks;
-        let bank_offset = (addr as usize) % ROM_BANK_SIZE;
-        let effective_address = ROM_BANK_SIZE * bank_number + bank_offset;
-
-        self.rom[effective_address]
+        self.rom[self.rom_offset(addr)]
     }
 
     fn write(&mut self, addr: u16, val: u8) {
@@ -53,7 +70,7 @@ impl Mbc for Mbc1 {
             0..=0x1FFF => self.ram_enabled = val & 0xF == 0xA,
             0x2000..=0x3FFF => self.rom_bank_lo = if val & 0x1F == 0 { 1 } else { val & 0x1F },
             0x4000..=0x5FFF => self.rom_bank_hi = val & 0x3,
-            0x6000..=0x7FFF if val == 0 => self.banking_mode = BankingMode::RAM,
+            0x6000..=0x7FFF if val & 1 == 1 => self.banking_mode = BankingMode::RAM,
             0x6000..=0x7FFF => self.banking_mode = BankingMode::ROM,
             _ => panic!("Invalid write to cartridge at address {:04X}", addr)
         }
@@ -84,6 +101,22 @@ impl Mbc for Mbc1 {
         }
     }
 
+    fn rom_bank(&self) -> usize {
+        let shift = self.bank_hi_shift();
+        ((self.rom_bank_hi << shift) | (self.rom_bank_lo & ((1 << shift) - 1))) % self.n_rom_banks
+    }
+
+    fn rom_offset(&self, addr: u16) -> usize {
+        // In mode 1 the upper bank register also applies to 0x0000-0x3FFF
+        let bank_number = match addr {
+            0x0000..=0x3FFF if matches!(self.banking_mode, BankingMode::RAM) =>
+                (self.rom_bank_hi << self.bank_hi_shift()) % self.n_rom_banks,
+            0x0000..=0x3FFF => 0,
+            _ => self.rom_bank()
+        };
+        ROM_BANK_SIZE * bank_number + (addr as usize) % ROM_BANK_SIZE
+    }
+
     fn has_battery(&self) -> bool {
         self.battery
     }
@@ -91,11 +124,93 @@ impl Mbc for Mbc1 {
         matches!(self.ram, Some(_))
     }
 
+    fn ram_bank(&self) -> usize {
+        if matches!(self.banking_mode, BankingMode::RAM) { self.rom_bank_hi } else { 0 }
+    }
+
     fn ram(&self) -> Option<&Vec<u8>> {
         self.ram.as_ref()
     }
 
+    fn ram_mut(&mut self) -> Option<&mut Vec<u8>> {
+        self.ram.as_mut()
+    }
+
     fn rom(&self) -> &Vec<u8> {
         &self.rom
     }
-}
\ No newline at end of file
+
+    fn rom_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.rom
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 1 MiB ROM, each bank filled with its number, with the same logo in the header of the given 256 KiB games.
+    fn rom_with_logos(games: &[usize]) -> Vec<u8> {
+        let mut rom: Vec<u8> = (0..MULTICART_SIZE / ROM_BANK_SIZE).flat_map(|bank| vec![bank as u8; ROM_BANK_SIZE]).collect();
+        for game in games {
+            let base = game * MULTICART_GAME_SIZE;
+            for (i, byte) in rom[base + LOGO.start..base + LOGO.end].iter_mut().enumerate() {
+                *byte = 0xCE ^ i as u8;
+            }
+        }
+        rom[0x148] = 0x05;
+        rom
+    }
+
+    fn mbc1(rom: Vec<u8>) -> Mbc1 {
+        let header = CartridgeHeader::new(&rom);
+        Mbc1::new(rom, &header, None, false)
+    }
+
+    #[test]
+    fn multicarts_are_detected_by_the_logo_of_the_second_game() {
+        assert!(mbc1(rom_with_logos(&[0, 1])).multicart);
+        assert!(!mbc1(rom_with_logos(&[0])).multicart);
+        // Only 1 MiB ROMs can be multicarts
+        let mut rom = rom_with_logos(&[0, 1]);
+        rom.truncate(MULTICART_SIZE / 2);
+        rom[0x148] = 0x04;
+        assert!(!mbc1(rom).multicart);
+    }
+
+    #[test]
+    fn upper_bank_register_selects_the_multicart_game() {
+        let mut mbc = mbc1(rom_with_logos(&[0, 1, 2, 3]));
+        // Only 4 bits of the lower register are wired
+        mbc.write(0x2000, 0x12);
+        for (game, mode) in [(1, BankingMode::ROM), (2, BankingMode::ROM), (1, BankingMode::RAM), (2, BankingMode::RAM)] {
+            mbc.banking_mode = mode;
+            mbc.write(0x4000, game as u8);
+            let first_bank = game * MULTICART_GAME_SIZE / ROM_BANK_SIZE;
+            assert_eq!(mbc.read(0x4000) as usize, first_bank + 2);
+            // In mode 1 the game's first bank replaces bank 0
+            let low_bank = if matches!(mbc.banking_mode, BankingMode::RAM) { first_bank } else { 0 };
+            assert_eq!(mbc.read(0x0000) as usize, low_bank);
+        }
+    }
+
+    #[test]
+    fn regular_roms_use_5_bits_of_the_lower_register() {
+        let mut mbc = mbc1(rom_with_logos(&[0]));
+        mbc.write(0x2000, 0x12);
+        mbc.write(0x4000, 0x01);
+        assert_eq!(mbc.read(0x4000), 0x32);
+        mbc.banking_mode = BankingMode::RAM;
+        assert_eq!(mbc.read(0x0000), 0x20);
+    }
+
+    #[test]
+    fn bit_0_of_the_mode_register_selects_mode_1() {
+        let mut mbc = mbc1(rom_with_logos(&[0]));
+        mbc.write(0x4000, 0x01);
+        for (val, mode_1) in [(0x01, true), (0x00, false), (0x03, true), (0x02, false)] {
+            mbc.write(0x6000, val);
+            assert_eq!(matches!(mbc.banking_mode, BankingMode::RAM), mode_1);
+            assert_eq!(mbc.read(0x0000), if mode_1 { 0x20 } else { 0x00 });
+        }
+    }
+}
@@ -0,0 +1,150 @@
+// Copyright Antonio Porsia 2025. Licensed under the EUPL-1.2 or later.
+
+use log::{debug, warn};
+use crate::memory::cartridge::CartridgeHeader;
+use super::{Mbc, ROM_BANK_SIZE};
+
+/// The MBC2 has 512 half-bytes of RAM built in, the header reports no RAM.
+const RAM_SIZE: usize = 0x200;
+
+pub(crate) struct Mbc2 {
+    rom: Vec<u8>,
+    /// Only the lower nibble of each byte is used.
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    battery: bool,
+    rom_bank: usize,
+    n_rom_banks: usize,
+}
+
+impl Mbc2 {
+    pub fn new(rom: Vec<u8>, cart_header: &CartridgeHeader, sav: Option<Vec<u8>>, battery: bool) -> Self {
+        let mut ram = sav.unwrap_or_default();
+        if !ram.is_empty() && ram.len() != RAM_SIZE {
+            warn!("Unexpected MBC2 save size {:x}, expected {:x}", ram.len(), RAM_SIZE);
+        }
+        ram.resize(RAM_SIZE, 0);
+        Self {
+            rom,
+            ram,
+            ram_enabled: false,
+            battery,
+            rom_bank: 1,
+            n_rom_banks: cart_header.rom_size / ROM_BANK_SIZE,
+        }
+    }
+}
+
+impl Mbc for Mbc2 {
+    fn read(&self, addr: u16) -> u8 {
+        self.rom[self.rom_offset(addr)]
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        let val = val as usize;
+        match addr {
+            // Bit 8 of the address selects between the RAM enable and the ROM bank register
+            0..=0x3FFF if addr & 0x100 == 0 => self.ram_enabled = val & 0xF == 0xA,
+            0..=0x3FFF => self.rom_bank = if val & 0xF == 0 { 1 } else { val & 0xF },
+            _ => debug!("Invalid write to cartridge at address {:04X}", addr)
+        }
+    }
+
+    fn read_ext_ram(&self, addr: u16) -> u8 {
+        if self.ram_enabled {
+            // The 512 half-bytes are echoed across the whole 0xA000-0xBFFF range
+            self.peek_ext_ram(addr, 0)
+        } else {
+            0xFF
+        }
+    }
+
+    fn write_ext_ram(&mut self, addr: u16, val: u8) {
+        if self.ram_enabled {
+            self.poke_ext_ram(addr, 0, val);
+        }
+    }
+
+    fn peek_ext_ram(&self, addr: u16, _bank: usize) -> u8 {
+        self.ram[addr as usize & (RAM_SIZE - 1)] | 0xF0
+    }
+
+    fn poke_ext_ram(&mut self, addr: u16, _bank: usize, val: u8) {
+        self.ram[addr as usize & (RAM_SIZE - 1)] = val & 0x0F;
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank % self.n_rom_banks
+    }
+
+    fn has_battery(&self) -> bool {
+        self.battery
+    }
+    fn has_ram(&self) -> bool {
+        true
+    }
+
+    fn ram(&self) -> Option<&Vec<u8>> {
+        Some(&self.ram)
+    }
+
+    fn ram_mut(&mut self) -> Option<&mut Vec<u8>> {
+        Some(&mut self.ram)
+    }
+
+    fn rom(&self) -> &Vec<u8> {
+        &self.rom
+    }
+
+    fn rom_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.rom
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mbc2() -> Mbc2 {
+        // 4 ROM banks, each filled with its number
+        let mut rom: Vec<u8> = (0..4).flat_map(|bank| vec![bank as u8; ROM_BANK_SIZE]).collect();
+        rom[0x148] = 0x01;
+        let header = CartridgeHeader::new(&rom);
+        Mbc2::new(rom, &header, None, true)
+    }
+
+    #[test]
+    fn ram_is_512_nibbles_echoed_over_the_whole_range() {
+        let mut mbc = mbc2();
+        mbc.write_ext_ram(0xA000, 0x5A);
+        assert_eq!(mbc.read_ext_ram(0xA000), 0xFF);
+
+        mbc.write(0x0000, 0x0A);
+        mbc.write_ext_ram(0xA001, 0x5A);
+        assert_eq!(mbc.read_ext_ram(0xA001), 0xFA);
+        assert_eq!(mbc.read_ext_ram(0xA201), 0xFA);
+        assert_eq!(mbc.read_ext_ram(0xBE01), 0xFA);
+        assert_eq!(mbc.ram().unwrap()[1], 0x0A);
+
+        // The debugger sees the same mirrors, even with the RAM disabled
+        mbc.write(0x0000, 0x00);
+        assert_eq!(mbc.read_ext_ram(0xA001), 0xFF);
+        assert_eq!(mbc.peek_ext_ram(0xB401, 0), 0xFA);
+        mbc.poke_ext_ram(0xA203, 0, 0x37);
+        assert_eq!(mbc.ram().unwrap()[3], 0x07);
+    }
+
+    #[test]
+    fn address_bit_8_selects_the_rom_bank_register() {
+        let mut mbc = mbc2();
+        mbc.write(0x2100, 0x03);
+        assert_eq!(mbc.read(0x4000), 3);
+        mbc.write(0x2100, 0x00);
+        assert_eq!(mbc.read(0x4000), 1);
+        // Without bit 8 the write goes to the RAM enable register instead
+        mbc.write(0x2000, 0x0A);
+        assert_eq!(mbc.read(0x4000), 1);
+        mbc.write_ext_ram(0xA000, 0x01);
+        assert_eq!(mbc.read_ext_ram(0xA000), 0xF1);
+    }
+}
@@ -3,8 +3,9 @@
 use bitfield::bitfield;
 use log::warn;
 use crate::memory::cartridge::CartridgeHeader;
+use crate::memory::cartridge::clock::{ClockSource, SystemClock};
 use super::{Mbc, RAM_BANK_SIZE, ROM_BANK_SIZE};
-use std::time::SystemTime;
+use std::time::Duration;
 
 bitfield! {
     struct RtcDayHi(u8);
@@ -15,6 +16,11 @@ bitfield! {
     pub day_hi, set_hi: 0;
 }
 
+/// Size of the RTC footer with a 32-bit timestamp.
+const RTC_FOOTER_SIZE_32: usize = 44;
+/// Size of the RTC footer with a 64-bit timestamp.
+const RTC_FOOTER_SIZE_64: usize = 48;
+
 impl RtcDayHi {
     pub fn new() -> Self {
         Self(0)
@@ -31,11 +37,13 @@ struct Rtc {
     latched_hours: u8,
     latched_day: u8,
     latched_day_hi: RtcDayHi,
-    current_time: SystemTime
+    /// The time of the last whole second counted, as given by `clock`.
+    current_time: Duration,
+    clock: Box<dyn ClockSource>,
 }
 
 impl Rtc {
-    pub fn new() -> Self {
+    fn with_clock(clock: Box<dyn ClockSource>) -> Self {
         Self {
             seconds: 0,
             minutes: 0,
@@ -47,12 +55,23 @@ impl Rtc {
             latched_hours: 0,
             latched_day: 0,
             latched_day_hi: RtcDayHi::new(),
-            current_time: SystemTime::now(),
+            current_time: clock.now(),
+            clock,
         }
     }
 
-    pub fn read_saved_time_from_buf(&mut self, buf: &[u8], size: usize) {
-        if size < 40 {
+    /// Switches to another clock. The registers keep their values and count from the time of
+    /// the new clock on, so switching to a deterministic clock doesn't depend on the host time.
+    pub fn set_clock(&mut self, clock: Box<dyn ClockSource>) {
+        self.current_time = clock.now();
+        self.clock = clock;
+    }
+
+    /// Restores the clock from the footer other emulators (VBA-M, BGB, mGBA...) append to the `.sav`:
+    /// the current and latched registers as 32-bit little endian words, followed by the Unix time
+    /// of the save as a 32-bit (44-byte footer) or 64-bit (48-byte footer) little endian word.
+    pub fn read_saved_time_from_buf(&mut self, buf: &[u8]) {
+        if buf.len() < RTC_FOOTER_SIZE_32 {
             warn!("Buffer size is too small to read RTC data");
             return;
         }
@@ -68,15 +87,23 @@ impl Rtc {
         self.latched_day = buf[32];
         self.latched_day_hi.0 = buf[36];
 
-        if size > 40 {
-            self.current_time = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(u64::from_le_bytes(buf[40..48].try_into().unwrap()));
+        let timestamp = if buf.len() >= RTC_FOOTER_SIZE_64 {
+            u64::from_le_bytes(buf[40..48].try_into().unwrap())
+        } else {
+            u32::from_le_bytes(buf[40..44].try_into().unwrap()) as u64
+        };
+        if self.clock.is_wall_clock() {
+            // Count the time the game was closed for
+            self.current_time = Duration::from_secs(timestamp);
+            self.update_time();
         } else {
-            self.current_time = SystemTime::now();
+            self.current_time = self.clock.now();
         }
     }
 
+    /// Returns the 48-byte footer to append to the `.sav`, see `read_saved_time_from_buf`.
     pub fn rtc_buffer_for_sav(&mut self) -> Vec<u8> {
-        let mut buf = vec![0; 48];
+        let mut buf = vec![0; RTC_FOOTER_SIZE_64];
         self.update_time();
 
         buf[0] = self.seconds;
@@ -91,49 +118,68 @@ impl Rtc {
         buf[32] = self.latched_day;
         buf[36] = self.latched_day_hi.0;
 
-        let elapsed_secs = self.current_time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs();
-        buf[40..48].copy_from_slice(&elapsed_secs.to_le_bytes());
+        // Whatever the clock of the RTC, other emulators and the next session expect the Unix time
+        buf[40..48].copy_from_slice(&SystemClock.now().as_secs().to_le_bytes());
 
         buf
     }
 
+    /// Adds the whole seconds elapsed on the clock since the last update to the counters.
     pub fn update_time(&mut self) {
-        let now = SystemTime::now();
-        if self.day_hi.halt() {
+        let now = self.clock.now();
+        // Time doesn't count while halted, and a clock that went backwards just restarts from now
+        if self.day_hi.halt() || now < self.current_time {
+            self.current_time = now;
             return;
         }
-        let new_time = now.duration_since(self.current_time);
-        if let Ok(duration) = new_time {
-            let difference = duration.as_secs() as u32;
-            let new_secs = self.seconds as u32 + difference;
-            let new_mins = self.minutes as u32 + (new_secs / 60);
-            let new_hours = self.hours as u32 + (new_mins / 60);
-            let new_days = self.day as u32 + (new_hours / 24);
-            if new_secs == self.seconds as u32 {
-                return;
-            }
-            self.seconds = (new_secs % 60) as u8;
-            self.minutes = (new_mins % 60) as u8;
-            self.hours = (new_hours % 24) as u8;
-            let days = ((self.day_hi.day_hi() as u32) << 8) | (self.day as u32);
-            let new_days = days + (new_hours / 24);
-            self.day = (new_days % 256) as u8;
-            self.day_hi.0 &= 0xFE;
-            self.day_hi.set_hi((new_days >> 8) != 0);
-            if new_days > 511 {
-                self.day_hi.set_day_carry(true);
-            }
+        let difference = (now - self.current_time).as_secs();
+        if difference == 0 {
+            return;
+        }
+        // Keep the fraction of a second for the next update
+        self.current_time += Duration::from_secs(difference);
+
+        let new_secs = self.seconds as u64 + difference;
+        let new_mins = self.minutes as u64 + new_secs / 60;
+        let new_hours = self.hours as u64 + new_mins / 60;
+        self.seconds = (new_secs % 60) as u8;
+        self.minutes = (new_mins % 60) as u8;
+        self.hours = (new_hours % 24) as u8;
+        let days = ((self.day_hi.day_hi() as u64) << 8) | (self.day as u64);
+        let new_days = days + new_hours / 24;
+        self.day = new_days as u8;
+        self.day_hi.set_hi((new_days >> 8) & 1 != 0);
+        if new_days > 511 {
+            self.day_hi.set_day_carry(true);
         }
-        self.current_time = now;
     }
 
     pub fn latch_time(&mut self) {
+        self.update_time();
         self.latched_seconds = self.seconds;
         self.latched_minutes = self.minutes;
         self.latched_hours = self.hours;
         self.latched_day = self.day;
         self.latched_day_hi.0 = self.day_hi.0;
     }
+
+    /// Writes one of the RTC registers (0x08-0x0C).
+    pub fn write(&mut self, reg: usize, val: u8) {
+        // Count the time elapsed so far against the old values
+        self.update_time();
+        match reg {
+            0x8 => {
+                self.seconds = val;
+                // Writing the seconds resets the sub-second counter
+                self.current_time = self.clock.now();
+            },
+            0x9 => self.minutes = val,
+            0xA => self.hours = val,
+            0xB => self.day = val,
+            0xC => self.day_hi.0 = val,
+            _ => {}
+        }
+    }
 }
 
 pub(crate) struct Mbc3 {
@@ -146,39 +192,63 @@ pub(crate) struct Mbc3 {
     rom_bank: usize,
     n_rom_banks: usize,
     n_ram_banks: usize,
-    latch: bool
+    latch: bool,
+    /// The MBC30 has an 8-bit ROM bank register and 8 RAM banks.
+    mbc30: bool,
 }
 
 impl Mbc3 {
-    pub fn new(rom: Vec<u8>, cart_header: &CartridgeHeader, sav: Option<Vec<u8>>, battery: bool, rtc: bool) -> Self {
+    pub fn new(rom: Vec<u8>, cart_header: &CartridgeHeader, mut sav: Option<Vec<u8>>, battery: bool, rtc: bool,
+               clock: Box<dyn ClockSource>) -> Self {
+        let rtc = if rtc {
+            let mut rtc = Rtc::with_clock(clock);
+            // The clock is saved after the RAM, if at all
+            if let Some(ref mut sav) = sav {
+                let footer = sav.len().saturating_sub(cart_header.ram_size);
+                if matches!(footer, RTC_FOOTER_SIZE_32 | RTC_FOOTER_SIZE_64) {
+                    rtc.read_saved_time_from_buf(&sav[cart_header.ram_size..]);
+                    sav.truncate(cart_header.ram_size);
+                } else if footer != 0 {
+                    warn!("Ignoring {} unexpected bytes at the end of the save file", footer);
+                }
+            }
+            Some(rtc)
+        } else {
+            None
+        };
         Self {
             rom,
             ram: match sav {
-                Some(sav) => Some(sav),
-                None if cart_header.ram_size != 0 => Some(vec![0; cart_header.ram_size]),
+                Some(sav) if !sav.is_empty() => Some(sav),
+                _ if cart_header.ram_size != 0 => Some(vec![0; cart_header.ram_size]),
                 _ => None
             },
-            rtc: if rtc { Some(Rtc::new()) } else { None },
+            rtc,
             ram_rtc_enabled: false,
             ram_bank_rtc_reg: 0,
             battery,
             rom_bank: 1,
             n_rom_banks: cart_header.rom_size / ROM_BANK_SIZE,
             n_ram_banks: cart_header.ram_size / RAM_BANK_SIZE,
-            latch: false
+            latch: false,
+            // Only larger cartridges need the extra bits, they work the same on a plain MBC3 otherwise
+            mbc30: cart_header.rom_size > 128 * ROM_BANK_SIZE || cart_header.ram_size > 4 * RAM_BANK_SIZE,
         }
     }
+
+    /// Whether the RAM bank/RTC register selects a RAM bank rather than an RTC register.
+    fn ram_selected(&self) -> bool {
+        self.ram_bank_rtc_reg < if self.mbc30 { 8 } else { 4 }
+    }
+
+    fn ram_addr(&self, addr: u16) -> usize {
+        (self.ram_bank_rtc_reg % self.n_ram_banks.max(1)) * RAM_BANK_SIZE + (addr as usize - 0xA000)
+    }
 }
 
 impl Mbc for Mbc3 {
     fn read(&self, addr: u16) -> u8 {
-        let mut bank_number = if addr < 0x4000 { 0 } else { self.rom_bank };
-
-        bank_number %= self.n_rom_banks;
-        let bank_offset = (addr as usize) % ROM_BANK_SIZE;
-        let effective_address = ROM_BANK_SIZE * bank_number + bank_offset;
-
-        self.rom[effective_address]
+        self.rom[self.rom_offset(addr)]
     }
 
     fn write(&mut self, addr: u16, val: u8) {
@@ -186,7 +256,7 @@ impl Mbc for Mbc3 {
         match addr {
             0..=0x1FFF => self.ram_rtc_enabled = val & 0xF == 0xA,
             0x2000..=0x3FFF => {
-                self.rom_bank = val & 0x7F;
+                self.rom_bank = val & if self.mbc30 { 0xFF } else { 0x7F };
                 if self.rom_bank == 0 {
                     self.rom_bank = 1;
                 }
@@ -211,13 +281,9 @@ impl Mbc for Mbc3 {
         if !self.ram_rtc_enabled {
             return 0xFF
         }
-        if self.ram_bank_rtc_reg < 4 {
+        if self.ram_selected() {
             match self.ram {
-                Some(ref ram) => {
-                    let addr =
-                        self.ram_bank_rtc_reg * RAM_BANK_SIZE + (addr as usize - 0xA000);
-                    ram[addr]
-                },
+                Some(ref ram) => ram[self.ram_addr(addr)],
                 _ => 0xFF
             }
         } else {
@@ -241,32 +307,42 @@ impl Mbc for Mbc3 {
         if !self.ram_rtc_enabled {
             return
         }
-        if self.ram_bank_rtc_reg < 4 {
-            match self.ram {
-                Some(ref mut ram) => {
-                    let addr =
-                        self.ram_bank_rtc_reg * RAM_BANK_SIZE + (addr as usize - 0xA000);
-                    ram[addr] = val;
-                },
-                _ => {} // warn!("Tried writing to external RAM when cartridge has none")
+        if self.ram_selected() {
+            let addr = self.ram_addr(addr);
+            if let Some(ref mut ram) = self.ram {
+                ram[addr] = val;
             }
         } else {
-            match self.rtc {
-                Some(ref mut rtc) => {
-                    match self.ram_bank_rtc_reg {
-                        0x8 => rtc.seconds = val,
-                        0x9 => rtc.minutes = val,
-                        0xA => rtc.hours = val,
-                        0xB => rtc.day = val,
-                        0xC => rtc.day_hi.0 = val,
-                        _ => {}
-                    }
-                },
-                None => {}
+            if let Some(ref mut rtc) = self.rtc {
+                rtc.write(self.ram_bank_rtc_reg, val);
             }
         }
     }
 
+    fn clock(&mut self, m_cycles: u32) {
+        if let Some(ref mut rtc) = self.rtc {
+            rtc.clock.advance(4 * m_cycles as u64);
+        }
+    }
+
+    fn set_clock_source(&mut self, clock: Box<dyn ClockSource>) {
+        if let Some(ref mut rtc) = self.rtc {
+            rtc.set_clock(clock);
+        }
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank % self.n_rom_banks
+    }
+
+    fn rtc_save_footer(&mut self) -> Option<Vec<u8>> {
+        self.rtc.as_mut().map(Rtc::rtc_buffer_for_sav)
+    }
+
+    fn has_rtc(&self) -> bool {
+        self.rtc.is_some()
+    }
+
     fn has_battery(&self) -> bool {
         self.battery
     }
@@ -274,11 +350,161 @@ impl Mbc for Mbc3 {
         matches!(self.ram, Some(_))
     }
 
+    fn ram_bank(&self) -> usize {
+        if self.ram_selected() { self.ram_bank_rtc_reg } else { 0 }
+    }
+
     fn ram(&self) -> Option<&Vec<u8>> {
         self.ram.as_ref()
     }
 
+    fn ram_mut(&mut self) -> Option<&mut Vec<u8>> {
+        self.ram.as_mut()
+    }
+
     fn rom(&self) -> &Vec<u8> {
         &self.rom
     }
-}
\ No newline at end of file
+
+    fn rom_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.rom
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::cartridge::clock::{EmulatedClock, FixedClock, SystemClock};
+
+    fn rtc_at(seconds: u8, minutes: u8, hours: u8, days: u16) -> (Rtc, FixedClock) {
+        let clock = FixedClock::new(Duration::from_secs(1000));
+        let mut rtc = Rtc::with_clock(Box::new(clock.clone()));
+        rtc.write(0x8, seconds);
+        rtc.write(0x9, minutes);
+        rtc.write(0xA, hours);
+        rtc.write(0xB, days as u8);
+        rtc.write(0xC, (days >> 8) as u8 & 1);
+        (rtc, clock)
+    }
+
+    #[test]
+    fn rtc_footer_round_trips() {
+        let (mut rtc, clock) = rtc_at(30, 15, 10, 0x123);
+        clock.advance_by(Duration::from_secs(5));
+        let mut footer = rtc.rtc_buffer_for_sav();
+        assert_eq!(footer.len(), RTC_FOOTER_SIZE_64);
+        // Stamped with the Unix time of the save, not the time of the RTC clock
+        let stamp = u64::from_le_bytes(footer[40..48].try_into().unwrap());
+        assert!(stamp.abs_diff(SystemClock.now().as_secs()) <= 1);
+
+        // The 32-bit variant, reopened on the wall clock 90 seconds after the save
+        let saved = SystemClock.now().as_secs() - 90;
+        footer[40..44].copy_from_slice(&(saved as u32).to_le_bytes());
+        let mut restored = Rtc::with_clock(Box::new(SystemClock));
+        restored.read_saved_time_from_buf(&footer[..RTC_FOOTER_SIZE_32]);
+        restored.latch_time();
+        assert_eq!((restored.latched_minutes, restored.latched_hours), (17, 10));
+        // A second may tick over while the test runs
+        assert!((5..=6).contains(&restored.latched_seconds));
+        assert_eq!(restored.latched_day, 0x23);
+        assert!(restored.latched_day_hi.day_hi());
+
+        // Saved in deterministic mode 4 emulated seconds later, then reopened right away on the wall clock
+        let mut emulated = Rtc::with_clock(Box::new(EmulatedClock::default()));
+        emulated.read_saved_time_from_buf(&footer[..RTC_FOOTER_SIZE_32]);
+        emulated.clock.advance(4 * 4_194_304);
+        let footer = emulated.rtc_buffer_for_sav();
+        let mut restored = Rtc::with_clock(Box::new(SystemClock));
+        restored.read_saved_time_from_buf(&footer);
+        restored.latch_time();
+        assert_eq!((restored.latched_minutes, restored.latched_hours, restored.latched_day), (15, 10, 0x23));
+        assert!((39..=40).contains(&restored.latched_seconds));
+    }
+
+    #[test]
+    fn rtc_footer_loads_the_same_under_emulated_clock() {
+        let (mut rtc, _) = rtc_at(30, 15, 10, 0x123);
+        let footer = rtc.rtc_buffer_for_sav();
+        // The time elapsed since the save only counts on the wall clock
+        let load = || {
+            let mut rtc = Rtc::with_clock(Box::new(EmulatedClock::default()));
+            rtc.read_saved_time_from_buf(&footer);
+            rtc.latch_time();
+            (rtc.latched_seconds, rtc.latched_minutes, rtc.latched_hours, rtc.latched_day, rtc.latched_day_hi.0)
+        };
+        let first = load();
+        assert_eq!(first, (30, 15, 10, 0x23, 0x01));
+        assert_eq!(load(), first);
+    }
+
+    /// An MBC3 of the given size codes, without RTC, with each ROM bank filled with its number.
+    fn mbc3(rom_size: u8, ram_size: u8) -> Mbc3 {
+        let banks = 2 << rom_size;
+        let mut rom: Vec<u8> = (0..banks).flat_map(|bank| vec![bank as u8; ROM_BANK_SIZE]).collect();
+        rom[0x148] = rom_size;
+        rom[0x149] = ram_size;
+        let header = CartridgeHeader::new(&rom);
+        Mbc3::new(rom, &header, None, true, false, Box::new(FixedClock::default()))
+    }
+
+    #[test]
+    fn mbc30_has_256_rom_banks_and_8_ram_banks() {
+        let mut mbc = mbc3(0x07, 0x05);
+        mbc.write(0x2000, 0xFF);
+        assert_eq!(mbc.read(0x4000), 0xFF);
+        mbc.write(0x2000, 0x80);
+        assert_eq!(mbc.read(0x4000), 0x80);
+
+        mbc.write(0x0000, 0x0A);
+        for bank in 0..8 {
+            mbc.write(0x4000, bank);
+            mbc.write_ext_ram(0xA000, bank + 1);
+        }
+        for bank in 0..8 {
+            assert_eq!(mbc.ram().unwrap()[bank * RAM_BANK_SIZE], bank as u8 + 1);
+        }
+
+        // A plain MBC3 only has 7 bank bits and 4 RAM banks
+        let mut mbc = mbc3(0x06, 0x03);
+        mbc.write(0x2000, 0xFF);
+        assert_eq!(mbc.read(0x4000), 0x7F);
+        mbc.write(0x0000, 0x0A);
+        mbc.write(0x4000, 0x04);
+        assert_eq!(mbc.read_ext_ram(0xA000), 0xFF);
+    }
+
+    #[test]
+    fn rtc_rolls_over_into_next_day() {
+        let (mut rtc, clock) = rtc_at(59, 59, 23, 0x0FF);
+        clock.advance_by(Duration::from_millis(500));
+        rtc.latch_time();
+        assert_eq!(rtc.latched_seconds, 59);
+
+        clock.advance_by(Duration::from_millis(500));
+        rtc.latch_time();
+        assert_eq!((rtc.latched_seconds, rtc.latched_minutes, rtc.latched_hours), (0, 0, 0));
+        assert_eq!(rtc.latched_day, 0x00);
+        assert!(rtc.latched_day_hi.day_hi());
+        assert!(!rtc.latched_day_hi.day_carry());
+    }
+
+    #[test]
+    fn rtc_sets_day_carry_and_stops_while_halted() {
+        let (mut rtc, clock) = rtc_at(0, 0, 12, 511);
+        clock.advance_by(Duration::from_secs(12 * 60 * 60));
+        rtc.latch_time();
+        assert_eq!((rtc.latched_hours, rtc.latched_day), (0, 0));
+        assert!(!rtc.latched_day_hi.day_hi());
+        assert!(rtc.latched_day_hi.day_carry());
+
+        // Nothing counts while halted
+        rtc.write(0xC, 0x40);
+        clock.advance_by(Duration::from_secs(3600));
+        rtc.latch_time();
+        assert_eq!(rtc.latched_hours, 0);
+        rtc.write(0xC, 0x00);
+        clock.advance_by(Duration::from_secs(61));
+        rtc.latch_time();
+        assert_eq!((rtc.latched_minutes, rtc.latched_seconds), (1, 1));
+        assert!(!rtc.latched_day_hi.day_carry());
+    }
+}
@@ -13,11 +13,17 @@ pub(crate) struct Mbc5 {
     rom_bank_hi: usize,
     rom_bank_lo: usize,
     n_rom_banks: usize,
-    n_ram_banks: usize
+    n_ram_banks: usize,
+    /// Rumble cartridges use bit 3 of the RAM bank register to drive the motor.
+    has_rumble: bool,
+    rumble: bool,
+    /// M-cycles the motor was on, and in total, since the rumble strength was last taken.
+    rumble_on_cycles: u32,
+    rumble_cycles: u32,
 }
 
 impl Mbc5 {
-    pub fn new(rom: Vec<u8>, cart_header: &CartridgeHeader, sav: Option<Vec<u8>>, battery: bool) -> Self {
+    pub fn new(rom: Vec<u8>, cart_header: &CartridgeHeader, sav: Option<Vec<u8>>, battery: bool, rumble: bool) -> Self {
         Self {
             rom,
             ram: match sav {
@@ -31,20 +37,18 @@ impl Mbc5 {
             rom_bank_hi: 0,
             rom_bank_lo: 1,
             n_rom_banks: cart_header.rom_size / ROM_BANK_SIZE,
-            n_ram_banks: cart_header.ram_size / RAM_BANK_SIZE
+            n_ram_banks: cart_header.ram_size / RAM_BANK_SIZE,
+            has_rumble: rumble,
+            rumble: false,
+            rumble_on_cycles: 0,
+            rumble_cycles: 0,
         }
     }
 }
 
 impl Mbc for Mbc5 {
     fn read(&self, addr: u16) -> u8 {
-        let mut bank_number = if addr < 0x4000 { 0 } else { (self.rom_bank_hi << 8) | self.rom_bank_lo };
-
-        bank_number %= self.n_rom_banks;
-        let bank_offset = (addr as usize) % ROM_BANK_SIZE;
-        let effective_address = ROM_BANK_SIZE * bank_number + bank_offset;
-
-        self.rom[effective_address]
+        self.rom[self.rom_offset(addr)]
     }
 
     fn write(&mut self, addr: u16, val: u8) {
@@ -53,6 +57,10 @@ impl Mbc for Mbc5 {
             0..=0x1FFF => self.ram_enabled = val & 0xF == 0xA,
             0x2000..=0x2FFF => self.rom_bank_lo = val,
             0x3000..=0x3FFF => self.rom_bank_hi = val & 0x1,
+            0x4000..=0x5FFF if self.has_rumble => {
+                self.rumble = val & 0x8 != 0;
+                self.ram_bank = val & 0x7;
+            },
             0x4000..=0x5FFF => self.ram_bank = val & 0xF,
             _ => debug!("Invalid write to cartridge at address {:04X}", addr)
         }
@@ -80,6 +88,30 @@ impl Mbc for Mbc5 {
         }
     }
 
+    fn rom_bank(&self) -> usize {
+        ((self.rom_bank_hi << 8) | self.rom_bank_lo) % self.n_rom_banks
+    }
+
+    fn clock(&mut self, m_cycles: u32) {
+        if self.has_rumble {
+            self.rumble_cycles = self.rumble_cycles.saturating_add(m_cycles);
+            if self.rumble {
+                self.rumble_on_cycles = self.rumble_on_cycles.saturating_add(m_cycles);
+            }
+        }
+    }
+
+    fn take_rumble(&mut self) -> f32 {
+        let strength = if self.rumble_cycles == 0 {
+            if self.rumble { 1.0 } else { 0.0 }
+        } else {
+            self.rumble_on_cycles as f32 / self.rumble_cycles as f32
+        };
+        self.rumble_on_cycles = 0;
+        self.rumble_cycles = 0;
+        strength
+    }
+
     fn has_battery(&self) -> bool {
         self.battery
     }
@@ -87,11 +119,47 @@ impl Mbc for Mbc5 {
         matches!(self.ram, Some(_))
     }
 
+    fn ram_bank(&self) -> usize {
+        self.ram_bank
+    }
+
     fn ram(&self) -> Option<&Vec<u8>> {
         self.ram.as_ref()
     }
 
+    fn ram_mut(&mut self) -> Option<&mut Vec<u8>> {
+        self.ram.as_mut()
+    }
+
     fn rom(&self) -> &Vec<u8> {
         &self.rom
     }
-}
\ No newline at end of file
+
+    fn rom_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.rom
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rumble_strength_follows_the_pulse_width() {
+        let header = CartridgeHeader::new(&vec![0; 0x8000]);
+        let mut mbc = Mbc5::new(vec![0; 0x8000], &header, None, false, true);
+        assert_eq!(mbc.take_rumble(), 0.0);
+
+        // On for a quarter of the time
+        for _ in 0..10 {
+            mbc.write(0x4000, 0x08);
+            mbc.clock(100);
+            mbc.write(0x4000, 0x00);
+            mbc.clock(300);
+        }
+        assert_eq!(mbc.take_rumble(), 0.25);
+
+        mbc.write(0x4000, 0x08);
+        mbc.clock(100);
+        assert_eq!(mbc.take_rumble(), 1.0);
+    }
+}
@@ -0,0 +1,400 @@
+// Copyright Antonio Porsia 2025. Licensed under the EUPL-1.2 or later.
+
+use log::{debug, warn};
+use crate::memory::cartridge::{AccelerometerInput, CartridgeHeader};
+use super::{Mbc, ROM_BANK_SIZE};
+
+/// The 93LC56 holds 128 16-bit words.
+const EEPROM_WORDS: usize = 128;
+/// Accelerometer reading when the cartridge is level.
+const ACCEL_CENTER: f32 = 0x81D0 as f32;
+/// Change of the accelerometer reading per g.
+const ACCEL_PER_G: f32 = 0x70 as f32;
+/// Reading after the latch has been erased and before the next one.
+const ACCEL_ERASED: u16 = 0x8000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EepromState {
+    /// Waiting for a start bit.
+    Idle,
+    /// Shifting in the 2-bit opcode and the 8-bit address.
+    Command,
+    /// Shifting out a word, MSB first.
+    Read,
+    /// Shifting in a word for `addr`, or for every address if `addr` is `None`.
+    Write { addr: Option<usize> },
+}
+
+/// The 93LC56 serial EEPROM, in 16-bit organization, driven through register 0xA080:
+/// bit 7 is chip select, bit 6 the clock, bit 1 data in and bit 0 data out.
+struct Eeprom {
+    /// Words stored little endian, as in the save file.
+    data: Vec<u8>,
+    state: EepromState,
+    write_enabled: bool,
+    cs: bool,
+    clk: bool,
+    di: bool,
+    /// Data out, high when ready.
+    dout: bool,
+    shift: u16,
+    count: usize,
+}
+
+impl Eeprom {
+    fn new(data: Vec<u8>) -> Self {
+        Self {
+            data,
+            state: EepromState::Idle,
+            write_enabled: false,
+            cs: false,
+            clk: false,
+            di: false,
+            dout: true,
+            shift: 0,
+            count: 0,
+        }
+    }
+
+    fn word(&self, addr: usize) -> u16 {
+        u16::from_le_bytes([self.data[addr * 2], self.data[addr * 2 + 1]])
+    }
+
+    fn set_word(&mut self, addr: usize, val: u16) {
+        if self.write_enabled {
+            self.data[addr * 2..addr * 2 + 2].copy_from_slice(&val.to_le_bytes());
+        }
+    }
+
+    fn read(&self) -> u8 {
+        (self.cs as u8) << 7 | (self.clk as u8) << 6 | (self.di as u8) << 1 | self.dout as u8
+    }
+
+    fn write(&mut self, val: u8) {
+        let rising_edge = !self.clk && val & 0x40 != 0;
+        self.cs = val & 0x80 != 0;
+        self.clk = val & 0x40 != 0;
+        self.di = val & 0x02 != 0;
+        if !self.cs {
+            self.state = EepromState::Idle;
+            self.dout = true;
+            return;
+        }
+        if rising_edge {
+            self.clock();
+        }
+    }
+
+    /// Handles a rising edge of the clock while the chip is selected.
+    fn clock(&mut self) {
+        match self.state {
+            EepromState::Idle if self.di => {
+                self.state = EepromState::Command;
+                self.shift = 0;
+                self.count = 0;
+            },
+            EepromState::Idle => {},
+            EepromState::Command => {
+                self.shift_in();
+                if self.count == 10 {
+                    self.execute((self.shift >> 8) & 0x3, (self.shift & 0xFF) as usize);
+                }
+            },
+            EepromState::Read => {
+                self.dout = self.shift & 0x8000 != 0;
+                self.shift <<= 1;
+                self.count += 1;
+                if self.count == 16 {
+                    self.state = EepromState::Idle;
+                }
+            },
+            EepromState::Write { addr } => {
+                self.shift_in();
+                if self.count == 16 {
+                    match addr {
+                        Some(addr) => self.set_word(addr, self.shift),
+                        None => (0..EEPROM_WORDS).for_each(|addr| self.set_word(addr, self.shift)),
+                    }
+                    self.state = EepromState::Idle;
+                    self.dout = true;
+                }
+            }
+        }
+    }
+
+    fn shift_in(&mut self) {
+        self.shift = (self.shift << 1) | self.di as u16;
+        self.count += 1;
+    }
+
+    fn execute(&mut self, opcode: u16, addr: usize) {
+        self.shift = 0;
+        self.count = 0;
+        self.state = EepromState::Idle;
+        let word = addr & (EEPROM_WORDS - 1);
+        match (opcode, addr >> 6) {
+            // READ, data out starts with a dummy 0
+            (0b10, _) => {
+                self.shift = self.word(word);
+                self.dout = false;
+                self.state = EepromState::Read;
+            },
+            // WRITE
+            (0b01, _) => self.state = EepromState::Write { addr: Some(word) },
+            // ERASE
+            (0b11, _) => self.set_word(word, 0xFFFF),
+            // EWDS
+            (0b00, 0b00) => self.write_enabled = false,
+            // WRAL
+            (0b00, 0b01) => self.state = EepromState::Write { addr: None },
+            // ERAL
+            (0b00, 0b10) => (0..EEPROM_WORDS).for_each(|addr| self.set_word(addr, 0xFFFF)),
+            // EWEN
+            _ => self.write_enabled = true,
+        }
+    }
+}
+
+pub(crate) struct Mbc7 {
+    rom: Vec<u8>,
+    eeprom: Eeprom,
+    ram_enabled_1: bool,
+    ram_enabled_2: bool,
+    rom_bank: usize,
+    n_rom_banks: usize,
+    accelerometer: AccelerometerInput,
+    latched: (u16, u16),
+    latch_erased: bool,
+}
+
+impl Mbc7 {
+    pub fn new(rom: Vec<u8>, cart_header: &CartridgeHeader, sav: Option<Vec<u8>>) -> Self {
+        let mut data = sav.unwrap_or_else(|| vec![0xFF; EEPROM_WORDS * 2]);
+        if data.len() != EEPROM_WORDS * 2 {
+            warn!("Unexpected MBC7 save size {:x}, expected {:x}", data.len(), EEPROM_WORDS * 2);
+            data.resize(EEPROM_WORDS * 2, 0xFF);
+        }
+        Self {
+            rom,
+            eeprom: Eeprom::new(data),
+            ram_enabled_1: false,
+            ram_enabled_2: false,
+            rom_bank: 1,
+            n_rom_banks: cart_header.rom_size / ROM_BANK_SIZE,
+            accelerometer: AccelerometerInput::default(),
+            latched: (ACCEL_ERASED, ACCEL_ERASED),
+            latch_erased: false,
+        }
+    }
+
+    fn ram_enabled(&self) -> bool {
+        self.ram_enabled_1 && self.ram_enabled_2
+    }
+
+    fn accelerometer_value(g: f32) -> u16 {
+        (ACCEL_CENTER + g * ACCEL_PER_G).clamp(0.0, u16::MAX as f32) as u16
+    }
+}
+
+impl Mbc for Mbc7 {
+    fn read(&self, addr: u16) -> u8 {
+        self.rom[self.rom_offset(addr)]
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0..=0x1FFF => self.ram_enabled_1 = val & 0xF == 0xA,
+            0x2000..=0x3FFF => self.rom_bank = val as usize,
+            0x4000..=0x5FFF => self.ram_enabled_2 = val == 0x40,
+            _ => debug!("Invalid write to cartridge at address {:04X}", addr)
+        }
+    }
+
+    fn read_ext_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled() || addr >= 0xB000 {
+            return 0xFF;
+        }
+        // Registers are selected by bits 4-7 of the address
+        match (addr >> 4) & 0xF {
+            0x2 => self.latched.0 as u8,
+            0x3 => (self.latched.0 >> 8) as u8,
+            0x4 => self.latched.1 as u8,
+            0x5 => (self.latched.1 >> 8) as u8,
+            0x6 => 0x00,
+            0x8 => self.eeprom.read(),
+            _ => 0xFF
+        }
+    }
+
+    fn write_ext_ram(&mut self, addr: u16, val: u8) {
+        if !self.ram_enabled() || addr >= 0xB000 {
+            return;
+        }
+        match (addr >> 4) & 0xF {
+            0x0 if val == 0x55 => {
+                self.latch_erased = true;
+                self.latched = (ACCEL_ERASED, ACCEL_ERASED);
+            },
+            0x1 if val == 0xAA && self.latch_erased => {
+                self.latch_erased = false;
+                // Tilting right and towards the player increase the readings
+                self.latched = (Self::accelerometer_value(self.accelerometer.x),
+                                Self::accelerometer_value(self.accelerometer.y));
+            },
+            0x8 => self.eeprom.write(val),
+            _ => {}
+        }
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_bank % self.n_rom_banks
+    }
+
+    fn set_accelerometer(&mut self, input: AccelerometerInput) {
+        self.accelerometer = input;
+    }
+
+    fn has_battery(&self) -> bool {
+        true
+    }
+    fn has_ram(&self) -> bool {
+        true
+    }
+
+    fn ram(&self) -> Option<&Vec<u8>> {
+        Some(&self.eeprom.data)
+    }
+
+    fn ram_mut(&mut self) -> Option<&mut Vec<u8>> {
+        Some(&mut self.eeprom.data)
+    }
+
+    fn rom(&self) -> &Vec<u8> {
+        &self.rom
+    }
+
+    fn rom_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.rom
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The 2-bit opcode and the 8-bit address of each command, the address is ORed in where needed
+    const READ: u32 = 0b10_0000_0000;
+    const WRITE: u32 = 0b01_0000_0000;
+    const ERASE: u32 = 0b11_0000_0000;
+    const EWDS: u32 = 0b00_0000_0000;
+    const WRAL: u32 = 0b00_0100_0000;
+    const ERAL: u32 = 0b00_1000_0000;
+    const EWEN: u32 = 0b00_1100_0000;
+
+    fn mbc7() -> Mbc7 {
+        let rom = vec![0; 0x8000];
+        let header = CartridgeHeader::new(&rom);
+        let mut mbc = Mbc7::new(rom, &header, None);
+        mbc.write(0x0000, 0x0A);
+        mbc.write(0x4000, 0x40);
+        mbc
+    }
+
+    /// Clocks `count` bits into the EEPROM, MSB first, and returns the bits on data out after each rising edge.
+    fn transfer(mbc: &mut Mbc7, bits: u32, count: usize) -> u32 {
+        let mut out = 0;
+        for i in (0..count).rev() {
+            let di = ((bits >> i) & 1) as u8 * 0x02;
+            mbc.write_ext_ram(0xA080, 0x80 | di);
+            mbc.write_ext_ram(0xA080, 0xC0 | di);
+            out = (out << 1) | (mbc.read_ext_ram(0xA080) & 1) as u32;
+        }
+        out
+    }
+
+    /// Sends a start bit and a command with chip select freshly raised.
+    fn command(mbc: &mut Mbc7, command: u32) {
+        mbc.write_ext_ram(0xA080, 0x00);
+        transfer(mbc, (1 << 10) | command, 11);
+    }
+
+    fn read_word(mbc: &mut Mbc7, addr: u32) -> u16 {
+        command(mbc, READ | addr);
+        assert_eq!(mbc.read_ext_ram(0xA080) & 1, 0, "READ starts with a dummy 0");
+        transfer(mbc, 0, 16) as u16
+    }
+
+    fn write_word(mbc: &mut Mbc7, addr: u32, val: u16) {
+        command(mbc, WRITE | addr);
+        transfer(mbc, val as u32, 16);
+    }
+
+    #[test]
+    fn eeprom_writes_only_between_ewen_and_ewds() {
+        let mut mbc = mbc7();
+        write_word(&mut mbc, 5, 0x1234);
+        assert_eq!(read_word(&mut mbc, 5), 0xFFFF);
+
+        command(&mut mbc, EWEN);
+        write_word(&mut mbc, 5, 0x1234);
+        assert_eq!(read_word(&mut mbc, 5), 0x1234);
+        assert_eq!(mbc.ram().unwrap()[10..12], [0x34, 0x12]);
+
+        command(&mut mbc, EWDS);
+        command(&mut mbc, ERASE | 5);
+        assert_eq!(read_word(&mut mbc, 5), 0x1234);
+        command(&mut mbc, EWEN);
+        command(&mut mbc, ERASE | 5);
+        assert_eq!(read_word(&mut mbc, 5), 0xFFFF);
+    }
+
+    #[test]
+    fn eeprom_wral_and_eral_cover_every_word() {
+        let mut mbc = mbc7();
+        command(&mut mbc, EWEN);
+        command(&mut mbc, WRAL);
+        transfer(&mut mbc, 0xBEEF, 16);
+        assert_eq!(read_word(&mut mbc, 0), 0xBEEF);
+        assert_eq!(read_word(&mut mbc, 127), 0xBEEF);
+
+        command(&mut mbc, ERAL);
+        assert!(mbc.ram().unwrap().iter().all(|&b| b == 0xFF));
+    }
+
+    #[test]
+    fn accelerometer_is_latched_by_the_55_aa_sequence() {
+        let mut mbc = mbc7();
+        let read_axes = |mbc: &Mbc7| (0..4).map(|i| mbc.read_ext_ram(0xA020 + i * 0x10)).collect::<Vec<_>>();
+        mbc.set_accelerometer(AccelerometerInput { x: 1.0, y: -0.5 });
+        assert_eq!(read_axes(&mbc), [0x00, 0x80, 0x00, 0x80]);
+
+        // 0xAA alone doesn't latch
+        mbc.write_ext_ram(0xA010, 0xAA);
+        assert_eq!(read_axes(&mbc), [0x00, 0x80, 0x00, 0x80]);
+
+        mbc.write_ext_ram(0xA000, 0x55);
+        mbc.write_ext_ram(0xA010, 0xAA);
+        assert_eq!(read_axes(&mbc), [0x40, 0x82, 0x98, 0x81]);
+
+        // The latched values stay until the next sequence
+        mbc.set_accelerometer(AccelerometerInput::default());
+        assert_eq!(read_axes(&mbc), [0x40, 0x82, 0x98, 0x81]);
+        mbc.write_ext_ram(0xA000, 0x55);
+        assert_eq!(read_axes(&mbc), [0x00, 0x80, 0x00, 0x80]);
+        mbc.write_ext_ram(0xA010, 0xAA);
+        assert_eq!(read_axes(&mbc), [0xD0, 0x81, 0xD0, 0x81]);
+    }
+
+    #[test]
+    fn registers_need_both_ram_enables() {
+        let rom = vec![0; 0x8000];
+        let header = CartridgeHeader::new(&rom);
+        let mut mbc = Mbc7::new(rom, &header, None);
+        mbc.write(0x0000, 0x0A);
+        assert_eq!(mbc.read_ext_ram(0xA080), 0xFF);
+        mbc.write(0x4000, 0x40);
+        assert_eq!(mbc.read_ext_ram(0xA080), 0x01);
+        mbc.write(0x0000, 0x00);
+        assert_eq!(mbc.read_ext_ram(0xA080), 0xFF);
+    }
+}
@@ -0,0 +1,254 @@
+// Copyright Antonio Porsia 2025. Licensed under the EUPL-1.2 or later.
+
+use log::{debug, info};
+use crate::memory::cartridge::CartridgeHeader;
+use super::{BankingMode, Mbc, RAM_BANK_SIZE, ROM_BANK_SIZE};
+
+/// The MMM01 multi-game mapper.
+///
+/// It starts unmapped, with the menu in the last 32 KiB of the ROM at 0x0000-0x7FFF.
+/// While unmapped, the menu writes the outer bank bits and the bank mask of the chosen game,
+/// then sets bit 6 of 0x0000-0x1FFF to map it. From then on the mapper behaves like an MBC1
+/// confined to that game, until the next reset.
+pub(crate) struct Mmm01 {
+    rom: Vec<u8>,
+    ram: Option<Vec<u8>>,
+    ram_enabled: bool,
+    battery: bool,
+    mapped: bool,
+    /// Bits 0-4 of the ROM bank number.
+    rom_bank_lo: usize,
+    /// Bits 5-6 of the ROM bank number, only writable while unmapped.
+    rom_bank_mid: usize,
+    /// Bits 7-8 of the ROM bank number, only writable while unmapped.
+    rom_bank_hi: usize,
+    /// Bits 1-4 of the ROM bank number that are fixed once mapped.
+    rom_bank_mask: usize,
+    ram_bank_lo: usize,
+    /// Bits 2-3 of the RAM bank number, only writable while unmapped.
+    ram_bank_hi: usize,
+    banking_mode: BankingMode,
+    /// Prevents the game from changing the banking mode once mapped.
+    mode_locked: bool,
+    n_rom_banks: usize,
+    n_ram_banks: usize,
+}
+
+impl Mmm01 {
+    pub fn new(rom: Vec<u8>, cart_header: &CartridgeHeader, sav: Option<Vec<u8>>, battery: bool) -> Self {
+        Self {
+            // The header is read from the menu, it covers the whole ROM
+            n_rom_banks: (rom.len() / ROM_BANK_SIZE).max(2),
+            rom,
+            ram: match sav {
+                Some(sav) => Some(sav),
+                None if cart_header.ram_size != 0 => Some(vec![0; cart_header.ram_size]),
+                _ => None
+            },
+            ram_enabled: false,
+            battery,
+            mapped: false,
+            rom_bank_lo: 0,
+            rom_bank_mid: 0,
+            rom_bank_hi: 0,
+            rom_bank_mask: 0,
+            ram_bank_lo: 0,
+            ram_bank_hi: 0,
+            banking_mode: BankingMode::ROM,
+            mode_locked: false,
+            n_ram_banks: cart_header.ram_size / RAM_BANK_SIZE,
+        }
+    }
+
+    /// The bits of the low ROM bank register that can still be written.
+    fn writable_rom_bits(&self) -> usize {
+        if self.mapped { 0x1F & !(self.rom_bank_mask << 1) } else { 0x1F }
+    }
+
+    fn outer_rom_bank(&self) -> usize {
+        self.rom_bank_hi << 7 | self.rom_bank_mid << 5
+    }
+
+    fn ram_addr(&self, addr: u16) -> usize {
+        let bank = match self.banking_mode {
+            BankingMode::ROM => self.ram_bank_hi << 2,
+            BankingMode::RAM => self.ram_bank_hi << 2 | self.ram_bank_lo,
+        };
+        (bank % self.n_ram_banks.max(1)) * RAM_BANK_SIZE + (addr as usize - 0xA000)
+    }
+}
+
+impl Mbc for Mmm01 {
+    fn read(&self, addr: u16) -> u8 {
+        self.rom[self.rom_offset(addr)]
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        let val = val as usize;
+        match addr {
+            0..=0x1FFF => {
+                self.ram_enabled = val & 0xF == 0xA;
+                if !self.mapped && val & 0x40 != 0 {
+                    info!("MMM01 mapped game at ROM bank {:x}", self.outer_rom_bank() | self.rom_bank_lo);
+                    self.mapped = true;
+                }
+            },
+            0x2000..=0x3FFF => {
+                if !self.mapped {
+                    self.rom_bank_mid = (val >> 5) & 0x3;
+                }
+                let writable = self.writable_rom_bits();
+                self.rom_bank_lo = (self.rom_bank_lo & !writable) | (val & writable);
+            },
+            0x4000..=0x5FFF => {
+                self.ram_bank_lo = val & 0x3;
+                if !self.mapped {
+                    self.ram_bank_hi = (val >> 2) & 0x3;
+                    self.rom_bank_hi = (val >> 4) & 0x3;
+                    self.mode_locked = val & 0x40 != 0;
+                }
+            },
+            0x6000..=0x7FFF => {
+                if !(self.mapped && self.mode_locked) {
+                    self.banking_mode = if val & 1 == 1 { BankingMode::RAM } else { BankingMode::ROM };
+                }
+                if !self.mapped {
+                    self.rom_bank_mask = (val >> 2) & 0xF;
+                }
+            },
+            _ => debug!("Invalid write to cartridge at address {:04X}", addr)
+        }
+    }
+
+    fn read_ext_ram(&self, addr: u16) -> u8 {
+        match self.ram {
+            Some(ref ram) if self.ram_enabled => ram[self.ram_addr(addr)],
+            _ => 0xFF
+        }
+    }
+
+    fn write_ext_ram(&mut self, addr: u16, val: u8) {
+        if !self.ram_enabled {
+            return;
+        }
+        let addr = self.ram_addr(addr);
+        if let Some(ref mut ram) = self.ram {
+            ram[addr] = val;
+        }
+    }
+
+    fn num_banks(&self) -> usize {
+        self.n_rom_banks
+    }
+
+    fn num_ram_banks(&self) -> usize {
+        self.n_ram_banks
+    }
+
+    fn rom_bank(&self) -> usize {
+        if !self.mapped {
+            return self.n_rom_banks - 1;
+        }
+        // Only the bits the game can write are checked for zero, as on the MBC1
+        let writable = self.writable_rom_bits();
+        let lo = if self.rom_bank_lo & writable == 0 { self.rom_bank_lo | 1 } else { self.rom_bank_lo };
+        (self.outer_rom_bank() | lo) % self.n_rom_banks
+    }
+
+    fn rom_offset(&self, addr: u16) -> usize {
+        let bank = match addr {
+            0..=0x3FFF if self.mapped => (self.outer_rom_bank() | (self.rom_bank_lo & !self.writable_rom_bits())) % self.n_rom_banks,
+            // The menu lives in the last 32 KiB
+            0..=0x3FFF => self.n_rom_banks - 2,
+            _ => self.rom_bank(),
+        };
+        ROM_BANK_SIZE * bank + (addr as usize) % ROM_BANK_SIZE
+    }
+
+    fn has_battery(&self) -> bool {
+        self.battery
+    }
+    fn has_ram(&self) -> bool {
+        self.ram.is_some()
+    }
+
+    fn ram_bank(&self) -> usize {
+        self.ram_addr(0xA000) / RAM_BANK_SIZE
+    }
+
+    fn ram(&self) -> Option<&Vec<u8>> {
+        self.ram.as_ref()
+    }
+
+    fn ram_mut(&mut self) -> Option<&mut Vec<u8>> {
+        self.ram.as_mut()
+    }
+
+    fn rom(&self) -> &Vec<u8> {
+        &self.rom
+    }
+
+    fn rom_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.rom
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A cartridge of the given size codes, with each ROM bank filled with its number.
+    fn mmm01(rom_size: u8, ram_size: u8) -> Mmm01 {
+        let banks = 2 << rom_size;
+        let mut rom: Vec<u8> = (0..banks).flat_map(|bank| vec![bank as u8; ROM_BANK_SIZE]).collect();
+        rom[0x148] = rom_size;
+        rom[0x149] = ram_size;
+        let header = CartridgeHeader::new(&rom);
+        Mmm01::new(rom, &header, None, true)
+    }
+
+    #[test]
+    fn menu_maps_a_game_and_locks_its_banks() {
+        let mut mbc = mmm01(0x04, 0x00);
+        // The menu, in the last 32 KiB
+        assert_eq!((mbc.read(0x0000), mbc.read(0x4000)), (30, 31));
+
+        // A 128 KiB game at bank 16: bits 3-4 of the bank number are fixed once mapped
+        mbc.write(0x2000, 0x10);
+        mbc.write(0x6000, 0xC << 2);
+        mbc.write(0x0000, 0x40);
+        assert_eq!((mbc.read(0x0000), mbc.read(0x4000)), (16, 17));
+        mbc.write(0x2000, 0x03);
+        assert_eq!(mbc.read(0x4000), 19);
+        mbc.write(0x2000, 0x1F);
+        assert_eq!(mbc.read(0x4000), 23);
+        mbc.write(0x2000, 0x08);
+        assert_eq!(mbc.read(0x4000), 17);
+
+        // The game can't change the mask or the outer bank, nor go back to the menu
+        mbc.write(0x6000, 0x00);
+        mbc.write(0x4000, 0x30);
+        mbc.write(0x0000, 0x00);
+        mbc.write(0x2000, 0x1F);
+        assert_eq!((mbc.read(0x0000), mbc.read(0x4000)), (16, 23));
+    }
+
+    #[test]
+    fn mapped_game_sees_its_outer_rom_and_ram_banks() {
+        let mut mbc = mmm01(0x05, 0x04);
+        // A 64 KiB game at bank 32, with RAM banks 4-7 and the banking mode locked to RAM banking
+        mbc.write(0x2000, 0x20);
+        mbc.write(0x4000, 0x44);
+        mbc.write(0x6000, 0xE << 2 | 1);
+        mbc.write(0x0000, 0x4A);
+        assert_eq!((mbc.read(0x0000), mbc.read(0x4000)), (32, 33));
+        mbc.write(0x2000, 0x07);
+        assert_eq!(mbc.read(0x4000), 35);
+
+        mbc.write(0x4000, 0x02);
+        mbc.write(0x6000, 0x00);
+        assert_eq!(mbc.ram_bank(), 6);
+        mbc.write_ext_ram(0xA000, 0x77);
+        assert_eq!(mbc.ram().unwrap()[6 * RAM_BANK_SIZE], 0x77);
+    }
+}
@@ -2,16 +2,30 @@
 
 mod none;
 mod mbc1;
+mod mbc2;
 mod mbc3;
 mod mbc5;
+mod mbc7;
+mod huc1;
+mod huc3;
+mod camera;
+mod mmm01;
 
 use log::warn;
-use crate::memory::cartridge::{CartridgeHeader, CartridgeType};
+use crate::memory::cartridge::{AccelerometerInput, CartridgeHeader, CartridgeType};
+use crate::memory::cartridge::camera::CameraSource;
+use crate::memory::cartridge::clock::ClockSource;
 
 pub(crate) use none::None;
 pub(crate) use mbc1::Mbc1;
+pub(crate) use mbc2::Mbc2;
 pub(crate) use mbc3::Mbc3;
 pub(crate) use mbc5::Mbc5;
+pub(crate) use mbc7::Mbc7;
+pub(crate) use huc1::HuC1;
+pub(crate) use huc3::HuC3;
+pub(crate) use camera::PocketCamera;
+pub(crate) use mmm01::Mmm01;
 
 #[allow(clippy::upper_case_acronyms)]
 enum BankingMode {
@@ -22,50 +36,111 @@ enum BankingMode {
 const ROM_BANK_SIZE: usize = 0x4000;
 const RAM_BANK_SIZE: usize = 0x2000;
 
+fn ext_ram_offset(addr: u16, bank: usize) -> usize {
+    bank.saturating_mul(RAM_BANK_SIZE).saturating_add(addr as usize - 0xA000)
+}
+
 pub(crate) trait Mbc {
     fn read(&self, addr: u16) -> u8;
     fn write(&mut self, addr: u16, val: u8);
     fn read_ext_ram(&self, _addr: u16) -> u8 { 0xFF }
     fn write_ext_ram(&mut self, _addr: u16, _val: u8) {}
+    /// Reads the external RAM byte seen at `addr` (0xA000-0xBFFF) in the given bank, for the debugger.
+    /// Works while the RAM is disabled and doesn't touch the MBC registers. Banks are laid out linearly.
+    fn peek_ext_ram(&self, addr: u16, bank: usize) -> u8 {
+        self.ram()
+            .and_then(|ram| ram.get(ext_ram_offset(addr, bank)).copied())
+            .unwrap_or(0xFF)
+    }
+    /// Writes the external RAM byte seen at `addr` (0xA000-0xBFFF) in the given bank, see `peek_ext_ram`.
+    fn poke_ext_ram(&mut self, addr: u16, bank: usize, val: u8) {
+        if let Some(byte) = self.ram_mut().and_then(|ram| ram.get_mut(ext_ram_offset(addr, bank))) {
+            *byte = val;
+        }
+    }
 
     fn num_banks(&self) -> usize { 2 }
     fn num_ram_banks(&self) -> usize { 0 }
+    /// The ROM bank currently mapped at 0x4000-0x7FFF.
+    fn rom_bank(&self) -> usize { 1 }
+    /// The external RAM bank currently mapped at 0xA000-0xBFFF.
+    fn ram_bank(&self) -> usize { 0 }
+    /// The offset in the ROM image of the byte mapped at `addr` (0x0000-0x7FFF).
+    fn rom_offset(&self, addr: u16) -> usize {
+        let bank_number = if addr < 0x4000 { 0 } else { self.rom_bank() };
+        ROM_BANK_SIZE * bank_number + (addr as usize) % ROM_BANK_SIZE
+    }
 
     fn has_battery(&self) -> bool { false }
     fn has_rtc(&self) -> bool { false }
+    /// The clock state to append to the save file after the RAM.
+    fn rtc_save_footer(&mut self) -> Option<Vec<u8>> { None }
+    /// The share of time, from 0.0 to 1.0, the rumble motor was on since the last call.
+    /// Games vary the strength of the motor by pulsing it.
+    fn take_rumble(&mut self) -> f32 { 0.0 }
+    /// Whether the cartridge speaker is beeping.
+    fn tone(&self) -> bool { false }
+    fn set_accelerometer(&mut self, _input: AccelerometerInput) {}
+    fn set_camera_source(&mut self, _source: Box<dyn CameraSource>) {}
+    /// Runs hardware that keeps time on its own for the given number of M-cycles at normal speed,
+    /// i.e. 4 dots of real time each, whatever the CPU speed.
+    fn clock(&mut self, _m_cycles: u32) {}
+    fn set_clock_source(&mut self, _clock: Box<dyn ClockSource>) {}
     fn has_ram(&self) -> bool { false }
 
     fn ram(&self) -> Option<&Vec<u8>> { None }
+    fn ram_mut(&mut self) -> Option<&mut Vec<u8>> { None }
     fn rom(&self) -> &Vec<u8>;
+    fn rom_mut(&mut self) -> &mut Vec<u8>;
 }
 
-pub(super) fn make_mbc(header: &CartridgeHeader, rom: Vec<u8>, ram: Option<Vec<u8>>) -> Box<dyn Mbc> {
-    #[allow(unreachable_patterns)] 
-    match header.cart_type {
-        CartridgeType::None => Box::new(None::new(rom)),
-        CartridgeType::Mbc1 =>
-            Box::new(Mbc1::new(rom, header, None, false)),
-        CartridgeType::Mbc1Ram =>
-            Box::new(Mbc1::new(rom, header, ram, false)),
-        CartridgeType::Mbc1RamBattery => Box::new(Mbc1::new(rom, header, ram, true)),
-        CartridgeType::Mbc3TimerBattery =>
-            Box::new(Mbc3::new(rom, header, None, true, true)),
-        CartridgeType::Mbc3TimerRamBattery =>
-            Box::new(Mbc3::new(rom, header, ram, true, true)),
-        CartridgeType::Mbc3 =>
-            Box::new(Mbc3::new(rom, header, None, false, false)),
-        CartridgeType::Mbc3Ram =>
-            Box::new(Mbc3::new(rom, header, ram, false, false)),
-        CartridgeType::Mbc3RamBattery =>
-            Box::new(Mbc3::new(rom, header, ram, true, false)),
-        CartridgeType::Mbc5 =>
-            Box::new(Mbc5::new(rom, header, None, false)),
-        CartridgeType::Mbc5Ram =>
-            Box::new(Mbc5::new(rom, header, ram, false)),
-        CartridgeType::Mbc5RamBattery => Box::new(Mbc5::new(rom, header, ram, true)),
-        t => {
-            warn!("Unimplemented cartridge type {:?}. Falling back to None", t);
+/// Builds the mapper of a cartridge from its header, ROM, save data and RTC clock.
+type MbcConstructor = fn(&CartridgeHeader, Vec<u8>, Option<Vec<u8>>, Box<dyn ClockSource>) -> Box<dyn Mbc>;
+
+/// The mapper of each cartridge type, the one place that says which types ohBoi supports.
+fn mbc_constructor(cart_type: CartridgeType) -> Option<MbcConstructor> {
+    #[allow(unreachable_patterns)]
+    let constructor: MbcConstructor = match cart_type {
+        CartridgeType::None => |_, rom, _, _| Box::new(None::new(rom)),
+        CartridgeType::Mbc1 => |header, rom, _, _| Box::new(Mbc1::new(rom, header, None, false)),
+        CartridgeType::Mbc1Ram => |header, rom, ram, _| Box::new(Mbc1::new(rom, header, ram, false)),
+        CartridgeType::Mbc1RamBattery => |header, rom, ram, _| Box::new(Mbc1::new(rom, header, ram, true)),
+        CartridgeType::Mbc2 => |header, rom, _, _| Box::new(Mbc2::new(rom, header, None, false)),
+        CartridgeType::Mbc2Battery => |header, rom, ram, _| Box::new(Mbc2::new(rom, header, ram, true)),
+        CartridgeType::Mmm01 => |header, rom, _, _| Box::new(Mmm01::new(rom, header, None, false)),
+        CartridgeType::Mmm01Ram => |header, rom, ram, _| Box::new(Mmm01::new(rom, header, ram, false)),
+        CartridgeType::Mmm01RamBattery => |header, rom, ram, _| Box::new(Mmm01::new(rom, header, ram, true)),
+        CartridgeType::Mbc3TimerBattery | CartridgeType::Mbc3TimerRamBattery =>
+            |header, rom, ram, clock| Box::new(Mbc3::new(rom, header, ram, true, true, clock)),
+        CartridgeType::Mbc3 => |header, rom, _, clock| Box::new(Mbc3::new(rom, header, None, false, false, clock)),
+        CartridgeType::Mbc3Ram => |header, rom, ram, clock| Box::new(Mbc3::new(rom, header, ram, false, false, clock)),
+        CartridgeType::Mbc3RamBattery => |header, rom, ram, clock| Box::new(Mbc3::new(rom, header, ram, true, false, clock)),
+        CartridgeType::Mbc5 => |header, rom, _, _| Box::new(Mbc5::new(rom, header, None, false, false)),
+        CartridgeType::Mbc5Ram => |header, rom, ram, _| Box::new(Mbc5::new(rom, header, ram, false, false)),
+        CartridgeType::Mbc5RamBattery => |header, rom, ram, _| Box::new(Mbc5::new(rom, header, ram, true, false)),
+        CartridgeType::Mbc5Rumble => |header, rom, _, _| Box::new(Mbc5::new(rom, header, None, false, true)),
+        CartridgeType::Mbc5RumbleRam => |header, rom, ram, _| Box::new(Mbc5::new(rom, header, ram, false, true)),
+        CartridgeType::Mbc5RumbleRamBattery => |header, rom, ram, _| Box::new(Mbc5::new(rom, header, ram, true, true)),
+        CartridgeType::Mbc7SensorRumbleRamBattery => |header, rom, ram, _| Box::new(Mbc7::new(rom, header, ram)),
+        CartridgeType::PocketCamera => |header, rom, ram, _| Box::new(PocketCamera::new(rom, header, ram)),
+        CartridgeType::HuC3 => |header, rom, ram, clock| Box::new(HuC3::new(rom, header, ram, clock)),
+        CartridgeType::HuC1RamBattery => |header, rom, ram, _| Box::new(HuC1::new(rom, header, ram, true)),
+        _ => return Option::None,
+    };
+    Some(constructor)
+}
+
+/// Whether `make_mbc` has a mapper for the cartridge type, rather than falling back to none.
+pub(crate) fn has_mapper(cart_type: CartridgeType) -> bool {
+    mbc_constructor(cart_type).is_some()
+}
+
+pub(super) fn make_mbc(header: &CartridgeHeader, rom: Vec<u8>, ram: Option<Vec<u8>>, clock: Box<dyn ClockSource>) -> Box<dyn Mbc> {
+    match mbc_constructor(header.cart_type) {
+        Some(constructor) => constructor(header, rom, ram, clock),
+        Option::None => {
+            warn!("Unimplemented cartridge type {:?}. Falling back to None", header.cart_type);
             Box::new(None::new(rom))
         }
     }
-}
\ No newline at end of file
+}
@@ -25,4 +25,9 @@ impl Mbc for None {
     fn rom(&self) -> &Vec<u8> {
         &self.rom
     }
+
+    #[inline]
+    fn rom_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.rom
+    }
 }
\ No newline at end of file
@@ -1,17 +1,33 @@
 // Copyright Antonio Porsia 2025. Licensed under the EUPL-1.2 or later.
 
 mod mbc;
+/// Picture sources for the Game Boy Camera.
+pub mod camera;
+/// Time sources for cartridge clocks.
+pub mod clock;
+/// Public cartridge header parsing and verification.
+pub mod info;
+/// IPS, UPS and BPS patching.
+pub mod patch;
 
 use std::fs::File;
-use std::path::PathBuf;
-use std::io::{Read, Result, Write};
+use std::path::{Path, PathBuf};
+use std::io::{Error, ErrorKind, Read, Result, Write};
 use std::ops::Index;
 use std::slice::SliceIndex;
-use log::warn;
+use log::{info, warn};
 use crate::memory::cartridge::mbc::{make_mbc, Mbc};
+use crate::memory::cartridge::camera::CameraSource;
+use crate::memory::cartridge::clock::{ClockSource, SystemClock};
+use crate::memory::cartridge::info::{header_offset, CartridgeInfo};
+use crate::memory::cartridge::patch::{apply_patch_file, find_patch};
+#[cfg(feature = "debugging")]
+use std::cell::{Ref, RefCell};
+#[cfg(feature = "debugging")]
+use crate::debug::cdl::{CodeDataLogger, RomAccess};
 
 /// Represents the type of a Game Boy cartridge.
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum CartridgeType {
     /// No cartridge.
     None = 0x00,
@@ -21,6 +37,17 @@ pub enum CartridgeType {
     Mbc1Ram = 0x02,
     /// MBC1 with RAM and battery backup.
     Mbc1RamBattery = 0x03,
+    /// Memory Bank Controller 2 (MBC2).
+    /// This includes 512x4 bits of RAM.
+    Mbc2 = 0x05,
+    /// MBC2 with battery backup.
+    Mbc2Battery = 0x06,
+    /// MMM01 multi-game mapper.
+    Mmm01 = 0x0B,
+    /// MMM01 with RAM.
+    Mmm01Ram = 0x0C,
+    /// MMM01 with RAM and battery backup.
+    Mmm01RamBattery = 0x0D,
     /// Memory Bank Controller 3 (MBC3).
     /// This includes RTC support.
     Mbc3TimerBattery = 0x0F,
@@ -41,10 +68,26 @@ pub enum CartridgeType {
     Mbc5Ram = 0x1A,
     /// MBC5 with RAM and battery backup.
     Mbc5RamBattery = 0x1B,
+    /// MBC5 with a rumble motor.
+    Mbc5Rumble = 0x1C,
+    /// MBC5 with a rumble motor and RAM.
+    Mbc5RumbleRam = 0x1D,
+    /// MBC5 with a rumble motor, RAM and battery backup.
+    Mbc5RumbleRamBattery = 0x1E,
+    /// Game Boy Camera, with RAM, battery backup and an image sensor.
+    PocketCamera = 0xFC,
+    /// Memory Bank Controller 7 (MBC7).
+    /// This includes an accelerometer and a serial EEPROM.
+    Mbc7SensorRumbleRamBattery = 0x22,
+    /// Hudson HuC3, with RAM, an RTC, an infrared port and a speaker.
+    HuC3 = 0xFE,
+    /// Hudson HuC1, with RAM, battery backup and an infrared port.
+    HuC1RamBattery = 0xFF,
 }
 
-impl From<u8> for CartridgeType {
-    /// Converts a `u8` value into a `CartridgeType`.
+impl CartridgeType {
+    /// Converts the cartridge type byte of the header, if ohBoi knows it.
+    /// Every known type has a mapper implementation.
     ///
     /// # Arguments
     ///
@@ -52,13 +95,18 @@ impl From<u8> for CartridgeType {
     ///
     /// # Returns
     ///
-    /// The corresponding `CartridgeType`. Defaults to `None` for unknown values.
-    fn from(value: u8) -> Self {
-        match value {
+    /// The corresponding `CartridgeType`, or `None` for unknown values.
+    pub fn from_code(value: u8) -> Option<Self> {
+        Some(match value {
             0x00 => Self::None,
             0x01 => Self::Mbc1,
             0x02 => Self::Mbc1Ram,
             0x03 => Self::Mbc1RamBattery,
+            0x05 => Self::Mbc2,
+            0x06 => Self::Mbc2Battery,
+            0x0B => Self::Mmm01,
+            0x0C => Self::Mmm01Ram,
+            0x0D => Self::Mmm01RamBattery,
             0x0F => Self::Mbc3TimerBattery,
             0x10 => Self::Mbc3TimerRamBattery,
             0x11 => Self::Mbc3,
@@ -67,14 +115,45 @@ impl From<u8> for CartridgeType {
             0x19 => Self::Mbc5,
             0x1A => Self::Mbc5Ram,
             0x1B => Self::Mbc5RamBattery,
-            _ => {
-                warn!("Unknown cartridge type 0x{:x}. Falling back to None", value);
-                Self::None
-            }
-        }
+            0x1C => Self::Mbc5Rumble,
+            0x1D => Self::Mbc5RumbleRam,
+            0x1E => Self::Mbc5RumbleRamBattery,
+            0x22 => Self::Mbc7SensorRumbleRamBattery,
+            0xFC => Self::PocketCamera,
+            0xFE => Self::HuC3,
+            0xFF => Self::HuC1RamBattery,
+            _ => return None,
+        })
+    }
+}
+
+impl From<u8> for CartridgeType {
+    /// Converts a `u8` value into a `CartridgeType`.
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The `u8` value representing the cartridge type.
+    ///
+    /// # Returns
+    ///
+    /// The corresponding `CartridgeType`. Defaults to `None` for unknown values.
+    fn from(value: u8) -> Self {
+        Self::from_code(value).unwrap_or_else(|| {
+            warn!("Unknown cartridge type 0x{:x}. Falling back to None", value);
+            Self::None
+        })
     }
 }
 
+/// The acceleration measured by cartridges with a tilt sensor (MBC7), in g.
+///
+/// `x` is positive when the Game Boy is tilted to the right, `y` when its top is tilted away from the player.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct AccelerometerInput {
+    pub x: f32,
+    pub y: f32,
+}
+
 /// Represents the header of a Game Boy cartridge.
 ///
 /// The header contains metadata about the cartridge, such as its title, type, and memory sizes.
@@ -173,18 +252,23 @@ impl CartridgeHeader {
 /// This structure manages the ROM, RAM, and memory bank controller (MBC) for the cartridge.
 pub struct Cartridge {
     /// The path to the ROM file.
-    #[allow(unused)]
     rom_path: PathBuf,
+    /// The patch explicitly applied to the ROM, if any.
+    patch_path: Option<PathBuf>,
     /// The path to the save file.
     sav_path: PathBuf,
     /// The header of the cartridge.
     header: CartridgeHeader,
     /// The memory bank controller (MBC) used by the cartridge.
     pub(crate) mbc: Box<dyn Mbc>,
+    /// Records how each ROM byte is accessed, while enabled.
+    #[cfg(feature = "debugging")]
+    cdl: RefCell<Option<CodeDataLogger>>,
 }
 
 impl Cartridge {
     /// Opens a cartridge from the given ROM file path.
+    /// A `.bps`, `.ups` or `.ips` patch with the same name as the ROM is applied if there is one.
     ///
     /// # Arguments
     ///
@@ -194,9 +278,55 @@ impl Cartridge {
     ///
     /// A `Result` containing the `Cartridge` instance or an error.
     pub fn open(rom_path: PathBuf) -> Result<Self> {
+        Self::open_with_patch(rom_path, None)
+    }
+
+    /// Opens a cartridge from the given ROM file path, applying a patch in memory.
+    ///
+    /// # Arguments
+    ///
+    /// * `rom_path` - The path to the ROM file.
+    /// * `patch_path` - The IPS, UPS or BPS patch to apply, or `None` to look for one next to the ROM.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the `Cartridge` instance or an error, also if the given patch doesn't apply.
+    pub fn open_with_patch(rom_path: PathBuf, patch_path: Option<&Path>) -> Result<Self> {
+        Self::open_with_clock(rom_path, patch_path, Box::new(SystemClock))
+    }
+
+    /// Opens a cartridge like `open_with_patch`, with its RTC, if any, following the given clock.
+    /// The clock is in place before the RTC is restored from the save file, so that only a wall
+    /// clock counts the time elapsed since the game was saved.
+    ///
+    /// # Arguments
+    ///
+    /// * `rom_path` - The path to the ROM file.
+    /// * `patch_path` - The IPS, UPS or BPS patch to apply, or `None` to look for one next to the ROM.
+    /// * `clock` - Where the cartridge RTC takes the time from.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the `Cartridge` instance or an error, also if the given patch doesn't apply.
+    pub fn open_with_clock(rom_path: PathBuf, patch_path: Option<&Path>, clock: Box<dyn ClockSource>) -> Result<Self> {
         let mut rom = Vec::new();
         File::open(&rom_path)?
             .read_to_end(&mut rom)?;
+        match patch_path {
+            Some(patch_path) => {
+                rom = apply_patch_file(&rom, patch_path)?;
+                info!("Applied patch {:?}", patch_path);
+            },
+            None => if let Some(patch_path) = find_patch(&rom_path) {
+                match apply_patch_file(&rom, &patch_path) {
+                    Ok(patched) => {
+                        rom = patched;
+                        info!("Applied patch {:?}", patch_path);
+                    },
+                    Err(e) => warn!("Ignoring patch {:?}: {}", patch_path, e),
+                }
+            }
+        }
 
         let sav_path = rom_path.with_extension("sav");
         let sav = match File::open(&sav_path) {
@@ -207,12 +337,27 @@ impl Cartridge {
             },
             Err(_) => None,
         };
-        let header = CartridgeHeader::new(&rom);
+        let header = CartridgeHeader::new(&rom[header_offset(&rom)..].to_vec());
+        match CartridgeInfo::from_rom(&rom) {
+            Some(info) if !info.is_bootable() =>
+                warn!("Corrupt cartridge header (header checksum {}, logo {}). A real Game Boy wouldn't boot it",
+                      if info.header_checksum_valid { "ok" } else { "bad" }, if info.logo_valid { "ok" } else { "bad" }),
+            Some(info) if !info.global_checksum_valid => warn!("Global checksum mismatch, the ROM may be a bad dump or patched"),
+            _ => {}
+        }
         if rom.len() != header.rom_size {
             warn!("Inconsistent ROM size. Cartridge header reports {:x}, but actual size is {:x}", header.rom_size, rom.len());
         }
-        let mbc = make_mbc(&header, rom, sav);
-        Ok(Cartridge { rom_path, sav_path, header, mbc })
+        let mbc = make_mbc(&header, rom, sav, clock);
+        Ok(Cartridge {
+            rom_path,
+            patch_path: patch_path.map(Path::to_path_buf),
+            sav_path,
+            header,
+            mbc,
+            #[cfg(feature = "debugging")]
+            cdl: RefCell::new(None),
+        })
     }
 
     /// Reads a byte from the cartridge at the specified address.
@@ -225,6 +370,13 @@ impl Cartridge {
     ///
     /// The value at the specified address.
     pub fn read(&self, addr: u16) -> u8 {
+        #[cfg(feature = "debugging")]
+        self.log_access(addr, RomAccess::Data);
+        self.read_mapped(addr)
+    }
+
+    /// Reads a byte through the MBC, without recording the access in the code/data log.
+    fn read_mapped(&self, addr: u16) -> u8 {
         match addr {
             0x0000..=0x7FFF => self.mbc.read(addr),
             0xA000..=0xBFFF => self.mbc.read_ext_ram(addr),
@@ -235,6 +387,67 @@ impl Cartridge {
         }
     }
 
+    /// The size of the external RAM, 0 if the cartridge has none.
+    pub(crate) fn ram_size(&self) -> usize {
+        match self.mbc.ram() {
+            Some(ram) if self.mbc.has_ram() => ram.len(),
+            _ => 0
+        }
+    }
+
+    /// Reads a byte straight from the ROM image or the external RAM, bypassing the MBC.
+    /// External RAM can be read even while it is disabled.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - The address to read from (0x0000-0x7FFF or 0xA000-0xBFFF).
+    /// * `bank` - The ROM bank to read 0x4000-0x7FFF from or the RAM bank, `None` for the bank currently
+    ///   mapped at `addr`. 0x0000-0x3FFF isn't switched by `bank`, it always reads what the MBC maps there.
+    ///
+    /// # Returns
+    ///
+    /// The value at the specified address, or 0xFF if it doesn't exist.
+    pub fn peek(&self, addr: u16, bank: Option<usize>) -> u8 {
+        match addr {
+            0x0000..=0x7FFF => self.rom_offset(addr, bank)
+                .and_then(|offset| self.mbc.rom().get(offset).copied())
+                .unwrap_or(0xFF),
+            0xA000..=0xBFFF => self.mbc.peek_ext_ram(addr, bank.unwrap_or(self.mbc.ram_bank())),
+            _ => 0xFF
+        }
+    }
+
+    /// Writes a byte straight to the ROM image or the external RAM, bypassing the MBC.
+    /// Writes to ROM patch the loaded image, the file on disk is left untouched.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - The address to write to (0x0000-0x7FFF or 0xA000-0xBFFF).
+    /// * `bank` - The ROM bank to write 0x4000-0x7FFF to or the RAM bank, `None` for the bank currently
+    ///   mapped at `addr`. As with `peek`, 0x0000-0x3FFF isn't switched by `bank`.
+    /// * `val` - The value to write.
+    pub fn poke(&mut self, addr: u16, bank: Option<usize>, val: u8) {
+        match addr {
+            0x0000..=0x7FFF => {
+                if let Some(byte) = self.rom_offset(addr, bank).and_then(|offset| self.mbc.rom_mut().get_mut(offset)) {
+                    *byte = val;
+                }
+            },
+            0xA000..=0xBFFF => {
+                let bank = bank.unwrap_or(self.mbc.ram_bank());
+                self.mbc.poke_ext_ram(addr, bank, val);
+            },
+            _ => {}
+        }
+    }
+
+    fn rom_offset(&self, addr: u16, bank: Option<usize>) -> Option<usize> {
+        match bank {
+            Some(bank) if addr >= 0x4000 => bank.checked_mul(0x4000).map(|base| base + addr as usize % 0x4000),
+            _ => Some(self.mbc.rom_offset(addr))
+        }
+    }
+
     /// Writes a byte to the cartridge at the specified address.
     ///
     /// # Arguments
@@ -249,15 +462,79 @@ impl Cartridge {
         }
     }
 
+    /// Returns the ROM bank currently mapped at 0x4000-0x7FFF.
+    pub fn rom_bank(&self) -> usize {
+        self.mbc.rom_bank()
+    }
+
+    /// Updates the acceleration measured by the cartridge's tilt sensor, if it has one.
+    pub fn set_accelerometer(&mut self, input: AccelerometerInput) {
+        self.mbc.set_accelerometer(input);
+    }
+
+    /// Replaces the pictures seen by the Game Boy Camera sensor, if the cartridge has one.
+    pub fn set_camera_source(&mut self, source: Box<dyn CameraSource>) {
+        self.mbc.set_camera_source(source);
+    }
+
+    /// Replaces the time source of the cartridge RTC, if it has one.
+    pub fn set_clock_source(&mut self, clock: Box<dyn ClockSource>) {
+        self.mbc.set_clock_source(clock);
+    }
+
+    /// Advances the cartridge hardware that runs on its own, like the camera sensor or the RTC.
+    ///
+    /// # Arguments
+    ///
+    /// * `m_cycles` - The number of M-cycles elapsed at normal speed (4 dots each).
+    pub fn clock(&mut self, m_cycles: u32) {
+        self.mbc.clock(m_cycles);
+    }
+
+    /// Returns the share of time, from 0.0 to 1.0, the rumble motor of the cartridge was on
+    /// since the last call.
+    pub fn take_rumble(&mut self) -> f32 {
+        self.mbc.take_rumble()
+    }
+
+    /// Returns `true` while the speaker of the cartridge (HuC3) is beeping.
+    pub fn tone(&self) -> bool {
+        self.mbc.tone()
+    }
+
     /// Saves the current state of the cartridge to the save file.
-    pub fn save(&self) {
-        if self.mbc.has_ram() && self.mbc.has_battery() {
+    /// The RAM is followed by the RTC state, if the cartridge has one, in the format used by other emulators.
+    pub fn save(&mut self) {
+        if !self.mbc.has_battery() {
+            return;
+        }
+        let mut data = self.mbc.ram().cloned().unwrap_or_default();
+        if let Some(footer) = self.mbc.rtc_save_footer() {
+            data.extend_from_slice(&footer);
+        }
+        if !data.is_empty() {
             let mut f = File::create(&self.sav_path).expect("Failed to create save file");
             warn!("Writing save file to {:?}", self.sav_path);
-            f.write_all(self.mbc.ram().unwrap()).expect("Failed to write save file");
+            f.write_all(&data).expect("Failed to write save file");
         }
     }
 
+    /// Returns the path of the ROM the cartridge was loaded from.
+    pub fn rom_path(&self) -> &Path {
+        &self.rom_path
+    }
+
+    /// Returns the patch explicitly applied when opening the cartridge, if any.
+    pub fn patch_path(&self) -> Option<&Path> {
+        self.patch_path.as_deref()
+    }
+
+    /// Returns the parsed cartridge header, or an error if the ROM is too small to have one.
+    pub fn info(&self) -> Result<CartridgeInfo> {
+        CartridgeInfo::from_rom(self.mbc.rom())
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Cartridge ROM is smaller than its header"))
+    }
+
     /// Checks if the cartridge supports Color Game Boy (CGB).
     ///
     /// # Returns
@@ -267,6 +544,78 @@ impl Cartridge {
         self.header.cgb
     }
 
+    /// Checks if the cartridge supports the Super Game Boy functions. The SGB BIOS also
+    /// requires the old licensee code to be 0x33.
+    ///
+    /// # Returns
+    ///
+    /// `true` if the cartridge supports SGB, `false` otherwise.
+    pub fn is_sgb(&self) -> bool {
+        self.header.sgb && self.header.old_licensee_code == 0x33
+    }
+
+    /// Reads a byte, recording in the code/data log how a ROM byte is being accessed.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - The address to read from.
+    /// * `access` - Whether the byte is fetched as an opcode, an operand, data or a DMA source.
+    ///
+    /// # Returns
+    ///
+    /// The value at the specified address.
+    #[cfg(feature = "debugging")]
+    pub(crate) fn read_as(&self, addr: u16, access: RomAccess) -> u8 {
+        self.log_access(addr, access);
+        self.read_mapped(addr)
+    }
+
+    #[cfg(feature = "debugging")]
+    fn log_access(&self, addr: u16, access: RomAccess) {
+        if addr < 0x8000 {
+            if let Some(ref mut cdl) = *self.cdl.borrow_mut() {
+                cdl.log(self.mbc.rom_offset(addr), access);
+            }
+        }
+    }
+
+    /// Returns the path the code/data log of this cartridge is saved to, next to the ROM.
+    #[cfg(feature = "debugging")]
+    pub fn cdl_path(&self) -> PathBuf {
+        self.rom_path.with_extension("cdl")
+    }
+
+    /// Starts logging ROM accesses. An existing log is kept, otherwise the one saved next to the ROM
+    /// is loaded so that coverage accumulates over sessions.
+    #[cfg(feature = "debugging")]
+    pub fn start_code_data_log(&self) {
+        let mut cdl = self.cdl.borrow_mut();
+        if cdl.is_none() {
+            let rom_size = self.mbc.rom().len();
+            let path = self.cdl_path();
+            *cdl = Some(match CodeDataLogger::load(&path, rom_size) {
+                Ok(log) => log,
+                Err(e) => {
+                    if path.exists() {
+                        warn!("Failed to load code/data log {:?}: {}", path, e);
+                    }
+                    CodeDataLogger::new(rom_size)
+                }
+            });
+        }
+    }
+
+    /// Stops logging ROM accesses and returns the log.
+    #[cfg(feature = "debugging")]
+    pub fn stop_code_data_log(&self) -> Option<CodeDataLogger> {
+        self.cdl.borrow_mut().take()
+    }
+
+    #[cfg(feature = "debugging")]
+    pub fn code_data_log(&self) -> Ref<'_, Option<CodeDataLogger>> {
+        self.cdl.borrow()
+    }
+
     /// Returns a reference to the ROM data.
     ///
     /// This method is only available when the `debug_ui` feature is enabled.
@@ -0,0 +1,361 @@
+// Copyright Antonio Porsia 2025. Licensed under the EUPL-1.2 or later.
+
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use crate::utils::crc32;
+
+/// Patch formats looked for next to a ROM, in order of preference.
+const PATCH_EXTENSIONS: [&str; 3] = ["bps", "ups", "ips"];
+/// The largest ROM a UPS or BPS patch may produce, twice the largest cartridge.
+const MAX_TARGET_SIZE: usize = 8 * 1024 * 1024;
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg)
+}
+
+/// Finds a patch with the same name as the ROM, e.g. `game.ips` for `game.gb`.
+///
+/// # Arguments
+///
+/// * `rom_path` - The path to the ROM file.
+///
+/// # Returns
+///
+/// The path to the patch, or `None` if there is none.
+pub fn find_patch(rom_path: &Path) -> Option<PathBuf> {
+    PATCH_EXTENSIONS.iter()
+        .map(|ext| rom_path.with_extension(ext))
+        .find(|path| path.is_file())
+}
+
+/// Reads a patch file and applies it to a ROM, detecting the format from its header.
+///
+/// # Arguments
+///
+/// * `rom` - The original ROM image.
+/// * `patch_path` - The path to an IPS, UPS or BPS patch.
+///
+/// # Returns
+///
+/// A `Result` containing the patched ROM or an error.
+pub fn apply_patch_file(rom: &[u8], patch_path: &Path) -> io::Result<Vec<u8>> {
+    apply_patch(rom, &fs::read(patch_path)?)
+}
+
+/// Applies an IPS, UPS or BPS patch, detecting the format from its header.
+/// UPS and BPS patches are rejected unless the source, target and patch CRCs all match.
+///
+/// # Arguments
+///
+/// * `rom` - The original ROM image.
+/// * `patch` - The patch data.
+///
+/// # Returns
+///
+/// A `Result` containing the patched ROM or an error.
+pub fn apply_patch(rom: &[u8], patch: &[u8]) -> io::Result<Vec<u8>> {
+    if patch.starts_with(b"PATCH") {
+        apply_ips(rom, patch)
+    } else if patch.starts_with(b"UPS1") {
+        apply_ups(rom, patch)
+    } else if patch.starts_with(b"BPS1") {
+        apply_bps(rom, patch)
+    } else {
+        Err(invalid(String::from("Unknown patch format")))
+    }
+}
+
+/// Sequential reader over patch data.
+struct PatchReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> PatchReader<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Self {
+        Self { data, pos }
+    }
+
+    fn bytes(&mut self, count: usize) -> io::Result<&'a [u8]> {
+        let bytes = self.pos.checked_add(count)
+            .and_then(|end| self.data.get(self.pos..end))
+            .ok_or_else(|| invalid(String::from("Truncated patch")))?;
+        self.pos += count;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> io::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn be(&mut self, count: usize) -> io::Result<usize> {
+        Ok(self.bytes(count)?.iter().fold(0, |acc, &b| acc << 8 | b as usize))
+    }
+
+    /// The variable length numbers of UPS and BPS: 7 bits at a time, least significant first,
+    /// with the high bit marking the last byte and an implicit +1 per continuation.
+    fn varint(&mut self) -> io::Result<usize> {
+        let (mut value, mut shift) = (0usize, 1usize);
+        loop {
+            let byte = self.byte()?;
+            value = value.checked_add((byte as usize & 0x7F).saturating_mul(shift))
+                .ok_or_else(|| invalid(String::from("Number too large in patch")))?;
+            if byte & 0x80 != 0 {
+                return Ok(value);
+            }
+            shift = shift.checked_shl(7).ok_or_else(|| invalid(String::from("Number too large in patch")))?;
+            value += shift;
+        }
+    }
+}
+
+/// Applies an IPS patch: records of big endian offsets and data, or runs of a single byte,
+/// optionally followed by the size to truncate the ROM to.
+pub fn apply_ips(rom: &[u8], patch: &[u8]) -> io::Result<Vec<u8>> {
+    let mut out = rom.to_vec();
+    let mut reader = PatchReader::new(patch, 5);
+    loop {
+        if reader.data.get(reader.pos..reader.pos + 3) == Some(b"EOF") {
+            reader.pos += 3;
+            break;
+        }
+        let offset = reader.be(3)?;
+        let (data, len) = match reader.be(2)? {
+            0 => {
+                let len = reader.be(2)?;
+                (None, len)
+            },
+            len => (Some(reader.bytes(len)?), len)
+        };
+        if out.len() < offset + len {
+            out.resize(offset + len, 0);
+        }
+        match data {
+            Some(data) => out[offset..offset + len].copy_from_slice(data),
+            None => out[offset..offset + len].fill(reader.byte()?),
+        }
+    }
+    if let Ok(size) = reader.be(3) {
+        out.truncate(size);
+    }
+    Ok(out)
+}
+
+fn overflow() -> io::Error {
+    invalid(String::from("Offset or length out of range in patch"))
+}
+
+/// Reads the header of a UPS or BPS patch after checking the patch CRC, so that nothing from
+/// a corrupt patch is trusted. Returns a reader over the actions and the source and target sizes.
+fn read_header<'a>(rom: &[u8], patch: &'a [u8]) -> io::Result<(PatchReader<'a>, usize)> {
+    if patch.len() < 4 + 12 {
+        return Err(invalid(String::from("Truncated patch")));
+    }
+    let footer = patch.len() - 12;
+    if u32::from_le_bytes(patch[footer + 8..].try_into().unwrap()) != crc32(&patch[..footer + 8]) {
+        return Err(invalid(String::from("Patch is corrupt, its CRC doesn't match")));
+    }
+    let mut reader = PatchReader::new(&patch[..footer], 4);
+    let source_size = reader.varint()?;
+    let target_size = reader.varint()?;
+    if source_size != rom.len() {
+        return Err(invalid(format!("Patch is for a ROM of {} bytes, this one has {}", source_size, rom.len())));
+    }
+    if target_size > MAX_TARGET_SIZE {
+        return Err(invalid(format!("Patched ROM would be {} bytes, more than the {} supported", target_size, MAX_TARGET_SIZE)));
+    }
+    Ok((reader, target_size))
+}
+
+/// Checks the source and target CRC32s at the end of a UPS or BPS patch.
+fn check_crcs(source: &[u8], target: &[u8], patch: &[u8]) -> io::Result<()> {
+    let footer = patch.len() - 12;
+    let crc = |offset: usize| u32::from_le_bytes(patch[footer + offset..footer + offset + 4].try_into().unwrap());
+    if crc(0) != crc32(source) {
+        return Err(invalid(format!("Patch is for another ROM (CRC32 {:08x}, this one is {:08x})", crc(0), crc32(source))));
+    }
+    if crc(4) != crc32(target) {
+        return Err(invalid(String::from("Patched ROM doesn't match the expected CRC")));
+    }
+    Ok(())
+}
+
+/// Applies a UPS patch: XOR runs between the source and the target, separated by unchanged bytes.
+pub fn apply_ups(rom: &[u8], patch: &[u8]) -> io::Result<Vec<u8>> {
+    let (mut reader, target_size) = read_header(rom, patch)?;
+    let mut out = rom.to_vec();
+    out.resize(target_size, 0);
+    let mut pos = 0usize;
+    while reader.pos < reader.data.len() {
+        pos = pos.checked_add(reader.varint()?).ok_or_else(overflow)?;
+        loop {
+            let byte = reader.byte()?;
+            if byte == 0 {
+                pos += 1;
+                break;
+            }
+            if let Some(b) = out.get_mut(pos) {
+                *b ^= byte;
+            }
+            pos += 1;
+        }
+    }
+    check_crcs(rom, &out, patch)?;
+    Ok(out)
+}
+
+/// Applies a BPS patch: a list of copy actions from the source, the patch or the target itself.
+pub fn apply_bps(rom: &[u8], patch: &[u8]) -> io::Result<Vec<u8>> {
+    let (mut reader, target_size) = read_header(rom, patch)?;
+    let metadata_size = reader.varint()?;
+    reader.bytes(metadata_size)?;
+    let out_of_bounds = || invalid(String::from("Patch reads outside of the ROM"));
+    // Moves a relative offset, stored as a magnitude with the sign in bit 0
+    let relative = |base: usize, offset: usize| if offset & 1 != 0 {
+        base.checked_sub(offset >> 1).ok_or_else(out_of_bounds)
+    } else {
+        base.checked_add(offset >> 1).ok_or_else(overflow)
+    };
+    let mut out = Vec::with_capacity(target_size);
+    let (mut source_offset, mut target_offset) = (0usize, 0usize);
+    while reader.pos < reader.data.len() {
+        let data = reader.varint()?;
+        let len = (data >> 2).checked_add(1).ok_or_else(overflow)?;
+        let end = out.len().checked_add(len).ok_or_else(overflow)?;
+        if end > target_size {
+            return Err(invalid(format!("Patch writes past the {} bytes of the patched ROM", target_size)));
+        }
+        match data & 3 {
+            // SourceRead
+            0 => out.extend_from_slice(rom.get(out.len()..end).ok_or_else(out_of_bounds)?),
+            // TargetRead
+            1 => out.extend_from_slice(reader.bytes(len)?),
+            // SourceCopy
+            2 => {
+                source_offset = relative(source_offset, reader.varint()?)?;
+                let source_end = source_offset.checked_add(len).ok_or_else(overflow)?;
+                out.extend_from_slice(rom.get(source_offset..source_end).ok_or_else(out_of_bounds)?);
+                source_offset = source_end;
+            },
+            // TargetCopy, byte by byte as the ranges may overlap
+            _ => {
+                target_offset = relative(target_offset, reader.varint()?)?;
+                if target_offset >= out.len() {
+                    return Err(out_of_bounds());
+                }
+                for _ in 0..len {
+                    out.push(out[target_offset]);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+    if out.len() != target_size {
+        return Err(invalid(format!("Patch produced {} bytes, expected {}", out.len(), target_size)));
+    }
+    check_crcs(rom, &out, patch)?;
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn varint(mut value: usize, out: &mut Vec<u8>) {
+        loop {
+            let byte = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                out.push(byte | 0x80);
+                return;
+            }
+            out.push(byte);
+            value -= 1;
+        }
+    }
+
+    fn with_footer(mut patch: Vec<u8>, source: &[u8], target: &[u8]) -> Vec<u8> {
+        patch.extend_from_slice(&crc32(source).to_le_bytes());
+        patch.extend_from_slice(&crc32(target).to_le_bytes());
+        patch.extend_from_slice(&crc32(&patch).to_le_bytes());
+        patch
+    }
+
+    #[test]
+    fn ips_writes_records_and_runs() {
+        let rom = vec![0u8; 8];
+        let mut patch = b"PATCH".to_vec();
+        patch.extend_from_slice(&[0x00, 0x00, 0x01, 0x00, 0x02, 0xAA, 0xBB]);
+        patch.extend_from_slice(&[0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x03, 0xCC]);
+        patch.extend_from_slice(b"EOF");
+        assert_eq!(apply_patch(&rom, &patch).unwrap(), [0, 0xAA, 0xBB, 0, 0, 0, 0, 0, 0xCC, 0xCC, 0xCC]);
+    }
+
+    #[test]
+    fn ups_and_bps_check_crcs() {
+        let rom: Vec<u8> = (0..200).collect();
+        let mut target = rom.clone();
+        target[150] = 0xFF;
+        target.extend_from_slice(&[1, 2, 3]);
+
+        let mut ups = b"UPS1".to_vec();
+        varint(rom.len(), &mut ups);
+        varint(target.len(), &mut ups);
+        varint(150, &mut ups);
+        ups.extend_from_slice(&[150 ^ 0xFF, 0x00]);
+        varint(48, &mut ups);
+        ups.extend_from_slice(&[1, 2, 3, 0x00]);
+        let ups = with_footer(ups, &rom, &target);
+        assert_eq!(apply_patch(&rom, &ups).unwrap(), target);
+
+        // SourceRead 150 bytes, TargetRead 0xFF, SourceCopy 49 bytes from 151, TargetRead 1, TargetCopy 2 from 0
+        let mut bps_target = target[..200].to_vec();
+        bps_target.extend_from_slice(&[1, 0, 1]);
+        let mut bps = b"BPS1".to_vec();
+        varint(rom.len(), &mut bps);
+        varint(bps_target.len(), &mut bps);
+        varint(0, &mut bps);
+        varint(149 << 2, &mut bps);
+        varint(1, &mut bps);
+        bps.push(0xFF);
+        varint((48 << 2) | 2, &mut bps);
+        varint(151 << 1, &mut bps);
+        varint(1, &mut bps);
+        bps.push(1);
+        varint((1 << 2) | 3, &mut bps);
+        varint(0, &mut bps);
+        let bps = with_footer(bps, &rom, &bps_target);
+        assert_eq!(apply_patch(&rom, &bps).unwrap(), bps_target);
+
+        let other_rom = vec![0; 200];
+        assert!(apply_patch(&other_rom, &ups).is_err());
+    }
+
+    #[test]
+    fn bps_rejects_oversized_and_overflowing_patches() {
+        let rom = vec![0u8; 16];
+        // Claims a huge target, rejected before anything is allocated
+        let mut huge = b"BPS1".to_vec();
+        varint(rom.len(), &mut huge);
+        varint(usize::MAX >> 8, &mut huge);
+        varint(0, &mut huge);
+        assert!(apply_patch(&rom, &with_footer(huge.clone(), &rom, &rom)).is_err());
+
+        // TargetCopy of more bytes than the target has, with a wrapping length
+        let mut copy = b"BPS1".to_vec();
+        varint(rom.len(), &mut copy);
+        varint(4, &mut copy);
+        varint(0, &mut copy);
+        varint(1, &mut copy);
+        copy.push(1);
+        varint(usize::MAX, &mut copy);
+        varint(0, &mut copy);
+        assert!(apply_patch(&rom, &with_footer(copy, &rom, &rom)).is_err());
+
+        // A corrupt patch is rejected by its own CRC
+        let mut corrupt = with_footer(huge, &rom, &rom);
+        corrupt[5] ^= 1;
+        assert!(apply_patch(&rom, &corrupt).is_err());
+    }
+}
@@ -6,8 +6,13 @@ pub(crate) mod dma;
 /// Module for handling cartridge-related operations.
 pub mod cartridge;
 
+/// Module for searching RAM for values, e.g. to find cheats.
+pub mod search;
+
 /// The size of a memory bank in bytes.
 const BANK_SIZE: usize = 0x1000;
+/// Bank 0 at 0xC000-0xCFFF, and banks 1-7 switched in at 0xD000-0xDFFF by SVBK.
+pub(crate) const WRAM_BANKS: usize = 8;
 
 /// Represents the Work RAM (RAM) structure.
 ///
@@ -27,7 +32,7 @@ impl WRAM {
     /// A new `WRAM` instance with all memory set to zero and bank 1 set to index 1.
     pub fn new() -> Self {
         WRAM {
-            mem: vec![0; BANK_SIZE * 8],
+            mem: vec![0; BANK_SIZE * WRAM_BANKS],
             bank1_index: 1,
         }
     }
@@ -73,6 +78,41 @@ impl WRAM {
         }
     }
 
+    /// Returns the index in WRAM of `addr`, as if `bank` were mapped at 0xD000-0xDFFF.
+    fn banked_index(&self, addr: u16, bank: Option<usize>) -> usize {
+        let index = addr as usize & 0xFFF;
+        match addr {
+            0xC000..=0xCFFF => index,
+            _ => BANK_SIZE * (bank.unwrap_or(self.bank1_index) & 0b111) + index,
+        }
+    }
+
+    /// Reads a byte from any WRAM bank.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - The address to read from (0xC000-0xDFFF).
+    /// * `bank` - The bank to read 0xD000-0xDFFF from, or `None` for the active bank.
+    ///
+    /// # Returns
+    ///
+    /// The value at the specified address.
+    pub fn peek(&self, addr: u16, bank: Option<usize>) -> u8 {
+        self.mem[self.banked_index(addr, bank)]
+    }
+
+    /// Writes a byte to any WRAM bank.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - The address to write to (0xC000-0xDFFF).
+    /// * `bank` - The bank to write 0xD000-0xDFFF to, or `None` for the active bank.
+    /// * `val` - The value to write.
+    pub fn poke(&mut self, addr: u16, bank: Option<usize>, val: u8) {
+        let index = self.banked_index(addr, bank);
+        self.mem[index] = val;
+    }
+
     /// Switches the active bank for bank 1.
     ///
     /// # Arguments
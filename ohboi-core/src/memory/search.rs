@@ -0,0 +1,193 @@
+// Copyright Antonio Porsia 2025. Licensed under the EUPL-1.2 or later.
+
+/// How the bytes at a candidate address are interpreted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueSize {
+    U8,
+    /// 16-bit value, low byte first.
+    U16Le,
+    /// 16-bit value, high byte first.
+    U16Be,
+}
+
+impl ValueSize {
+    fn len(self) -> u16 {
+        match self {
+            ValueSize::U8 => 1,
+            ValueSize::U16Le | ValueSize::U16Be => 2,
+        }
+    }
+}
+
+/// How the current value of a candidate is compared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Greater,
+    Less,
+}
+
+impl Comparison {
+    fn matches(self, value: u16, reference: u16) -> bool {
+        match self {
+            Comparison::Equal => value == reference,
+            Comparison::NotEqual => value != reference,
+            Comparison::Greater => value > reference,
+            Comparison::Less => value < reference,
+        }
+    }
+}
+
+/// What the current value of a candidate is compared to.
+///
+/// "Changed" and "unchanged" searches are `NotEqual` and `Equal` against `Previous`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchTarget {
+    /// The value in the snapshot the previous search step was run on.
+    Previous,
+    /// A constant value.
+    Value(u16),
+}
+
+/// A copy of the RAM regions that can be searched: every WRAM and cartridge RAM bank, and HRAM.
+///
+/// Bytes are located by bank and address. The bank is the one of the region the address is in:
+/// the WRAM bank for 0xC000-0xDFFF (0 below 0xD000), the cartridge RAM bank for 0xA000-0xBFFF
+/// and 0 for HRAM.
+#[derive(Debug, Clone, Default)]
+pub struct MemorySnapshot {
+    /// The bank of each region, the address it is mapped at, and its bytes.
+    regions: Vec<(usize, u16, Vec<u8>)>,
+}
+
+impl MemorySnapshot {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn add_region(&mut self, bank: usize, base: u16, bytes: Vec<u8>) {
+        self.regions.push((bank, base, bytes));
+    }
+
+    /// Returns the byte at `addr` in `bank`, or `None` if it isn't part of the snapshot.
+    pub fn read(&self, bank: usize, addr: u16) -> Option<u8> {
+        self.regions.iter()
+            .find(|(b, base, bytes)| *b == bank && addr >= *base && ((addr - base) as usize) < bytes.len())
+            .map(|(_, base, bytes)| bytes[(addr - base) as usize])
+    }
+
+    /// Returns the value at `addr` in `bank`, or `None` if any of its bytes isn't part of the snapshot.
+    pub fn value(&self, bank: usize, addr: u16, size: ValueSize) -> Option<u16> {
+        let lo = self.read(bank, addr)? as u16;
+        match size {
+            ValueSize::U8 => Some(lo),
+            ValueSize::U16Le => Some(((self.read(bank, addr.checked_add(1)?)? as u16) << 8) | lo),
+            ValueSize::U16Be => Some((lo << 8) | self.read(bank, addr.checked_add(1)?)? as u16),
+        }
+    }
+
+    fn locations(&self, size: ValueSize) -> impl Iterator<Item = (usize, u16)> + '_ {
+        self.regions.iter().flat_map(move |(bank, base, bytes)| {
+            let count = (bytes.len() + 1).saturating_sub(size.len() as usize) as u16;
+            (0..count).map(move |offset| (*bank, base + offset))
+        })
+    }
+}
+
+/// Narrows down the locations holding a value, e.g. the number of lives, over several snapshots.
+///
+/// A search starts with every (bank, address) as a candidate, each call to `filter` then keeps the
+/// ones whose value compares as requested. Values are compared within the same bank, whichever
+/// banks the game had mapped when the snapshots were taken.
+#[derive(Debug, Clone)]
+pub struct MemorySearch {
+    size: ValueSize,
+    candidates: Vec<(usize, u16)>,
+    snapshot: MemorySnapshot,
+}
+
+impl MemorySearch {
+    /// Starts a new search.
+    ///
+    /// # Arguments
+    ///
+    /// * `size` - How values are read.
+    /// * `snapshot` - The initial state of memory, used as reference for the first `Previous` comparison.
+    pub fn new(size: ValueSize, snapshot: MemorySnapshot) -> Self {
+        let candidates = snapshot.locations(size).collect();
+        Self { size, candidates, snapshot }
+    }
+
+    /// Keeps only the candidates whose value in `snapshot` compares to `target` as requested.
+    /// `snapshot` then becomes the reference for the next `Previous` comparison.
+    ///
+    /// # Returns
+    ///
+    /// The number of remaining candidates.
+    pub fn filter(&mut self, snapshot: MemorySnapshot, comparison: Comparison, target: SearchTarget) -> usize {
+        let size = self.size;
+        let previous = &self.snapshot;
+        self.candidates.retain(|&(bank, addr)| {
+            let reference = match target {
+                SearchTarget::Previous => previous.value(bank, addr, size),
+                SearchTarget::Value(value) => Some(value),
+            };
+            match (snapshot.value(bank, addr, size), reference) {
+                (Some(value), Some(reference)) => comparison.matches(value, reference),
+                _ => false
+            }
+        });
+        self.snapshot = snapshot;
+        self.candidates.len()
+    }
+
+    pub fn size(&self) -> ValueSize {
+        self.size
+    }
+
+    /// Returns the remaining candidates as (bank, address).
+    pub fn candidates(&self) -> &[(usize, u16)] {
+        &self.candidates
+    }
+
+    /// Returns the value of a candidate in the last snapshot.
+    pub fn value(&self, bank: usize, addr: u16) -> Option<u16> {
+        self.snapshot.value(bank, addr, self.size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(wram: &[u8], hram: &[u8]) -> MemorySnapshot {
+        let mut snapshot = MemorySnapshot::new();
+        snapshot.add_region(0, 0xC000, wram.to_vec());
+        snapshot.add_region(0, 0xFF80, hram.to_vec());
+        snapshot
+    }
+
+    #[test]
+    fn narrows_down_candidates() {
+        let mut search = MemorySearch::new(ValueSize::U8, snapshot(&[3, 3, 0], &[3]));
+        assert_eq!(search.candidates().len(), 4);
+
+        assert_eq!(search.filter(snapshot(&[2, 3, 1], &[2]), Comparison::Less, SearchTarget::Previous), 2);
+        assert_eq!(search.filter(snapshot(&[2, 3, 1], &[1]), Comparison::NotEqual, SearchTarget::Previous), 1);
+        assert_eq!(search.candidates(), [(0, 0xFF80)]);
+        assert_eq!(search.value(0, 0xFF80), Some(1));
+    }
+
+    #[test]
+    fn sixteen_bit_values_stay_within_regions() {
+        let memory = snapshot(&[0x34, 0x12, 0x00], &[0x12]);
+        assert_eq!(memory.value(0, 0xC000, ValueSize::U16Le), Some(0x1234));
+        assert_eq!(memory.value(0, 0xC000, ValueSize::U16Be), Some(0x3412));
+        assert_eq!(memory.value(0, 0xC002, ValueSize::U16Le), None);
+
+        let mut search = MemorySearch::new(ValueSize::U16Le, memory);
+        assert_eq!(search.candidates(), [(0, 0xC000), (0, 0xC001)]);
+        assert_eq!(search.filter(snapshot(&[0x34, 0x12, 0x00], &[0]), Comparison::Equal, SearchTarget::Value(0x1234)), 1);
+    }
+}
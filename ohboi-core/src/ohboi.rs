@@ -2,20 +2,79 @@
 
 use std::cell::RefCell;
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 use crate::audio::{Apu};
 use crate::bus::Bus;
 use crate::cpu::{Cpu, Speed};
-use crate::ppu::{Ppu, PpuState};
+use crate::cpu::disassembler::{Disassembler, Instruction};
+use crate::ppu::{ColorCorrection, DmgPaletteId, DmgShades, Ppu, PpuState};
+use crate::ppu::compat::CompatPalettes;
 use crate::cpu::interrupts::InterruptController;
 use crate::joypad::{Joypad, Key};
-use crate::memory::cartridge::Cartridge;
+use crate::memory::cartridge::{AccelerometerInput, Cartridge};
+use crate::memory::cartridge::camera::CameraSource;
+use crate::memory::cartridge::info::CartridgeInfo;
+use crate::memory::cartridge::clock::{ClockSource, EmulatedClock, SystemClock};
 use crate::memory::dma::{DmaController, HdmaController, HdmaState};
+use crate::memory::search::MemorySnapshot;
+use crate::sgb::{Sgb, SGB_HEIGHT, SGB_WIDTH};
 use crate::timers::Timer;
 
 #[cfg(feature = "debugging")]
 use crate::cpu::Registers;
+#[cfg(feature = "debugging")]
+use crate::debug::symbols::SymbolTable;
+#[cfg(feature = "debugging")]
+use crate::debug::trace::Tracer;
+#[cfg(feature = "debugging")]
+use crate::debug::callstack::StackFrame;
+#[cfg(feature = "debugging")]
+use crate::debug::profiler::Profiler;
+#[cfg(feature = "debugging")]
+use crate::debug::cdl::{CodeDataLogger, Coverage};
+#[cfg(feature = "debugging")]
+use std::collections::BTreeSet;
+#[cfg(feature = "debugging")]
+use log::warn;
+
+/// What a debugger command is waiting for before stopping the emulation again.
+#[cfg(feature = "debugging")]
+#[derive(Debug, Clone, Copy)]
+enum StopCondition {
+    /// Stop at the next instruction.
+    NextInstruction,
+    /// Stop when the instruction at `addr` is reached with at most `max_depth` frames on the call stack.
+    Address { addr: u16, max_depth: usize },
+    /// Stop once the call stack is shallower than `depth`.
+    Return { depth: usize },
+    /// Stop once LY differs from `ly`.
+    Scanline { ly: u8 },
+    /// Stop when VBlank starts. `armed` is set once the PPU has been seen outside of VBlank.
+    Frame { armed: bool },
+}
+
+/// The hardware games run on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Model {
+    Dmg,
+    Sgb,
+    /// DMG games run in the compatibility mode, colorized by the boot ROM palettes.
+    Cgb,
+}
+
+impl Model {
+    /// The model a game is meant for: CGB for color games, SGB for games using its functions.
+    pub fn for_cartridge(cartridge: &Cartridge) -> Self {
+        if cartridge.is_cgb() {
+            Model::Cgb
+        } else if cartridge.is_sgb() {
+            Model::Sgb
+        } else {
+            Model::Dmg
+        }
+    }
+}
 
 macro_rules! rc_cell {
     ($s:ty) => {
@@ -39,16 +98,53 @@ pub struct GameBoy {
     hdma_controller: Option<Rc<RefCell<HdmaController>>>,
     timer: Rc<RefCell<Timer>>,
     cartridge: Rc<RefCell<Cartridge>>,
+    model: Model,
+    /// Present when the game runs on a Super Game Boy.
+    sgb: Option<Rc<RefCell<Sgb>>>,
     cycle_counter: u64,
     stopped: bool,
+    /// Cartridge clocks follow the emulated cycles instead of the wall clock.
+    deterministic: bool,
+    #[cfg(feature = "debugging")]
+    symbols: SymbolTable,
+    #[cfg(feature = "debugging")]
+    breakpoints: BTreeSet<u16>,
+    #[cfg(feature = "debugging")]
+    stop_condition: Option<StopCondition>,
+    #[cfg(feature = "debugging")]
+    last_instruction_count: u64,
+    #[cfg(feature = "debugging")]
+    profiler: Option<Profiler>,
 }
 
 impl GameBoy {
     pub fn new(rom_path: PathBuf) -> io::Result<Self> {
-        let cartridge = rc_cell_new!(Cartridge::open(rom_path)?);
+        Self::with_model(rom_path, None)
+    }
+
+    /// Creates a Game Boy of the given model, or of the one the game is meant for if `None`.
+    pub fn with_model(rom_path: PathBuf, model: Option<Model>) -> io::Result<Self> {
+        Self::with_model_and_patch(rom_path, None, model)
+    }
+
+    /// Creates a Game Boy of the given model, or of the one the game is meant for if `None`,
+    /// applying an IPS, UPS or BPS patch to the game in memory.
+    /// Without an explicit patch, one with the same name as the ROM is applied if there is one.
+    pub fn with_model_and_patch(rom_path: PathBuf, patch_path: Option<&Path>, model: Option<Model>) -> io::Result<Self> {
+        Self::with_clock(rom_path, patch_path, model, Box::new(SystemClock))
+    }
+
+    /// Creates a Game Boy like `with_model_and_patch`, with the cartridge RTC, if any, following `clock`.
+    fn with_clock(rom_path: PathBuf, patch_path: Option<&Path>, model: Option<Model>, clock: Box<dyn ClockSource>) -> io::Result<Self> {
+        #[cfg(feature = "debugging")]
+        let symbols = SymbolTable::for_rom(&rom_path);
+        let cartridge = rc_cell_new!(Cartridge::open_with_clock(rom_path, patch_path, clock)?);
         let interrupts = rc_cell_new!(InterruptController::new());
 
-        let is_cgb = (*cartridge).borrow().is_cgb();
+        let model = model.unwrap_or_else(|| Model::for_cartridge(&(*cartridge).borrow()));
+        // A DMG game on a CGB keeps the CGB registers, but the rest of the hardware runs as on a DMG
+        let cgb_hardware = model == Model::Cgb;
+        let is_cgb = cgb_hardware && (*cartridge).borrow().is_cgb();
         
         let timer = rc_cell_new!(Timer::new(Rc::clone(&interrupts)));
         let joypad = rc_cell_new!(Joypad::new(Rc::clone(&interrupts)));
@@ -59,7 +155,7 @@ impl GameBoy {
         let bus = Bus::new(Rc::clone(&ppu), Rc::clone(&apu), Rc::clone(&timer), Rc::clone(&joypad),
                                Rc::clone(&interrupts), Rc::clone(&cartridge));
         
-        let cpu = rc_cell_new!(Cpu::new(Rc::clone(&interrupts), Bus::get_controller(&bus), is_cgb));
+        let cpu = rc_cell_new!(Cpu::new(Rc::clone(&interrupts), Box::new(Bus::get_controller(&bus)), cgb_hardware));
         let dma = rc_cell_new!(DmaController::new(Bus::get_controller(&bus)));
         
         let hdma_controller = if is_cgb {
@@ -75,7 +171,48 @@ impl GameBoy {
                 b.set_hdma_controller(Rc::clone(hdma_controller.as_ref().unwrap()));
             }
         }
-        Ok(Self { joypad, bus, cpu, ppu, apu, dma, hdma_controller, timer, cartridge: Rc::clone(&cartridge), cycle_counter: 0, stopped: false })
+        let mut gb = Self { joypad, bus, cpu, ppu, apu, dma, hdma_controller, timer, cartridge: Rc::clone(&cartridge), model, sgb: None, cycle_counter: 0, stopped: false, deterministic: false,
+            #[cfg(feature = "debugging")]
+            symbols,
+            #[cfg(feature = "debugging")]
+            breakpoints: BTreeSet::new(),
+            #[cfg(feature = "debugging")]
+            stop_condition: None,
+            #[cfg(feature = "debugging")]
+            last_instruction_count: 0,
+            #[cfg(feature = "debugging")]
+            profiler: None };
+        gb.connect_model();
+        Ok(gb)
+    }
+
+    /// Sets up what the boot ROM of the model does for the loaded game: the SGB, or the
+    /// palettes of a DMG game on a CGB.
+    fn connect_model(&mut self) {
+        let cartridge = (*self.cartridge).borrow();
+        self.sgb = (self.model == Model::Sgb).then(|| rc_cell_new!(Sgb::new()));
+        (*self.joypad).borrow_mut().set_sgb(self.sgb.clone());
+        (*self.ppu).borrow_mut().set_sgb(self.sgb.clone());
+        if self.model == Model::Cgb && !cartridge.is_cgb() {
+            (*self.ppu).borrow_mut().set_dmg_compatibility(&CompatPalettes::for_rom(&cartridge[..0x150]));
+        }
+    }
+
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    /// Checks if a DMG game is running on a CGB.
+    pub fn is_dmg_compat(&self) -> bool {
+        self.model == Model::Cgb && !(*self.cartridge).borrow().is_cgb()
+    }
+
+    /// Replaces the palettes of a DMG game running on a CGB, e.g. with the ones of a button
+    /// combination, see `CompatPalettes::for_keys`. Does nothing in the other modes.
+    pub fn set_compat_palettes(&self, palettes: &CompatPalettes) {
+        if self.is_dmg_compat() {
+            (*self.ppu).borrow_mut().set_dmg_compatibility(palettes);
+        }
     }
 
     pub fn clock(&mut self) {
@@ -112,7 +249,14 @@ impl GameBoy {
             if !matches!(cpu_state, CpuState::Stopped(_) | CpuState::HdmaHalted) { (*self.timer).borrow_mut().clock(); }
             (*self.cpu).borrow_mut().clock();
         }
+        // Cartridge hardware runs in real time, unaffected by double speed
+        (*self.cartridge).borrow_mut().clock(1);
         self.cycle_counter += 4;
+        #[cfg(feature = "debugging")]
+        {
+            self.profile(clocks);
+            self.check_stop_conditions();
+        }
     }
 
     pub fn reset_cycle_counter(&mut self) {
@@ -127,8 +271,23 @@ impl GameBoy {
         self.cycle_counter
     }
 
+    /// Returns the last frame as RGBA, see `screen_size` for its dimensions.
+    /// On a Super Game Boy that is the colorized picture with its border.
     pub fn screen(&self) -> Vec<u8> {
-        (*self.ppu).borrow_mut().screen().to_owned()
+        match self.sgb {
+            Some(ref sgb) => (**sgb).borrow().frame().to_owned(),
+            None => (*self.ppu).borrow_mut().screen().to_owned()
+        }
+    }
+
+    /// Returns the width and height of the frames returned by `screen`.
+    pub fn screen_size(&self) -> (usize, usize) {
+        if self.sgb.is_some() { (SGB_WIDTH, SGB_HEIGHT) } else { (160, 144) }
+    }
+
+    /// Checks if the game is running on a Super Game Boy.
+    pub fn is_sgb(&self) -> bool {
+        self.sgb.is_some()
     }
 
     pub fn press(&self, key: Key) {
@@ -148,19 +307,199 @@ impl GameBoy {
     }
 
     pub fn load_new_game(&mut self, rom_path: PathBuf) -> io::Result<()> {
-        self.cartridge.replace(Cartridge::open(rom_path)?);
+        self.load_new_game_with_patch(rom_path, None)
+    }
 
-        (*self.bus).borrow_mut().reset();
-        (*self.cpu).borrow_mut().reset();
-        (*self.ppu).borrow_mut().reset();
-        (*self.apu).borrow_mut().reset();
-        (*self.timer).borrow_mut().reset();
+    /// Loads a game, applying an IPS, UPS or BPS patch in memory.
+    /// Without an explicit patch, one with the same name as the ROM is applied if there is one.
+    ///
+    /// The game runs on the model it is meant for, whatever the previous game ran on.
+    pub fn load_new_game_with_patch(&mut self, rom_path: PathBuf, patch_path: Option<&Path>) -> io::Result<()> {
+        self.reload(rom_path, patch_path, None)
+    }
 
+    /// Replaces the hardware with a new one of the given model, or of the one the game is meant
+    /// for if `None`, running the given game. The emulator settings and the debugger state are kept.
+    fn reload(&mut self, rom_path: PathBuf, patch_path: Option<&Path>, model: Option<Model>) -> io::Result<()> {
+        #[cfg(feature = "debugging")]
+        if let Err(e) = self.save_code_data_log() {
+            warn!("Failed to save code/data log: {}", e);
+        }
+        let mut gb = Self::with_clock(rom_path, patch_path, model, self.clock_source())?;
+        gb.deterministic = self.deterministic;
+        gb.stopped = self.stopped;
+        gb.set_color_correction(self.color_correction());
+        for id in [DmgPaletteId::Bg, DmgPaletteId::Obj0, DmgPaletteId::Obj1] {
+            gb.set_dmg_palette(id, self.dmg_palette(id));
+        }
+        {
+            let apu = (*self.apu).borrow();
+            let mut new_apu = (*gb.apu).borrow_mut();
+            new_apu.square1_enable = apu.square1_enable;
+            new_apu.square2_enable = apu.square2_enable;
+            new_apu.wave_enable = apu.wave_enable;
+            new_apu.noise_enable = apu.noise_enable;
+        }
+        #[cfg(feature = "debugging")]
+        {
+            gb.breakpoints = std::mem::take(&mut self.breakpoints);
+            gb.profiler = self.profiler.take();
+            let tracer = self.stop_trace();
+            (*gb.cpu).borrow_mut().set_tracer(tracer);
+        }
+        *self = gb;
         Ok(())
     }
     
     pub fn close_game(&self) {
-        (*self.cartridge).borrow().save();
+        (*self.cartridge).borrow_mut().save();
+        #[cfg(feature = "debugging")]
+        if let Err(e) = self.save_code_data_log() {
+            warn!("Failed to save code/data log: {}", e);
+        }
+    }
+
+    /// Sets the shades of a DMG palette, e.g. from a `DmgPalettePreset`. Only DMG games on a DMG
+    /// use them, the SGB and the CGB have their own palettes. Takes effect with the next pixel drawn.
+    pub fn set_dmg_palette(&self, palette: DmgPaletteId, shades: DmgShades) {
+        (*self.ppu).borrow_mut().set_dmg_shades(palette, shades);
+    }
+
+    pub fn dmg_palette(&self, palette: DmgPaletteId) -> DmgShades {
+        (*self.ppu).borrow().dmg_shades(palette)
+    }
+
+    /// Sets how the colors of CGB games, and of DMG games on a CGB, are adapted to the host screen.
+    pub fn set_color_correction(&self, correction: ColorCorrection) {
+        (*self.ppu).borrow_mut().set_color_correction(correction);
+    }
+
+    pub fn color_correction(&self) -> ColorCorrection {
+        (*self.ppu).borrow().color_correction()
+    }
+
+    /// Returns the path of the loaded ROM.
+    pub fn rom_path(&self) -> PathBuf {
+        (*self.cartridge).borrow().rom_path().to_path_buf()
+    }
+
+    /// Returns the patch explicitly applied to the loaded ROM, if any.
+    pub fn patch_path(&self) -> Option<PathBuf> {
+        (*self.cartridge).borrow().patch_path().map(Path::to_path_buf)
+    }
+
+    /// Returns the parsed header of the loaded cartridge.
+    pub fn cartridge_info(&self) -> io::Result<CartridgeInfo> {
+        (*self.cartridge).borrow().info()
+    }
+
+    pub fn rom_bank(&self) -> usize {
+        (*self.cartridge).borrow().rom_bank()
+    }
+
+    /// Feeds the tilt sensor of MBC7 cartridges. Ignored by other cartridges.
+    pub fn set_accelerometer(&self, input: AccelerometerInput) {
+        (*self.cartridge).borrow_mut().set_accelerometer(input);
+    }
+
+    /// Sets where the Game Boy Camera takes its pictures from. Ignored by other cartridges.
+    pub fn set_camera_source(&self, source: Box<dyn CameraSource>) {
+        (*self.cartridge).borrow_mut().set_camera_source(source);
+    }
+
+    /// Sets where the cartridge RTC takes the time from. Applies to the current game only.
+    pub fn set_clock_source(&self, clock: Box<dyn ClockSource>) {
+        (*self.cartridge).borrow_mut().set_clock_source(clock);
+    }
+
+    /// In deterministic mode the cartridge RTC counts emulated cycles from zero instead of
+    /// following the wall clock, so that runs can be reproduced. Kept when loading another game.
+    ///
+    /// The current game is saved and restarted, so that its RTC is restored from the save
+    /// under the new clock, without the time elapsed since the save counted in deterministic mode.
+    pub fn set_deterministic(&mut self, deterministic: bool) -> io::Result<()> {
+        if self.deterministic == deterministic {
+            return Ok(());
+        }
+        self.deterministic = deterministic;
+        self.close_game();
+        self.reload(self.rom_path(), self.patch_path().as_deref(), Some(self.model))
+    }
+
+    fn clock_source(&self) -> Box<dyn ClockSource> {
+        if self.deterministic {
+            Box::new(EmulatedClock::default())
+        } else {
+            Box::new(SystemClock)
+        }
+    }
+
+    pub fn is_deterministic(&self) -> bool {
+        self.deterministic
+    }
+
+    /// Returns how strongly the rumble motor of the cartridge ran since the last call, from 0.0
+    /// (off) to 1.0 (always on). Games vary the strength by pulsing the motor, so poll it once
+    /// per frame rather than sampling the motor state.
+    pub fn rumble(&self) -> f32 {
+        (*self.cartridge).borrow_mut().take_rumble()
+    }
+
+    /// Returns `true` while the speaker of the cartridge (HuC3) is beeping, e.g. when its alarm
+    /// goes off. The frontend plays the tone. Poll it once per frame.
+    pub fn tone(&self) -> bool {
+        (*self.cartridge).borrow().tone()
+    }
+
+    /// Disassembles `count` instructions starting at `addr` straight from the ROM image,
+    /// with `bank` mapped at 0x4000-0x7FFF regardless of the current MBC state.
+    pub fn disassemble(&self, bank: usize, addr: u16, count: usize) -> Vec<Instruction> {
+        let cartridge = (*self.cartridge).borrow();
+        let disassembler = Disassembler::new(cartridge.mbc.rom());
+        #[cfg(feature = "debugging")]
+        let cdl = cartridge.code_data_log();
+        #[cfg(feature = "debugging")]
+        let disassembler = match *cdl {
+            Some(ref cdl) => disassembler.with_code_data_log(cdl.as_bytes()),
+            None => disassembler
+        };
+        disassembler.disassemble(bank, addr, count)
+    }
+
+    /// Reads memory for debuggers and memory viewers, bypassing VRAM/OAM blocking, DMA and the MBC.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - The address to read from.
+    /// * `bank` - The ROM, VRAM, WRAM or cartridge RAM bank to read from, depending on `addr`,
+    ///   or `None` for the bank currently mapped there. Only switchable regions are affected:
+    ///   0x0000-0x3FFF and 0xC000-0xCFFF always access what is mapped there.
+    ///
+    /// # Returns
+    ///
+    /// The value at the specified address. Nonexistent memory reads as 0xFF.
+    pub fn peek(&self, addr: u16, bank: Option<usize>) -> u8 {
+        (*self.bus).borrow().peek(addr, bank)
+    }
+
+    /// Writes memory for debuggers and scripts, bypassing VRAM/OAM blocking, DMA and the MBC.
+    /// Writes to ROM patch the loaded image. I/O registers are written like the CPU would.
+    ///
+    /// # Arguments
+    ///
+    /// * `addr` - The address to write to.
+    /// * `bank` - The ROM, VRAM, WRAM or cartridge RAM bank to write to, depending on `addr`,
+    ///   or `None` for the bank currently mapped there. Only switchable regions are affected:
+    ///   0x0000-0x3FFF and 0xC000-0xCFFF always access what is mapped there.
+    /// * `val` - The value to write.
+    pub fn poke(&mut self, addr: u16, bank: Option<usize>, val: u8) {
+        (*self.bus).borrow_mut().poke(addr, bank, val)
+    }
+
+    /// Copies the RAM the game can store its state in (WRAM, cartridge RAM and HRAM),
+    /// to be searched with a `MemorySearch`.
+    pub fn snapshot_memory(&self) -> MemorySnapshot {
+        (*self.bus).borrow().ram_snapshot()
     }
 
     pub fn enable_audio_channel(&mut self, channel: u8, enable: bool) {
@@ -190,27 +529,220 @@ impl GameBoy {
 
     pub fn debug_stop(&mut self) {
         self.stopped = true;
+        self.stop_condition = None;
     }
 
     pub fn debug_continue(&mut self) {
         self.stopped = false;
+        self.stop_condition = None;
     }
 
-    pub fn debug_step(&mut self) {
+    /// Resumes the emulation until `condition` is met. The stepping commands below don't block:
+    /// the emulation stops by itself in `clock` once they are done.
+    fn resume_until(&mut self, condition: StopCondition) {
+        self.stop_condition = Some(condition);
         self.stopped = false;
-        let cur_pc = (*self.cpu).borrow().get_current_inst_pc();
-        while cur_pc == (*self.cpu).borrow().get_current_inst_pc() {
-            self.clock();
+    }
+
+    /// Executes the current instruction.
+    pub fn debug_step(&mut self) {
+        self.resume_until(StopCondition::NextInstruction);
+    }
+
+    /// Like `debug_step`, but runs CALL and RST instructions until they return.
+    pub fn debug_step_over(&mut self) {
+        // Decoded through the bus, as the code may run from RAM
+        let inst = {
+            let bus = (*self.bus).borrow();
+            crate::cpu::disassembler::decode(self.rom_bank(), self.get_current_instr_pc() as u16, |addr| bus.peek(addr, None))
+        };
+        if inst.is_call() {
+            let depth = (*self.cpu).borrow().call_stack().depth();
+            self.resume_until(StopCondition::Address { addr: inst.next_addr(), max_depth: depth });
+        } else {
+            self.debug_step();
+        }
+    }
+
+    /// Runs until the current subroutine or interrupt handler returns.
+    pub fn debug_step_out(&mut self) {
+        let depth = (*self.cpu).borrow().call_stack().depth();
+        if depth == 0 {
+            warn!("Not inside a subroutine, stepping instead");
+            self.debug_step();
+        } else {
+            self.resume_until(StopCondition::Return { depth });
+        }
+    }
+
+    /// Runs until the instruction at `addr` is reached.
+    pub fn debug_run_to(&mut self, addr: u16) {
+        self.resume_until(StopCondition::Address { addr, max_depth: usize::MAX });
+    }
+
+    /// Runs until LY changes.
+    pub fn debug_step_scanline(&mut self) {
+        let ly = (*self.ppu).borrow().ly();
+        self.resume_until(StopCondition::Scanline { ly });
+    }
+
+    /// Runs until the start of the next VBlank.
+    pub fn debug_step_frame(&mut self) {
+        let armed = (*self.ppu).borrow().ly() != 144;
+        self.resume_until(StopCondition::Frame { armed });
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.remove(&addr);
+    }
+
+    pub fn toggle_breakpoint(&mut self, addr: u16) {
+        if !self.breakpoints.remove(&addr) {
+            self.breakpoints.insert(addr);
+        }
+    }
+
+    pub fn breakpoints(&self) -> &BTreeSet<u16> {
+        &self.breakpoints
+    }
+
+    /// Returns the current call stack, outermost frame first.
+    pub fn call_stack(&self) -> Vec<StackFrame> {
+        (*self.cpu).borrow().call_stack().frames().to_vec()
+    }
+
+    /// Starts counting where cycles are spent, discarding any previous profile.
+    pub fn start_profiling(&mut self) {
+        self.profiler = Some(Profiler::new());
+    }
+
+    /// Stops profiling and returns the collected profile.
+    pub fn stop_profiling(&mut self) -> Option<Profiler> {
+        self.profiler.take()
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    /// Formats the current profile as text, labeling routines with the loaded symbols.
+    pub fn profile_report(&self, top: usize) -> Option<String> {
+        self.profiler.as_ref().map(|p| p.report(&self.symbols, top))
+    }
+
+    /// Starts recording how every ROM byte is accessed. Continues from the log saved next to the ROM, if any.
+    pub fn start_code_data_log(&self) {
+        (*self.cartridge).borrow().start_code_data_log();
+    }
+
+    pub fn stop_code_data_log(&self) -> Option<CodeDataLogger> {
+        (*self.cartridge).borrow().stop_code_data_log()
+    }
+
+    pub fn is_logging_code_data(&self) -> bool {
+        (*self.cartridge).borrow().code_data_log().is_some()
+    }
+
+    /// Returns how much of the ROM has been accessed so far, as a whole and per bank.
+    pub fn code_data_coverage(&self) -> Option<(Coverage, Vec<Coverage>)> {
+        let cartridge = (*self.cartridge).borrow();
+        let cdl = cartridge.code_data_log();
+        cdl.as_ref().map(|cdl| (cdl.coverage(), cdl.bank_coverage()))
+    }
+
+    /// Saves the code/data log next to the ROM, with the `.cdl` extension. Does nothing when not logging.
+    pub fn save_code_data_log(&self) -> io::Result<()> {
+        let cartridge = (*self.cartridge).borrow();
+        let cdl = cartridge.code_data_log();
+        match *cdl {
+            Some(ref cdl) => cdl.save(&cartridge.cdl_path()),
+            None => Ok(())
+        }
+    }
+
+    fn profile(&mut self, cycles: u64) {
+        if let Some(ref mut profiler) = self.profiler {
+            use crate::cpu::CpuState;
+            let rom_bank = (*self.cartridge).borrow().rom_bank();
+            let cpu = (*self.cpu).borrow();
+            let halted = matches!(cpu.state(), CpuState::Halted);
+            profiler.sample(cycles, cpu.get_current_inst_pc(), rom_bank, halted, cpu.call_stack().frames());
+        }
+    }
+
+    /// Stops the emulation when a breakpoint is hit or the pending debugger command is done.
+    /// Both are only checked when a new instruction starts.
+    fn check_stop_conditions(&mut self) {
+        let (count, pc, depth) = {
+            let cpu = (*self.cpu).borrow();
+            (cpu.instruction_count(), cpu.get_current_inst_pc(), cpu.call_stack().depth())
+        };
+        let new_instruction = count != self.last_instruction_count;
+        self.last_instruction_count = count;
+        let ly = (*self.ppu).borrow().ly();
+
+        let done = match self.stop_condition {
+            None => false,
+            Some(StopCondition::NextInstruction) => new_instruction,
+            Some(StopCondition::Address { addr, max_depth }) => new_instruction && pc == addr && depth <= max_depth,
+            Some(StopCondition::Return { depth: start_depth }) => new_instruction && depth < start_depth,
+            Some(StopCondition::Scanline { ly: start_ly }) => new_instruction && ly != start_ly,
+            Some(StopCondition::Frame { ref mut armed }) => {
+                *armed |= ly != 144;
+                new_instruction && *armed && ly == 144
+            }
+        };
+        if done || (new_instruction && self.breakpoints.contains(&pc)) {
+            self.stopped = true;
+            self.stop_condition = None;
         }
-        self.stopped = true;
     }
 
     pub fn get_cpu_registers(&self) -> Registers {
         (*self.cpu).borrow().get_registers()
     }
 
-    pub fn get_current_instruction_window(&self) -> Vec<(usize, String)> {
-        (*self.cpu).borrow().get_current_instructions(None)
+    /// Returns the instructions around the current PC, with their operands labeled.
+    pub fn get_current_instruction_window(&self) -> Vec<Instruction> {
+        let rom_bank = self.rom_bank();
+        let mut instructions = (*self.cpu).borrow().get_current_instructions(4, 16);
+        for inst in instructions.iter_mut() {
+            inst.text = self.symbols.annotate(inst, rom_bank);
+        }
+        instructions
+    }
+
+    /// Starts logging every executed instruction, replacing any running trace.
+    pub fn start_trace(&mut self, tracer: Tracer) {
+        (*self.cpu).borrow_mut().set_tracer(Some(tracer));
+    }
+
+    /// Stops tracing and returns the tracer, e.g. to read back its ring buffer.
+    pub fn stop_trace(&mut self) -> Option<Tracer> {
+        (*self.cpu).borrow_mut().set_tracer(None)
+    }
+
+    pub fn is_tracing(&self) -> bool {
+        (*self.cpu).borrow().is_tracing()
+    }
+
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    /// Replaces the current symbols with the ones in the given `.sym` or `.map` file.
+    pub fn load_symbols(&mut self, path: &Path) -> io::Result<()> {
+        self.symbols = SymbolTable::load(path)?;
+        Ok(())
+    }
+
+    /// Describes `addr` using the closest preceding label, taking the current ROM bank into account.
+    pub fn describe_address(&self, addr: u16) -> Option<String> {
+        self.symbols.describe(self.rom_bank(), addr)
     }
 
     pub fn get_current_instr_pc(&self) -> usize {
@@ -232,4 +764,239 @@ impl GameBoy {
 
         tiles
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::memory::search::{Comparison, MemorySearch, SearchTarget, ValueSize};
+
+    /// Opens a Game Boy running `program` from 0x0150, on an MBC5 with 4 banks of ROM and 4 of RAM.
+    /// `cgb` sets the CGB flag, so that the game runs on a CGB.
+    fn game_boy(name: &str, program: &[u8], cgb: bool) -> GameBoy {
+        game_boy_with_code(name, &[(0x150, program)], cgb)
+    }
+
+    /// Like `game_boy`, with each piece of code placed at its address.
+    fn game_boy_with_code(name: &str, code: &[(usize, &[u8])], cgb: bool) -> GameBoy {
+        let mut rom = vec![0; 0x10000];
+        rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+        for (addr, bytes) in code {
+            rom[*addr..*addr + bytes.len()].copy_from_slice(bytes);
+        }
+        rom[0x143] = if cgb { 0x80 } else { 0x00 };
+        rom[0x147] = 0x1A;
+        rom[0x148] = 0x01;
+        rom[0x149] = 0x03;
+        let path = std::env::temp_dir().join(format!("ohboi-{}-{}.gb", name, std::process::id()));
+        fs::write(&path, &rom).unwrap();
+        let gb = GameBoy::new(path.clone());
+        fs::remove_file(path).unwrap();
+        gb.unwrap()
+    }
+
+    #[test]
+    fn memory_search_compares_the_same_wram_bank_across_svbk_switches() {
+        let mut gb = game_boy("search", &[0x18, 0xFE], true);
+        gb.poke(0xFF70, None, 2);
+        gb.poke(0xD000, None, 7);
+        let mut search = MemorySearch::new(ValueSize::U8, gb.snapshot_memory());
+
+        // The game switches to bank 3 and writes there, while the value in bank 2 goes down
+        gb.poke(0xFF70, None, 3);
+        gb.poke(0xD000, None, 9);
+        gb.poke(0xD000, Some(2), 6);
+        search.filter(gb.snapshot_memory(), Comparison::Less, SearchTarget::Previous);
+        assert_eq!(search.candidates(), [(2, 0xD000)]);
+        assert_eq!(search.value(2, 0xD000), Some(6));
+    }
+
+    #[test]
+    fn memory_snapshot_holds_unmapped_banks() {
+        let mut gb = game_boy("snapshot", &[0x18, 0xFE], true);
+        gb.poke(0xD123, Some(5), 0x55);
+        gb.poke(0xB456, Some(3), 0x33);
+        let snapshot = gb.snapshot_memory();
+        assert_eq!(snapshot.read(5, 0xD123), Some(0x55));
+        assert_eq!(snapshot.read(1, 0xD123), Some(0x00));
+        assert_eq!(snapshot.read(3, 0xB456), Some(0x33));
+        assert_eq!(snapshot.read(4, 0xA000), None);
+    }
+
+    /// The state a game can observe: I/O registers, HRAM and the last frame.
+    fn observable_state(gb: &GameBoy) -> (Vec<u8>, Vec<u8>) {
+        ((0xFF00..=0xFFFF).map(|addr| gb.peek(addr, None)).collect(), gb.screen())
+    }
+
+    #[test]
+    fn peek_has_no_side_effects() {
+        // Copies the joypad, STAT, LY and the palette data to HRAM in a loop
+        let program = [0xF0, 0x00, 0xE0, 0x80, 0xF0, 0x41, 0xE0, 0x81, 0xF0, 0x44, 0xE0, 0x82, 0xF0, 0x69, 0xE0, 0x83, 0x18, 0xEE];
+        let mut peeked = game_boy("peeked", &program, true);
+        let mut reference = game_boy("reference", &program, true);
+        peeked.poke(0xFF68, None, 0x80);
+        reference.poke(0xFF68, None, 0x80);
+        for _ in 0..20000 {
+            peeked.clock();
+            reference.clock();
+            // Every register, including the joypad and the PPU ones, in every PPU mode
+            for addr in [0xFF00, 0xFF41, 0xFF44, 0xFF4D, 0xFF4F, 0xFF68, 0xFF69, 0xFF70, 0x8000, 0xFE00] {
+                peeked.peek(addr, None);
+            }
+        }
+        for addr in 0..=0xFFFF {
+            peeked.peek(addr, None);
+        }
+        for _ in 0..20000 {
+            peeked.clock();
+            reference.clock();
+        }
+        assert_eq!(observable_state(&peeked), observable_state(&reference));
+    }
+
+    #[test]
+    fn peek_reads_banks_that_are_not_mapped() {
+        let mut gb = game_boy("banks", &[0x18, 0xFE], true);
+        for (addr, bank) in [(0x8123, 1), (0xD123, 5), (0xA123, 2), (0x4123, 3)] {
+            gb.poke(addr, Some(bank), 0x5A);
+            assert_eq!(gb.peek(addr, Some(bank)), 0x5A);
+            assert_ne!(gb.peek(addr, None), 0x5A, "{:04X} is mapped to bank {}", addr, bank);
+        }
+        // There is a single bank below 0x4000
+        gb.poke(0x0123, Some(3), 0xA5);
+        assert_eq!(gb.peek(0x0123, None), 0xA5);
+        assert_eq!(gb.peek(0x4123, Some(3)), 0x5A);
+    }
+
+    #[test]
+    fn poke_to_rom_doesnt_write_mbc_registers() {
+        let mut gb = game_boy("poke", &[0x18, 0xFE], false);
+        gb.poke(0x2000, None, 0x03);
+        assert_eq!(gb.rom_bank(), 1);
+        assert_eq!(gb.peek(0x2000, None), 0x03);
+
+        // RAM stays disabled for the game, while the debugger can still write it
+        gb.poke(0x0000, None, 0x0A);
+        gb.poke(0xA000, None, 0x42);
+        assert_eq!((*gb.cartridge).borrow().read(0xA000), 0xFF);
+        assert_eq!(gb.peek(0xA000, None), 0x42);
+    }
+
+    /// Runs until the pending debugger command stops the emulation, for at most a few frames.
+    #[cfg(feature = "debugging")]
+    fn run_until_stopped(gb: &mut GameBoy) -> u16 {
+        for _ in 0..4 * 70224 {
+            if !gb.is_running() {
+                return gb.get_current_instr_pc() as u16;
+            }
+            gb.clock();
+        }
+        panic!("The emulation didn't stop");
+    }
+
+    /// Calls 0x0200, which calls 0x0210, then RST 08, in a loop.
+    #[cfg(feature = "debugging")]
+    fn calling_game_boy(name: &str) -> GameBoy {
+        let mut gb = game_boy_with_code(name, &[
+            (0x0008, &[0xC9]),                               // RET
+            (0x0150, &[0xCD, 0x00, 0x02, 0xCF, 0x00, 0x18, 0xF9]), // CALL 0200, RST 08, NOP, JR 0150
+            (0x0200, &[0xCD, 0x10, 0x02, 0xC9]),             // CALL 0210, RET
+            (0x0210, &[0x00, 0xC9]),                         // NOP, RET
+        ], false);
+        gb.debug_run_to(0x0150);
+        assert_eq!(run_until_stopped(&mut gb), 0x0150);
+        gb
+    }
+
+    #[cfg(feature = "debugging")]
+    #[test]
+    fn step_over_runs_calls_and_rst_until_they_return() {
+        let mut gb = calling_game_boy("step-over");
+        gb.debug_step_over();
+        assert_eq!(run_until_stopped(&mut gb), 0x0153);
+        assert!(gb.call_stack().is_empty());
+        gb.debug_step_over();
+        assert_eq!(run_until_stopped(&mut gb), 0x0154);
+        gb.debug_step_over();
+        assert_eq!(run_until_stopped(&mut gb), 0x0155);
+    }
+
+    #[cfg(feature = "debugging")]
+    #[test]
+    fn step_into_and_out_of_subroutines() {
+        let mut gb = calling_game_boy("step-out");
+        gb.debug_step();
+        assert_eq!(run_until_stopped(&mut gb), 0x0200);
+        gb.debug_step();
+        assert_eq!(run_until_stopped(&mut gb), 0x0210);
+        assert_eq!(gb.call_stack(), [
+            StackFrame { call_site: 0x0150, target: 0x0200, return_addr: 0x0153, sp: 0xFFFC, bank: 1, interrupt: false },
+            StackFrame { call_site: 0x0200, target: 0x0210, return_addr: 0x0203, sp: 0xFFFA, bank: 1, interrupt: false },
+        ]);
+
+        gb.debug_step_out();
+        assert_eq!(run_until_stopped(&mut gb), 0x0203);
+        assert_eq!(gb.call_stack().len(), 1);
+        gb.debug_step_out();
+        assert_eq!(run_until_stopped(&mut gb), 0x0153);
+        assert!(gb.call_stack().is_empty());
+
+        gb.debug_step();
+        assert_eq!(run_until_stopped(&mut gb), 0x0008);
+        assert_eq!(gb.call_stack(), [
+            StackFrame { call_site: 0x0153, target: 0x0008, return_addr: 0x0154, sp: 0xFFFC, bank: 1, interrupt: false },
+        ]);
+        // Outside of any subroutine, stepping out is a single step
+        gb.debug_step_out();
+        assert_eq!(run_until_stopped(&mut gb), 0x0154);
+        gb.debug_step_out();
+        assert_eq!(run_until_stopped(&mut gb), 0x0155);
+    }
+
+    #[cfg(feature = "debugging")]
+    #[test]
+    fn run_to_cursor_and_breakpoints_stop_at_their_address() {
+        let mut gb = calling_game_boy("run-to");
+        gb.debug_run_to(0x0210);
+        assert_eq!(run_until_stopped(&mut gb), 0x0210);
+        assert_eq!(gb.call_stack().len(), 2);
+
+        gb.add_breakpoint(0x0008);
+        gb.debug_continue();
+        assert_eq!(run_until_stopped(&mut gb), 0x0008);
+        // The breakpoint is hit again on the next loop
+        gb.debug_continue();
+        assert_eq!(run_until_stopped(&mut gb), 0x0008);
+        gb.remove_breakpoint(0x0008);
+        gb.debug_run_to(0x0154);
+        assert_eq!(run_until_stopped(&mut gb), 0x0154);
+    }
+
+    #[cfg(feature = "debugging")]
+    #[test]
+    fn interrupts_are_frames_and_stepping_follows_the_ppu() {
+        let mut gb = game_boy_with_code("interrupt", &[
+            (0x0040, &[0x00, 0xD9]),                         // NOP, RETI
+            (0x0150, &[0x3E, 0x01, 0xE0, 0xFF, 0xAF, 0xE0, 0x0F, 0xFB, 0x18, 0xFE]), // IE = VBlank, IF = 0, EI, JR $
+        ], false);
+        gb.debug_run_to(0x0040);
+        assert_eq!(run_until_stopped(&mut gb), 0x0040);
+        assert_eq!(gb.call_stack(), [
+            StackFrame { call_site: 0x0158, target: 0x0040, return_addr: 0x0158, sp: 0xFFFC, bank: 1, interrupt: true },
+        ]);
+        assert_eq!((*gb.ppu).borrow().ly(), 144);
+        gb.debug_step_out();
+        assert_eq!(run_until_stopped(&mut gb), 0x0158);
+        assert!(gb.call_stack().is_empty());
+
+        gb.debug_step_scanline();
+        run_until_stopped(&mut gb);
+        assert_eq!((*gb.ppu).borrow().ly(), 145);
+        // A whole frame, up to the start of the next VBlank
+        gb.debug_step_frame();
+        run_until_stopped(&mut gb);
+        assert_eq!((*gb.ppu).borrow().ly(), 144);
+        // Stopping at the first instruction of the line, before the VBlank interrupt is served
+        assert_eq!(gb.get_current_instr_pc(), 0x0158);
+    }
+}
@@ -0,0 +1,164 @@
+// Copyright Antonio Porsia 2025. Licensed under the EUPL-1.2 or later.
+
+//! The palettes the CGB boot ROM assigns to DMG games, picked from a hash of the title
+//! or with a button combination while the logo is shown.
+
+use crate::joypad::Key;
+
+/// Sums of the title bytes of the Nintendo games the boot ROM knows. From index
+/// `FIRST_DUPLICATE` on, the 4th letter of the title tells games with the same sum apart.
+const TITLE_CHECKSUMS: [u8; 94] = [
+    0x00, 0x88, 0x16, 0x36, 0xD1, 0xDB, 0xF2, 0x3C, 0x8C, 0x92, 0x3D, 0x5C, 0x58, 0xC9, 0x3E, 0x70,
+    0x1D, 0x59, 0x69, 0x19, 0x35, 0xA8, 0x14, 0xAA, 0x75, 0x95, 0x99, 0x34, 0x6F, 0x15, 0xFF, 0x97,
+    0x4B, 0x90, 0x17, 0x10, 0x39, 0xF7, 0xF6, 0xA2, 0x49, 0x4E, 0x43, 0x68, 0xE0, 0x8B, 0xF0, 0xCE,
+    0x0C, 0x29, 0xE8, 0xB7, 0x86, 0x9A, 0x52, 0x01, 0x9D, 0x71, 0x9C, 0xBD, 0x5D, 0x6D, 0x67, 0x3F,
+    0x6B,
+    0xB3, 0x46, 0x28, 0xA5, 0xC6, 0xD3, 0x27, 0x61, 0x18, 0x66, 0x6A, 0xBF, 0x0D, 0xF4,
+    0xB3, 0x46, 0x28, 0xA5, 0xC6, 0xD3, 0x27, 0x61, 0x18, 0x66, 0x6A, 0xBF, 0x0D, 0xF4,
+    0xB3,
+];
+const FIRST_DUPLICATE: usize = 65;
+const FOURTH_LETTERS: &[u8; 29] = b"BEFAARBEKEK R-URAR INAILICE R";
+
+/// The palette combination of each entry of `TITLE_CHECKSUMS`.
+const TITLE_COMBINATIONS: [u8; 94] = [
+    0, 4, 5, 35, 34, 3, 31, 15, 10, 5, 19, 36, 7, 37, 30, 44,
+    21, 32, 31, 20, 5, 33, 13, 14, 5, 29, 5, 18, 9, 3, 2, 26,
+    25, 25, 41, 42, 26, 45, 42, 45, 36, 38, 26, 42, 30, 41, 34, 34,
+    5, 42, 6, 5, 33, 25, 42, 42, 40, 2, 16, 25, 42, 42, 5, 0,
+    39,
+    36, 22, 25, 6, 32, 12, 36, 11, 39, 18, 39, 24, 31, 50,
+    17, 46, 6, 27, 0, 47, 41, 41, 0, 0, 19, 34, 23, 18, 29,
+];
+
+/// Where OBJ0, OBJ1 and BG take their 4 colors in `COLORS`. Most start on a palette boundary,
+/// a few straddle two palettes as they do in the boot ROM.
+const COMBINATIONS: [[usize; 3]; 51] = [
+    [16, 16, 116], [72, 72, 72], [80, 80, 80], [96, 96, 96], [36, 36, 36], [0, 0, 0],
+    [108, 108, 108], [20, 20, 20], [48, 48, 48], [104, 104, 104], [64, 32, 32], [16, 112, 112],
+    [16, 8, 8], [12, 16, 16], [16, 116, 116], [112, 16, 112], [8, 68, 8], [64, 64, 32],
+    [16, 16, 28], [16, 16, 72], [16, 16, 80], [76, 76, 36], [15, 15, 44], [68, 68, 8],
+    [16, 16, 8], [16, 16, 12], [112, 112, 0], [12, 12, 0], [0, 0, 4], [72, 88, 72],
+    [80, 88, 80], [96, 88, 96], [64, 88, 32], [68, 16, 52], [111, 0, 56], [111, 16, 60],
+    [76, 88, 36], [64, 112, 40], [16, 92, 112], [68, 88, 8], [16, 0, 8], [16, 112, 12],
+    [112, 12, 0], [12, 112, 16], [84, 112, 16], [12, 112, 0], [100, 12, 112], [0, 112, 32],
+    [16, 12, 112], [112, 12, 24], [16, 112, 116],
+];
+
+/// The palettes of the boot ROM in RGB555, 4 colors each.
+const COLORS: [u16; 120] = [
+    0x7FFF, 0x32BF, 0x00D0, 0x0000, 0x639F, 0x4279, 0x15B0, 0x04CB,
+    0x7FFF, 0x6E31, 0x454A, 0x0000, 0x7FFF, 0x1BEF, 0x0200, 0x0000,
+    0x7FFF, 0x421F, 0x1CF2, 0x0000, 0x7FFF, 0x5294, 0x294A, 0x0000,
+    0x7FFF, 0x03FF, 0x012F, 0x0000, 0x7FFF, 0x03EF, 0x01D6, 0x0000,
+    0x7FFF, 0x42B5, 0x3DC8, 0x0000, 0x7E74, 0x03FF, 0x0180, 0x0000,
+    0x67FF, 0x77AC, 0x1A13, 0x2D6B, 0x7ED6, 0x4BFF, 0x2175, 0x0000,
+    0x53FF, 0x4A5F, 0x7E52, 0x0000, 0x4FFF, 0x7ED2, 0x3A4C, 0x1CE0,
+    0x03ED, 0x7FFF, 0x255F, 0x0000, 0x036A, 0x021F, 0x03FF, 0x7FFF,
+    0x7FFF, 0x01DF, 0x0112, 0x0000, 0x231F, 0x035F, 0x00F2, 0x0009,
+    0x7FFF, 0x03EA, 0x011F, 0x0000, 0x299F, 0x001A, 0x000C, 0x0000,
+    0x7FFF, 0x027F, 0x001F, 0x0000, 0x7FFF, 0x03E0, 0x0206, 0x0120,
+    0x7FFF, 0x7EEB, 0x001F, 0x7C00, 0x7FFF, 0x3FFF, 0x7E00, 0x001F,
+    0x7FFF, 0x03FF, 0x001F, 0x0000, 0x03FF, 0x001F, 0x000C, 0x0000,
+    0x7FFF, 0x033F, 0x0193, 0x0000, 0x0000, 0x4200, 0x037F, 0x7FFF,
+    0x7FFF, 0x7E8C, 0x7C00, 0x0000, 0x7FFF, 0x1BEF, 0x6180, 0x0000,
+];
+
+/// The combination used for games without a Nintendo licensee or with an unknown title.
+const DEFAULT_COMBINATION: usize = 0;
+
+/// The BG, OBJ0 and OBJ1 palettes, in RGB555, of a DMG game running on a CGB.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompatPalettes {
+    pub bg: [u16; 4],
+    pub obj0: [u16; 4],
+    pub obj1: [u16; 4],
+}
+
+impl CompatPalettes {
+    fn from_combination(index: usize) -> Self {
+        let [obj0, obj1, bg] = COMBINATIONS[index];
+        let palette = |offset: usize| -> [u16; 4] { COLORS[offset..offset + 4].try_into().unwrap() };
+        Self { bg: palette(bg), obj0: palette(obj0), obj1: palette(obj1) }
+    }
+
+    /// Picks the palettes the boot ROM would for a game. Only Nintendo games are looked up,
+    /// by the sum of the 16 title bytes and, for some sums, the 4th letter of the title.
+    ///
+    /// # Arguments
+    ///
+    /// * `rom` - The ROM image, at least as large as the header.
+    ///
+    /// # Returns
+    ///
+    /// The palettes of the game, or the default ones.
+    pub fn for_rom(rom: &[u8]) -> Self {
+        let nintendo = match rom[0x14B] {
+            0x01 => true,
+            0x33 => &rom[0x144..0x146] == b"01",
+            _ => false
+        };
+        if !nintendo {
+            return Self::from_combination(DEFAULT_COMBINATION);
+        }
+        let checksum = rom[0x134..0x144].iter().fold(0u8, |acc, &b| acc.wrapping_add(b));
+        let fourth_letter = rom[0x137];
+        let combination = TITLE_CHECKSUMS.iter()
+            .enumerate()
+            .position(|(i, &c)| c == checksum && (i < FIRST_DUPLICATE || FOURTH_LETTERS[i - FIRST_DUPLICATE] == fourth_letter))
+            .map_or(DEFAULT_COMBINATION, |i| TITLE_COMBINATIONS[i] as usize);
+        Self::from_combination(combination)
+    }
+
+    /// The palettes selected by holding a direction, optionally with A or B, during the boot logo.
+    ///
+    /// # Returns
+    ///
+    /// The palettes, or `None` if the keys are not a valid combination.
+    pub fn for_keys(direction: Key, button: Option<Key>) -> Option<Self> {
+        let combination = match (direction, button) {
+            (Key::Right, None) => 1,
+            (Key::Left, None) => 48,
+            (Key::Up, None) => 5,
+            (Key::Down, None) => 8,
+            (Key::Right, Some(Key::A)) => 0,
+            (Key::Left, Some(Key::A)) => 40,
+            (Key::Up, Some(Key::A)) => 43,
+            (Key::Down, Some(Key::A)) => 3,
+            (Key::Right, Some(Key::B)) => 6,
+            (Key::Left, Some(Key::B)) => 7,
+            (Key::Up, Some(Key::B)) => 28,
+            (Key::Down, Some(Key::B)) => 49,
+            _ => return None
+        };
+        Some(Self::from_combination(combination))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom(title: &[u8], licensee: u8) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x134..0x134 + title.len()].copy_from_slice(title);
+        rom[0x14B] = licensee;
+        rom
+    }
+
+    #[test]
+    fn palettes_follow_title_and_keys() {
+        let tetris = CompatPalettes::for_rom(&rom(b"TETRIS", 0x01));
+        assert_eq!(tetris.bg, [0x7FFF, 0x03FF, 0x001F, 0x0000]);
+        // Same sum as other titles, told apart by the 4th letter
+        let blue = CompatPalettes::for_rom(&rom(b"POKEMON BLUE", 0x01));
+        assert_eq!(blue.bg, [0x7FFF, 0x7E8C, 0x7C00, 0x0000]);
+        assert_eq!(blue.obj0, [0x7FFF, 0x421F, 0x1CF2, 0x0000]);
+
+        let default = CompatPalettes::from_combination(DEFAULT_COMBINATION);
+        assert_eq!(CompatPalettes::for_rom(&rom(b"TETRIS", 0x0A)), default);
+        assert_eq!(CompatPalettes::for_keys(Key::Right, Some(Key::A)), Some(default));
+        assert_eq!(CompatPalettes::for_keys(Key::Left, Some(Key::B)).unwrap().bg, [0x7FFF, 0x5294, 0x294A, 0x0000]);
+        assert_eq!(CompatPalettes::for_keys(Key::A, None), None);
+    }
+}
@@ -1,5 +1,6 @@
 // Copyright Antonio Porsia 2025. Licensed under the EUPL-1.2 or later.
 
+pub mod compat;
 mod fifo;
 mod palettes;
 mod oam;
@@ -12,11 +13,14 @@ use log::{trace, warn};
 use fifo::{PixelFetcher, TilePixel};
 use oam::Sprite;
 use palettes::DmgPalette;
+pub use palettes::{ColorCorrection, DmgPalettePreset, DmgShades};
 use vram::Tile;
 use crate::cpu::interrupts::{Interrupt, InterruptController};
 use crate::ppu::oam::Oam;
-use crate::ppu::palettes::CgbPalette;
+use crate::ppu::palettes::{CgbPalette, CgbPaletteColor};
 use crate::ppu::vram::Vram;
+use crate::sgb::Sgb;
+use compat::CompatPalettes;
 
 const WIDTH: usize = 160;
 const HEIGHT: usize = 144;
@@ -68,6 +72,24 @@ mod dmg_palettes {
     pub(crate) const OBJ1: usize = 2;
 }
 
+/// The DMG palettes: BGP, OBP0 and OBP1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DmgPaletteId {
+    Bg,
+    Obj0,
+    Obj1,
+}
+
+impl DmgPaletteId {
+    fn index(self) -> usize {
+        match self {
+            DmgPaletteId::Bg => dmg_palettes::BG,
+            DmgPaletteId::Obj0 => dmg_palettes::OBJ0,
+            DmgPaletteId::Obj1 => dmg_palettes::OBJ1,
+        }
+    }
+}
+
 struct Window {
     x: u8,
     y: u8,
@@ -78,6 +100,8 @@ struct Window {
 pub struct Ppu {
     interrupt_controller: Rc<RefCell<InterruptController>>,
     screen: [u8; WIDTH * HEIGHT * 4],
+    /// The DMG shade (0-3) of every pixel, colorized by the Super Game Boy.
+    shades: [u8; WIDTH * HEIGHT],
     vram: Vram,
     oam: Oam,
     pub (crate) state: PpuState,
@@ -97,7 +121,13 @@ pub struct Ppu {
     vram_bank: u8,
     cgb_bg_pal: Option<CgbPalette>,
     cgb_obj_pal: Option<CgbPalette>,
-    cgb: bool
+    color_correction: ColorCorrection,
+    cgb: bool,
+    /// OPRI, bit 0 set when objects are prioritized by X coordinate as on the DMG.
+    opri: u8,
+    /// DMG game running on a CGB: the DMG shades pick colors from the CGB palettes.
+    dmg_compat: bool,
+    sgb: Option<Rc<RefCell<Sgb>>>
 }
 
 impl Ppu {
@@ -108,6 +138,7 @@ impl Ppu {
         Self {
             interrupt_controller,
             screen: [0; WIDTH * HEIGHT * 4],
+            shades: [0; WIDTH * HEIGHT],
             vram: Vram::new(cgb),
             oam: Oam::new(),
             state: PpuState::VBlank,
@@ -126,7 +157,11 @@ impl Ppu {
             pixel_fetcher: PixelFetcher::new(),
             cgb_bg_pal,
             cgb_obj_pal,
-            cgb
+            color_correction: ColorCorrection::default(),
+            cgb,
+            opri: if cgb { 0 } else { 1 },
+            dmg_compat: false,
+            sgb: None
         }
     }
 
@@ -149,11 +184,21 @@ impl Ppu {
         self.window.rendering = true;
         self.window.internal_line_counter = 0;
 
-        self.dmg_palettes = [
-            DmgPalette::new(0xFC),
-            DmgPalette::new(0xFF),
-            DmgPalette::new(0xFF)
-        ];
+        // The shades are a setting of the emulator, they survive the reset
+        for (palette, value) in self.dmg_palettes.iter_mut().zip([0xFC, 0xFF, 0xFF]) {
+            palette.update_palette(value);
+        }
+        // The compatibility mode is set up again by the boot ROM, for DMG games only
+        let correction = self.color_correction;
+        let new_palette = || {
+            let mut palette = CgbPalette::new();
+            palette.set_correction(correction);
+            palette
+        };
+        self.cgb_bg_pal = self.cgb.then(new_palette);
+        self.cgb_obj_pal = self.cgb.then(new_palette);
+        self.opri = if self.cgb { 0 } else { 1 };
+        self.dmg_compat = false;
         self.vram_bank = 0;
         self.current_pixel = 0;
         self.scanline_counter = 0;
@@ -161,6 +206,7 @@ impl Ppu {
         self.sprites.clear();
 
         self.screen = [0; WIDTH * HEIGHT * 4];
+        self.shades = [0; WIDTH * HEIGHT];
         self.vram.reset();
         self.oam.reset();
     }
@@ -198,6 +244,31 @@ impl Ppu {
         }
     }
 
+    fn vram_index(&self, addr: u16, bank: Option<usize>) -> usize {
+        (bank.unwrap_or(self.vram.vram_bank()) & 1) * 0x2000 + (addr - 0x8000) as usize
+    }
+
+    /// Reads VRAM or OAM regardless of the PPU mode. `bank` selects the VRAM bank, `None` is the active one.
+    pub(crate) fn peek(&self, addr: u16, bank: Option<usize>) -> u8 {
+        match addr {
+            0x8000..=0x9FFF => self.vram[self.vram_index(addr, bank)],
+            0xFE00..=0xFE9F => self.oam.read(addr - 0xFE00),
+            _ => self.read(addr, false)
+        }
+    }
+
+    /// Writes VRAM or OAM regardless of the PPU mode. `bank` selects the VRAM bank, `None` is the active one.
+    pub(crate) fn poke(&mut self, addr: u16, bank: Option<usize>, val: u8) {
+        match addr {
+            0x8000..=0x9FFF => {
+                let index = self.vram_index(addr, bank);
+                self.vram[index] = val;
+            },
+            0xFE00..=0xFE9F => self.oam.write(addr - 0xFE00, val),
+            _ => self.write(addr, val, false)
+        }
+    }
+
     pub fn read(&self, addr: u16, dma: bool) -> u8 {
         match addr {
             0x8000..=0x9FFF => self.read_vram(addr - 0x8000),
@@ -213,11 +284,13 @@ impl Ppu {
             0xFF49 => self.dmg_palettes[dmg_palettes::OBJ1].value,
             0xFF4A => self.window.y,
             0xFF4B => self.window.x,
+            0xFF4C => 0xFF,
             0xFF4F => self.vram.vram_bank() as u8 | 0xFE,
             0xFF68 => self.cgb_bg_pal.as_ref().map_or(0xFF, |pal| pal.index.0),
             0xFF69 => self.cgb_bg_pal.as_ref().map_or(0xFF, |pal| pal.read_data()),
             0xFF6A => self.cgb_obj_pal.as_ref().map_or(0xFF, |pal| pal.index.0),
             0xFF6B => self.cgb_obj_pal.as_ref().map_or(0xFF, |pal| pal.read_data()),
+            0xFF6C if self.cgb || self.dmg_compat => self.opri | 0xFE,
             _ => {
                 warn!("Read from unimplemented I/O port: {:04X}", addr);
                 0xFF
@@ -243,6 +316,8 @@ impl Ppu {
             0xFF47..=0xFF49 => self.dmg_palettes[(addr - 0xFF47) as usize].update_palette(val),
             0xFF4A => self.window.y = val,
             0xFF4B => self.window.x = val,
+            // KEY0 is locked by the boot ROM
+            0xFF4C => {},
             0xFF4F if self.cgb => self.vram.set_vram_bank(val as usize & 1),
             0xFF68 => if let Some(ref mut pal) = self.cgb_bg_pal { pal.write_index(val) },
             0xFF69 => if let Some(ref mut pal) = self.cgb_bg_pal { 
@@ -260,6 +335,7 @@ impl Ppu {
                     pal.write_data(val)
                 }
             },
+            0xFF6C if self.cgb => self.opri = val & 1,
             _ => warn!("Write of value {:02X} to unimplemented I/O port: {:04X}", val, addr)
         }
     }
@@ -302,6 +378,7 @@ impl Ppu {
     fn hblank(&mut self) {
         self.advance_scanline();
         if self.ly == 144 {
+            self.finish_sgb_frame();
             (*self.interrupt_controller).borrow_mut().raise(Interrupt::Vblank);
             self.update_state(PpuState::VBlank);
         } else {
@@ -332,7 +409,7 @@ impl Ppu {
                 break;
             }
         }
-        if !self.cgb {
+        if self.opri & 1 != 0 {
             self.sprites.sort_by(|a, b| a.x.cmp(&b.x));
         }
 
@@ -396,6 +473,7 @@ impl Ppu {
                     _ => TilePixel::default()
             };
             let mut color = tile_pixel.color;
+            let mut dmg_palette = dmg_palettes::BG;
             let mut palette = if self.cgb {
                 self.cgb_bg_pal.as_ref().unwrap().color_array(tile_pixel.palette as usize)
             } else {
@@ -410,13 +488,21 @@ impl Ppu {
                         }
                     } else if !self.lcdc.bg_window_enable_priority() || sprite_pixel.pixel.priority || tile_pixel.color == 0 {
                         color = sprite_pixel.pixel.color;
-                        palette = self.dmg_palettes[sprite_pixel.pixel.palette as usize + 1].colors().to_owned();
+                        dmg_palette = sprite_pixel.pixel.palette as usize + 1;
+                        palette = self.dmg_palettes[dmg_palette].colors().to_owned();
                     }
                 }
             }
 
-            let pixel = (self.ly * 160 + self.current_pixel as usize) * 4;
-            self.screen[pixel..pixel+4].copy_from_slice(&palette[color as usize]);
+            let pixel = self.ly * 160 + self.current_pixel as usize;
+            let shade = (self.dmg_palettes[dmg_palette].value >> (color * 2)) & 0x3;
+            self.shades[pixel] = shade;
+            if self.dmg_compat {
+                let compat_palette = if dmg_palette == dmg_palettes::BG { &self.cgb_bg_pal } else { &self.cgb_obj_pal };
+                palette = compat_palette.as_ref().unwrap().color_array(dmg_palette.saturating_sub(1));
+                color = shade;
+            }
+            self.screen[pixel * 4..pixel * 4 + 4].copy_from_slice(&palette[color as usize]);
             self.advance_x();
         }
     }
@@ -469,7 +555,85 @@ impl Ppu {
     pub fn screen(&self) -> &[u8] {
         &self.screen
     }
+
+    /// Sets the shades a DMG palette maps its colors to. They are used from the next pixel drawn.
+    pub fn set_dmg_shades(&mut self, palette: DmgPaletteId, shades: DmgShades) {
+        self.dmg_palettes[palette.index()].set_shades(shades);
+    }
+
+    pub fn dmg_shades(&self, palette: DmgPaletteId) -> DmgShades {
+        self.dmg_palettes[palette.index()].shades()
+    }
+
+    /// Sets how the CGB palette colors are converted, from the next pixel drawn.
+    pub fn set_color_correction(&mut self, correction: ColorCorrection) {
+        self.color_correction = correction;
+        for palette in [&mut self.cgb_bg_pal, &mut self.cgb_obj_pal].into_iter().flatten() {
+            palette.set_correction(correction);
+        }
+    }
+
+    pub fn color_correction(&self) -> ColorCorrection {
+        self.color_correction
+    }
+
+    /// Runs a DMG game as a CGB does, as set up by its boot ROM: KEY0 selects the DMG
+    /// compatibility mode, OPRI the DMG object priority, and the palettes colorize the shades.
+    pub fn set_dmg_compatibility(&mut self, palettes: &CompatPalettes) {
+        let mut bg = CgbPalette::new();
+        let mut obj = CgbPalette::new();
+        for i in 0..4 {
+            bg.set_color(0, i, CgbPaletteColor(palettes.bg[i]));
+            obj.set_color(0, i, CgbPaletteColor(palettes.obj0[i]));
+            obj.set_color(1, i, CgbPaletteColor(palettes.obj1[i]));
+        }
+        bg.set_correction(self.color_correction);
+        obj.set_correction(self.color_correction);
+        self.cgb_bg_pal = Some(bg);
+        self.cgb_obj_pal = Some(obj);
+        self.opri = 1;
+        self.dmg_compat = true;
+    }
+
+    /// Connects the Super Game Boy that colorizes the frames, `None` to go back to a plain DMG.
+    pub fn set_sgb(&mut self, sgb: Option<Rc<RefCell<Sgb>>>) {
+        self.sgb = sgb;
+    }
+
+    /// Hands the finished frame to the Super Game Boy, after any VRAM transfer it is waiting for.
+    fn finish_sgb_frame(&self) {
+        let Some(ref sgb) = self.sgb else {
+            return;
+        };
+        let mut sgb = (**sgb).borrow_mut();
+        if sgb.transfer_pending() {
+            sgb.transfer(&self.sgb_transfer_data());
+        }
+        sgb.render(&self.shades);
+    }
+
+    /// The 4 KiB sent by the *_TRN commands: the first 256 tiles of the background map,
+    /// 20 per row, as the game lays them out on screen.
+    fn sgb_transfer_data(&self) -> Vec<u8> {
+        let tilemap = if self.lcdc.bg_tile_map() { 0x1C00 } else { 0x1800 };
+        let mut data = Vec::with_capacity(0x1000);
+        for i in 0..256 {
+            let tile = self.vram[tilemap + (i / 20) * 32 + i % 20];
+            let addr = if self.lcdc.bg_window_tile_data() {
+                tile as usize * 16
+            } else {
+                (0x1000 + (tile as i8 as isize) * 16) as usize
+            };
+            data.extend_from_slice(&self.vram[addr..addr + 16]);
+        }
+        data
+    }
     
+    #[cfg(feature = "debugging")]
+    pub fn ly(&self) -> u8 {
+        self.ly as u8
+    }
+
     #[cfg(feature = "debugging")]
     pub fn get_tileset0(&self) -> Vec<u8> {
         let mut res = Vec::new();
@@ -519,3 +683,82 @@ impl Ppu {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PALETTES: CompatPalettes = CompatPalettes {
+        bg: [0x7FFF, 0x03FF, 0x001F, 0x0000],
+        obj0: [0x7FFF, 0x7E8C, 0x7C00, 0x0000],
+        obj1: [0x7FFF, 0x421F, 0x1CF2, 0x0000],
+    };
+
+    fn dmg_compat_ppu() -> Ppu {
+        let mut ppu = Ppu::new(Rc::new(RefCell::new(InterruptController::new())), false);
+        ppu.set_dmg_compatibility(&PALETTES);
+        ppu
+    }
+
+    /// Runs until a whole frame has been drawn, whatever line the PPU starts on.
+    fn draw_frame(ppu: &mut Ppu) {
+        for _ in 0..2 * 154 * 456 / 4 {
+            ppu.clock();
+        }
+    }
+
+    fn pixel(ppu: &Ppu, x: usize, y: usize) -> [u8; 4] {
+        let offset = (y * WIDTH + x) * 4;
+        ppu.screen()[offset..offset + 4].try_into().unwrap()
+    }
+
+    fn rgba(color: u16) -> [u8; 4] {
+        ColorCorrection::default().apply(CgbPaletteColor(color))
+    }
+
+    #[test]
+    fn dmg_compat_colorizes_shades_through_bg_palette() {
+        let mut ppu = dmg_compat_ppu();
+        // Tile 0, used by the whole tile map, is filled with color 1
+        for row in 0..8 {
+            ppu.poke(0x8000 + row * 2, None, 0xFF);
+        }
+        // Color 1 is shade 2, which picks the 3rd color of the palette
+        ppu.write(0xFF47, 0b00_00_10_00, false);
+        draw_frame(&mut ppu);
+        assert_eq!(pixel(&ppu, 0, 0), rgba(PALETTES.bg[2]));
+        assert_eq!(pixel(&ppu, 159, 143), rgba(PALETTES.bg[2]));
+    }
+
+    #[test]
+    fn dmg_compat_objects_pick_palette_from_obp_number() {
+        let mut ppu = dmg_compat_ppu();
+        for row in 0..16 {
+            ppu.poke(0x8010 + row, None, 0xFF);
+        }
+        // Tile 1 in color 3 on OBP1 at x = 0, and on OBP0 at x = 8
+        for (i, (x, attributes)) in [(8, 0x10), (16, 0x00)].into_iter().enumerate() {
+            let oam = 0xFE00 + i as u16 * 4;
+            ppu.poke(oam, None, 16);
+            ppu.poke(oam + 1, None, x);
+            ppu.poke(oam + 2, None, 1);
+            ppu.poke(oam + 3, None, attributes);
+        }
+        ppu.write(0xFF48, 0b01_00_00_00, false);
+        ppu.write(0xFF49, 0b10_00_00_00, false);
+        ppu.write(0xFF40, 0x93, false);
+        draw_frame(&mut ppu);
+        assert_eq!(pixel(&ppu, 0, 0), rgba(PALETTES.obj1[2]));
+        assert_eq!(pixel(&ppu, 8, 0), rgba(PALETTES.obj0[1]));
+        assert_eq!(pixel(&ppu, 16, 0), rgba(PALETTES.bg[0]));
+    }
+
+    #[test]
+    fn reset_leaves_dmg_compat_mode() {
+        let mut ppu = dmg_compat_ppu();
+        ppu.reset();
+        assert!(!ppu.dmg_compat);
+        assert!(ppu.cgb_bg_pal.is_none() && ppu.cgb_obj_pal.is_none());
+        assert_eq!(ppu.read(0xFF6C, false), 0xFF);
+    }
+}
@@ -2,15 +2,67 @@
 
 use bitfield::bitfield;
 
-const DMG_PALETTE: [[u8; 4]; 4] = [
+/// The four shades of a DMG palette as RGBA, from lightest (color 0) to darkest.
+pub type DmgShades = [[u8; 4]; 4];
+
+const DMG_PALETTE: DmgShades = [
     [0xFF, 0xFF, 0xFF, 0xFF],
     [0xCC, 0xCC, 0xCC, 0xFF],
     [0x77, 0x77, 0x77, 0xFF],
     [0x00, 0x00, 0x00, 0xFF]
 ];
 
+/// Ready-made shades for the DMG palettes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DmgPalettePreset {
+    Grayscale,
+    /// The greyish LCD of the Game Boy Pocket.
+    PocketGrey,
+    /// The green LCD of the original Game Boy.
+    OriginalGreen,
+    HighContrast,
+    /// Shades that differ in both hue and lightness (viridis), telling them apart does not rely on color vision.
+    Colorblind,
+}
+
+impl DmgPalettePreset {
+    pub const ALL: [DmgPalettePreset; 5] = [
+        DmgPalettePreset::Grayscale, DmgPalettePreset::PocketGrey, DmgPalettePreset::OriginalGreen,
+        DmgPalettePreset::HighContrast, DmgPalettePreset::Colorblind
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DmgPalettePreset::Grayscale => "Grayscale",
+            DmgPalettePreset::PocketGrey => "Pocket grey",
+            DmgPalettePreset::OriginalGreen => "Original green",
+            DmgPalettePreset::HighContrast => "High contrast",
+            DmgPalettePreset::Colorblind => "Colorblind friendly",
+        }
+    }
+
+    pub fn shades(&self) -> DmgShades {
+        match self {
+            DmgPalettePreset::Grayscale => DMG_PALETTE,
+            DmgPalettePreset::PocketGrey => [
+                [0xC4, 0xCF, 0xA1, 0xFF], [0x8B, 0x95, 0x6D, 0xFF], [0x4D, 0x53, 0x3C, 0xFF], [0x1F, 0x1F, 0x1F, 0xFF]
+            ],
+            DmgPalettePreset::OriginalGreen => [
+                [0x9B, 0xBC, 0x0F, 0xFF], [0x8B, 0xAC, 0x0F, 0xFF], [0x30, 0x62, 0x30, 0xFF], [0x0F, 0x38, 0x0F, 0xFF]
+            ],
+            DmgPalettePreset::HighContrast => [
+                [0xFF, 0xFF, 0xFF, 0xFF], [0xAA, 0xAA, 0xAA, 0xFF], [0x55, 0x55, 0x55, 0xFF], [0x00, 0x00, 0x00, 0xFF]
+            ],
+            DmgPalettePreset::Colorblind => [
+                [0xFD, 0xE7, 0x25, 0xFF], [0x35, 0xB7, 0x79, 0xFF], [0x31, 0x68, 0x8E, 0xFF], [0x44, 0x01, 0x54, 0xFF]
+            ],
+        }
+    }
+}
+
 pub struct DmgPalette {
     pub(super) value: u8,
+    shades: DmgShades,
     colors: [[u8; 4]; 4]
 }
 
@@ -18,6 +70,7 @@ impl DmgPalette {
     pub fn new(value: u8) -> Self {
         let mut res = Self {
             value: 0,
+            shades: DMG_PALETTE,
             colors: [[0; 4]; 4]
         };
 
@@ -30,12 +83,22 @@ impl DmgPalette {
         self.colors
             .iter_mut()
             .for_each(|color| {
-                let new_color = &DMG_PALETTE[(new_value & 3) as usize];
+                let new_color = &self.shades[(new_value & 3) as usize];
                 color.copy_from_slice(new_color);
                 new_value >>= 2;
             })
     }
 
+    /// Replaces the shades the palette maps colors to.
+    pub fn set_shades(&mut self, shades: DmgShades) {
+        self.shades = shades;
+        self.update_palette(self.value);
+    }
+
+    pub fn shades(&self) -> DmgShades {
+        self.shades
+    }
+
     pub fn colors(&self) -> &[[u8; 4]; 4] {
         &self.colors
     }
@@ -68,34 +131,124 @@ bitfield! {
     pub auto_increment, _: 7;
 }
 
-#[derive(Debug, Default)]
+/// How RGB555 palette colors are converted for the host screen. The CGB LCD was far less
+/// saturated than a modern monitor, games were drawn with that in mind.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ColorCorrection {
+    /// Linear expansion of the 5 bit channels, the most saturated.
+    #[default]
+    None,
+    /// Mixes the channels the way the CGB LCD does.
+    CgbLcd,
+    /// The darker, gamma heavy screen of the GBA, on which CGB games were often played.
+    GbaLcd,
+    /// Linear colors with a lifted black point and a lowered white point.
+    ReducedContrast,
+}
+
+impl ColorCorrection {
+    pub const ALL: [ColorCorrection; 4] = [
+        ColorCorrection::None, ColorCorrection::CgbLcd, ColorCorrection::GbaLcd, ColorCorrection::ReducedContrast
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ColorCorrection::None => "None",
+            ColorCorrection::CgbLcd => "CGB LCD",
+            ColorCorrection::GbaLcd => "GBA LCD",
+            ColorCorrection::ReducedContrast => "Reduced contrast",
+        }
+    }
+
+    /// Converts an RGB555 color to RGBA.
+    pub fn apply(&self, color: CgbPaletteColor) -> [u8; 4] {
+        let (r, g, b) = (color.r() as u32, color.g() as u32, color.b() as u32);
+        let expand = |c: u32| ((c << 3) | (c >> 2)) as u8;
+        match self {
+            ColorCorrection::None => [expand(r), expand(g), expand(b), 0xFF],
+            ColorCorrection::CgbLcd => {
+                // The channel mix used by higan, each channel tops out at 240
+                let mix = |c: u32| (c.min(960) >> 2) as u8;
+                [mix(r * 26 + g * 4 + b * 2), mix(g * 24 + b * 8), mix(r * 6 + g * 4 + b * 22), 0xFF]
+            },
+            ColorCorrection::GbaLcd => {
+                let linear = |c: u32| (c as f32 / 31.0).powf(2.2) * 0.91;
+                let (r, g, b) = (linear(r), linear(g), linear(b));
+                let out = |c: f32| (c.clamp(0.0, 1.0).powf(1.0 / 2.2) * 255.0).round() as u8;
+                [
+                    out(0.80 * r + 0.275 * g - 0.075 * b),
+                    out(0.135 * r + 0.64 * g + 0.225 * b),
+                    out(0.195 * r + 0.155 * g + 0.65 * b),
+                    0xFF
+                ]
+            },
+            ColorCorrection::ReducedContrast => {
+                let reduce = |c: u32| 0x18 + (expand(c) as u32 * 13 / 16) as u8;
+                [reduce(r), reduce(g), reduce(b), 0xFF]
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
 pub struct CgbPalette {
     pub(super) data: [[CgbPaletteColor; 4]; 8],
-    pub(super) index: CgbPaletteIndex
+    pub(super) index: CgbPaletteIndex,
+    correction: ColorCorrection,
+    /// `data` converted to RGBA with `correction`.
+    rgba: [[[u8; 4]; 4]; 8]
+}
+
+impl Default for CgbPalette {
+    fn default() -> Self {
+        let mut palette = Self {
+            data: Default::default(),
+            index: Default::default(),
+            correction: ColorCorrection::default(),
+            rgba: [[[0; 4]; 4]; 8]
+        };
+        // Colors the game hasn't written yet are opaque black
+        palette.set_correction(palette.correction);
+        palette
+    }
 }
 
 impl CgbPalette {
     pub fn new() -> Self {
-        Self {
-            data: [[CgbPaletteColor(0); 4]; 8],
-            index: CgbPaletteIndex(0)
-        }
+        Self::default()
     }
 
     pub fn write_data(&mut self, val: u8) {
         let palette = self.index.palette() as usize;
         let color = self.index.color() as usize;
         let color_data = self.index.hilo();
+        let mut value = self.data[palette][color];
         if color_data {
-            self.data[palette][color].set_hi(val as u16);
+            value.set_hi(val as u16);
         } else {
-            self.data[palette][color].set_lo(val as u16);   
+            value.set_lo(val as u16);
         }
+        self.set_color(palette, color, value);
         if self.index.auto_increment() {
             self.increment_index();
         }
     }
 
+    /// Sets a color of a palette, as RGB555.
+    pub fn set_color(&mut self, palette: usize, color: usize, value: CgbPaletteColor) {
+        self.data[palette][color] = value;
+        self.rgba[palette][color] = self.correction.apply(value);
+    }
+
+    pub fn set_correction(&mut self, correction: ColorCorrection) {
+        self.correction = correction;
+        for (palette, rgba) in self.data.iter().zip(self.rgba.iter_mut()) {
+            for (color, out) in palette.iter().zip(rgba.iter_mut()) {
+                *out = correction.apply(*color);
+            }
+        }
+    }
+
     pub fn write_index(&mut self, val: u8) {
         self.index = CgbPaletteIndex(val);
     }
@@ -122,22 +275,49 @@ impl CgbPalette {
     pub fn color(&self) -> u32 {
         let palette = self.index.palette() as usize;
         let color = self.index.color() as usize;
-        let r = (self.data[palette][color].r() << 3) | (self.data[palette][color].r() >> 2);
-        let g = (self.data[palette][color].g() << 3) | (self.data[palette][color].g() >> 2);
-        let b = (self.data[palette][color].b() << 3) | (self.data[palette][color].b() >> 2);
-        
+        let [r, g, b, _] = self.rgba[palette][color];
+
         0x000000FF | ((r as u32) << 24) | ((g as u32) << 16) | ((b as u32) << 8)
     }
-    
+
     pub fn color_array(&self, palette_index: usize) -> [[u8; 4]; 4] {
-        let mut res = [[0; 4]; 4];
-        for i in 0..4 {
-            let r = ((self.data[palette_index][i].r() << 3) | (self.data[palette_index][i].r() >> 2)) as u8;
-            let g = ((self.data[palette_index][i].g() << 3) | (self.data[palette_index][i].g() >> 2)) as u8;
-            let b = ((self.data[palette_index][i].b() << 3) | (self.data[palette_index][i].b() >> 2)) as u8;
-            res[i] = [r, g, b, 0xFF];
-        }
-        
-        res
+        self.rgba[palette_index]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shades_follow_palette_value() {
+        let mut palette = DmgPalette::new(0b00_01_10_11);
+        let green = DmgPalettePreset::OriginalGreen.shades();
+        palette.set_shades(green);
+        assert_eq!(palette.colors(), &[green[3], green[2], green[1], green[0]]);
+        palette.update_palette(0b11_10_01_00);
+        assert_eq!(palette.colors(), &green);
+    }
+
+    #[test]
+    fn correction_applies_to_written_and_existing_colors() {
+        let mut palette = CgbPalette::new();
+        palette.write_index(0x80);
+        palette.write_data(0x1F);
+        palette.write_data(0x00);
+        assert_eq!(palette.color_array(0)[0], [0xFF, 0x00, 0x00, 0xFF]);
+        palette.set_correction(ColorCorrection::CgbLcd);
+        assert_eq!(palette.color_array(0)[0], [0xC9, 0x00, 0x2E, 0xFF]);
+        // White stays neutral
+        palette.write_data(0xFF);
+        palette.write_data(0x7F);
+        assert_eq!(palette.color_array(0)[1], [0xF0, 0xF0, 0xF0, 0xFF]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn unwritten_colors_are_opaque_black() {
+        let palette = CgbPalette::new();
+        assert_eq!(palette.color_array(7)[3], [0x00, 0x00, 0x00, 0xFF]);
+        assert_eq!(palette.color(), 0x000000FF);
+    }
+}
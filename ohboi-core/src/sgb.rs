@@ -0,0 +1,499 @@
+// Copyright Antonio Porsia 2025. Licensed under the EUPL-1.2 or later.
+
+//! Super Game Boy support: the packets games send through the joypad register,
+//! and the colorized 256x224 picture with the border around the Game Boy screen.
+
+use log::debug;
+
+pub const SGB_WIDTH: usize = 256;
+pub const SGB_HEIGHT: usize = 224;
+const GB_WIDTH: usize = 160;
+const GB_HEIGHT: usize = 144;
+/// Position of the Game Boy screen inside the SGB picture.
+const GB_X: usize = 48;
+const GB_Y: usize = 40;
+
+/// The attribute map covers the screen in 8x8 cells.
+const ATTR_WIDTH: usize = 20;
+const ATTR_HEIGHT: usize = 18;
+const PACKET_SIZE: usize = 16;
+/// Size of the data copied from VRAM by the *_TRN commands.
+const TRANSFER_SIZE: usize = 0x1000;
+const N_SYSTEM_PALETTES: usize = 512;
+const N_ATTRIBUTE_FILES: usize = 45;
+const ATTRIBUTE_FILE_SIZE: usize = 90;
+
+/// Palette 1-A of the SGB BIOS, used until the game sends its own.
+const DEFAULT_PALETTE: [u16; 4] = [0x67BF, 0x265B, 0x10B5, 0x2866];
+
+mod commands {
+    pub const PAL01: u8 = 0x00;
+    pub const PAL23: u8 = 0x01;
+    pub const PAL03: u8 = 0x02;
+    pub const PAL12: u8 = 0x03;
+    pub const ATTR_BLK: u8 = 0x04;
+    pub const ATTR_LIN: u8 = 0x05;
+    pub const ATTR_DIV: u8 = 0x06;
+    pub const ATTR_CHR: u8 = 0x07;
+    pub const PAL_SET: u8 = 0x0A;
+    pub const PAL_TRN: u8 = 0x0B;
+    pub const MLT_REQ: u8 = 0x11;
+    pub const CHR_TRN: u8 = 0x13;
+    pub const PCT_TRN: u8 = 0x14;
+    pub const ATTR_TRN: u8 = 0x15;
+    pub const ATTR_SET: u8 = 0x16;
+    pub const MASK_EN: u8 = 0x17;
+}
+
+/// What MASK_EN does to the Game Boy screen while the game prepares the next picture.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mask {
+    None,
+    Freeze,
+    Black,
+    Color0,
+}
+
+/// A VRAM transfer requested by a command, performed on the next frame.
+#[derive(Debug, Clone, Copy)]
+enum Transfer {
+    Palettes,
+    /// Border tiles, the flag selects tiles 0x80-0xFF.
+    Tiles(bool),
+    /// Border tile map and palettes.
+    Picture,
+    AttributeFiles,
+}
+
+pub struct Sgb {
+    /// Bits of the packet being received, `None` when waiting for a reset pulse.
+    bit: Option<usize>,
+    packet: [u8; PACKET_SIZE],
+    /// The packets of the command being received.
+    command: Vec<u8>,
+    /// Last value written to P14/P15.
+    last_select: u8,
+
+    palettes: [[u16; 4]; 4],
+    system_palettes: Vec<[u16; 4]>,
+    attributes: [u8; ATTR_WIDTH * ATTR_HEIGHT],
+    attribute_files: Vec<[u8; ATTRIBUTE_FILE_SIZE]>,
+    /// 256 border tiles, 4 bits per pixel in the SNES format.
+    border_tiles: Vec<u8>,
+    /// 32x32 border tile map entries.
+    border_map: [u16; 32 * 32],
+    /// Border palettes 4-7 of the SNES.
+    border_palettes: [[u16; 16]; 4],
+    mask: Mask,
+    pending: Option<Transfer>,
+
+    players: u8,
+    current_player: u8,
+
+    frame: Vec<u8>,
+}
+
+impl Default for Sgb {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sgb {
+    pub fn new() -> Self {
+        Self {
+            bit: None,
+            packet: [0; PACKET_SIZE],
+            command: Vec::new(),
+            last_select: 0x30,
+            palettes: [DEFAULT_PALETTE; 4],
+            system_palettes: vec![[0; 4]; N_SYSTEM_PALETTES],
+            attributes: [0; ATTR_WIDTH * ATTR_HEIGHT],
+            attribute_files: vec![[0; ATTRIBUTE_FILE_SIZE]; N_ATTRIBUTE_FILES],
+            border_tiles: vec![0; 256 * 32],
+            border_map: [0; 32 * 32],
+            border_palettes: [[0; 16]; 4],
+            mask: Mask::None,
+            pending: None,
+            players: 1,
+            current_player: 0,
+            frame: vec![0; SGB_WIDTH * SGB_HEIGHT * 4],
+        }
+    }
+
+    /// Handles a write to P14/P15 of the joypad register. Packets are sent one bit at a time,
+    /// LSB first: pulling P14 low sends a 0, pulling P15 low a 1, and both low starts a packet.
+    pub fn write_joypad(&mut self, val: u8) {
+        let select = val & 0x30;
+        match select {
+            0x00 => {
+                self.bit = Some(0);
+                self.packet = [0; PACKET_SIZE];
+            },
+            0x10 | 0x20 if self.last_select == 0x30 => {
+                match self.bit {
+                    // The stop bit after the packet
+                    Some(bit) if bit == PACKET_SIZE * 8 => self.bit = None,
+                    Some(bit) => {
+                        if select == 0x10 {
+                            self.packet[bit / 8] |= 1 << (bit % 8);
+                        }
+                        self.bit = Some(bit + 1);
+                        if bit + 1 == PACKET_SIZE * 8 {
+                            self.receive_packet();
+                        }
+                    },
+                    None => {}
+                }
+            },
+            // Releasing P15 after reading the buttons moves on to the next controller
+            0x30 if self.bit.is_none() && self.players > 1 && self.last_select & 0x20 == 0 => {
+                self.current_player = (self.current_player + 1) % self.players;
+            },
+            _ => {}
+        }
+        self.last_select = select;
+    }
+
+    /// The controller that is currently read, 0 to 3.
+    pub fn current_player(&self) -> u8 {
+        self.current_player
+    }
+
+    /// Whether MLT_REQ has enabled more than one controller.
+    pub fn multiplayer(&self) -> bool {
+        self.players > 1
+    }
+
+    fn receive_packet(&mut self) {
+        self.command.extend_from_slice(&self.packet);
+        let length = (self.command[0] & 0x7).max(1) as usize;
+        if self.command.len() >= length * PACKET_SIZE {
+            let command = std::mem::take(&mut self.command);
+            self.execute(&command);
+        }
+    }
+
+    fn execute(&mut self, data: &[u8]) {
+        let command = data[0] >> 3;
+        debug!("SGB command {:02X}", command);
+        match command {
+            commands::PAL01 => self.set_palettes(0, 1, data),
+            commands::PAL23 => self.set_palettes(2, 3, data),
+            commands::PAL03 => self.set_palettes(0, 3, data),
+            commands::PAL12 => self.set_palettes(1, 2, data),
+            commands::ATTR_BLK => self.attr_blk(data),
+            commands::ATTR_LIN => self.attr_lin(data),
+            commands::ATTR_DIV => self.attr_div(data),
+            commands::ATTR_CHR => self.attr_chr(data),
+            commands::PAL_SET => {
+                for i in 0..4 {
+                    let id = u16::from_le_bytes([data[1 + i * 2], data[2 + i * 2]]) as usize;
+                    self.palettes[i] = self.system_palettes[id % N_SYSTEM_PALETTES];
+                }
+                let shared = self.palettes[0][0];
+                self.palettes.iter_mut().for_each(|p| p[0] = shared);
+                if data[9] & 0x80 != 0 {
+                    self.apply_attribute_file(data[9] as usize & 0x3F);
+                }
+                if data[9] & 0x40 != 0 {
+                    self.mask = Mask::None;
+                }
+            },
+            commands::PAL_TRN => self.pending = Some(Transfer::Palettes),
+            commands::MLT_REQ => {
+                self.players = match data[1] & 0x3 {
+                    0 => 1,
+                    3 => 4,
+                    _ => 2
+                };
+                self.current_player = 0;
+            },
+            commands::CHR_TRN => self.pending = Some(Transfer::Tiles(data[1] & 1 != 0)),
+            commands::PCT_TRN => self.pending = Some(Transfer::Picture),
+            commands::ATTR_TRN => self.pending = Some(Transfer::AttributeFiles),
+            commands::ATTR_SET => {
+                self.apply_attribute_file(data[1] as usize & 0x3F);
+                if data[1] & 0x40 != 0 {
+                    self.mask = Mask::None;
+                }
+            },
+            commands::MASK_EN => {
+                self.mask = match data[1] & 0x3 {
+                    1 => Mask::Freeze,
+                    2 => Mask::Black,
+                    3 => Mask::Color0,
+                    _ => Mask::None
+                };
+            },
+            _ => debug!("Unsupported SGB command {:02X}", command)
+        }
+    }
+
+    /// PAL01-PAL12: color 0, shared by all palettes, then colors 1-3 of two palettes.
+    fn set_palettes(&mut self, first: usize, second: usize, data: &[u8]) {
+        let color = |i: usize| u16::from_le_bytes([data[1 + i * 2], data[2 + i * 2]]) & 0x7FFF;
+        self.palettes.iter_mut().for_each(|p| p[0] = color(0));
+        for i in 1..4 {
+            self.palettes[first][i] = color(i);
+            self.palettes[second][i] = color(i + 3);
+        }
+    }
+
+    fn attr_blk(&mut self, data: &[u8]) {
+        let count = (data[1] as usize).min(18);
+        for set in data[2..].chunks_exact(6).take(count) {
+            let control = set[0] & 0x7;
+            let (inside, border, outside) = (set[1] & 0x3, (set[1] >> 2) & 0x3, (set[1] >> 4) & 0x3);
+            // A block with only its inside or outside set also colors its border
+            let border = match control {
+                0b001 => Some(inside),
+                0b100 => Some(outside),
+                c if c & 0b010 != 0 => Some(border),
+                _ => None
+            };
+            let (x1, y1, x2, y2) = (set[2] as usize & 0x1F, set[3] as usize & 0x1F, set[4] as usize & 0x1F, set[5] as usize & 0x1F);
+            for y in 0..ATTR_HEIGHT {
+                for x in 0..ATTR_WIDTH {
+                    let within = (x1..=x2).contains(&x) && (y1..=y2).contains(&y);
+                    let on_border = within && (x == x1 || x == x2 || y == y1 || y == y2);
+                    let palette = if on_border {
+                        border
+                    } else if within {
+                        (control & 0b001 != 0).then_some(inside)
+                    } else {
+                        (control & 0b100 != 0).then_some(outside)
+                    };
+                    if let Some(palette) = palette {
+                        self.attributes[y * ATTR_WIDTH + x] = palette;
+                    }
+                }
+            }
+        }
+    }
+
+    fn attr_lin(&mut self, data: &[u8]) {
+        let count = data[1] as usize;
+        for &line in data[2..].iter().take(count) {
+            let (n, palette) = (line as usize & 0x1F, (line >> 5) & 0x3);
+            if line & 0x80 != 0 {
+                if n < ATTR_HEIGHT {
+                    self.attributes[n * ATTR_WIDTH..(n + 1) * ATTR_WIDTH].fill(palette);
+                }
+            } else if n < ATTR_WIDTH {
+                (0..ATTR_HEIGHT).for_each(|y| self.attributes[y * ATTR_WIDTH + n] = palette);
+            }
+        }
+    }
+
+    fn attr_div(&mut self, data: &[u8]) {
+        let (after, before, on_line) = (data[1] & 0x3, (data[1] >> 2) & 0x3, (data[1] >> 4) & 0x3);
+        let horizontal = data[1] & 0x40 != 0;
+        let line = data[2] as usize & 0x1F;
+        for y in 0..ATTR_HEIGHT {
+            for x in 0..ATTR_WIDTH {
+                let pos = if horizontal { y } else { x };
+                self.attributes[y * ATTR_WIDTH + x] = match pos.cmp(&line) {
+                    std::cmp::Ordering::Less => before,
+                    std::cmp::Ordering::Equal => on_line,
+                    std::cmp::Ordering::Greater => after
+                };
+            }
+        }
+    }
+
+    fn attr_chr(&mut self, data: &[u8]) {
+        let (mut x, mut y) = (data[1] as usize % ATTR_WIDTH, data[2] as usize % ATTR_HEIGHT);
+        let count = (u16::from_le_bytes([data[3], data[4]]) as usize).min(ATTR_WIDTH * ATTR_HEIGHT);
+        let vertical = data[5] & 1 != 0;
+        let palettes = data[6..].iter().flat_map(|&b| [b >> 6, (b >> 4) & 0x3, (b >> 2) & 0x3, b & 0x3]);
+        for palette in palettes.take(count) {
+            self.attributes[y * ATTR_WIDTH + x] = palette;
+            if vertical {
+                y += 1;
+                if y == ATTR_HEIGHT {
+                    y = 0;
+                    x = (x + 1) % ATTR_WIDTH;
+                }
+            } else {
+                x += 1;
+                if x == ATTR_WIDTH {
+                    x = 0;
+                    y = (y + 1) % ATTR_HEIGHT;
+                }
+            }
+        }
+    }
+
+    fn apply_attribute_file(&mut self, index: usize) {
+        let Some(file) = self.attribute_files.get(index) else {
+            return;
+        };
+        for (i, attribute) in self.attributes.iter_mut().enumerate() {
+            *attribute = (file[i / 4] >> (6 - (i % 4) * 2)) & 0x3;
+        }
+    }
+
+    /// Whether a command waits for data from VRAM, see `transfer`.
+    pub fn transfer_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Completes the pending *_TRN command with the 4 KiB the game has put on screen,
+    /// read as the first 256 tiles of the background.
+    pub fn transfer(&mut self, data: &[u8]) {
+        let word = |i: usize| u16::from_le_bytes([data[i], data[i + 1]]);
+        match self.pending.take() {
+            Some(Transfer::Palettes) => {
+                for (i, palette) in self.system_palettes.iter_mut().enumerate() {
+                    *palette = std::array::from_fn(|c| word(i * 8 + c * 2) & 0x7FFF);
+                }
+            },
+            Some(Transfer::Tiles(high)) => {
+                let offset = if high { TRANSFER_SIZE } else { 0 };
+                self.border_tiles[offset..offset + TRANSFER_SIZE].copy_from_slice(&data[..TRANSFER_SIZE]);
+            },
+            Some(Transfer::Picture) => {
+                for (i, entry) in self.border_map.iter_mut().enumerate() {
+                    *entry = word(i * 2);
+                }
+                for (p, palette) in self.border_palettes.iter_mut().enumerate() {
+                    *palette = std::array::from_fn(|c| word(0x800 + p * 32 + c * 2) & 0x7FFF);
+                }
+            },
+            Some(Transfer::AttributeFiles) => {
+                for (i, file) in self.attribute_files.iter_mut().enumerate() {
+                    file.copy_from_slice(&data[i * ATTRIBUTE_FILE_SIZE..(i + 1) * ATTRIBUTE_FILE_SIZE]);
+                }
+            },
+            None => {}
+        }
+    }
+
+    /// Composes the SGB picture from a finished Game Boy frame.
+    ///
+    /// # Arguments
+    ///
+    /// * `shades` - The 160x144 shades (0-3) output by the Game Boy, after BGP/OBP mapping.
+    pub fn render(&mut self, shades: &[u8]) {
+        let backdrop = rgb(self.palettes[0][0]);
+        for y in 0..SGB_HEIGHT {
+            for x in 0..SGB_WIDTH {
+                let pixel = (y * SGB_WIDTH + x) * 4;
+                let in_screen = (GB_X..GB_X + GB_WIDTH).contains(&x) && (GB_Y..GB_Y + GB_HEIGHT).contains(&y);
+                let color = match self.border_pixel(x, y) {
+                    Some(color) => color,
+                    None if in_screen => {
+                        let (gx, gy) = (x - GB_X, y - GB_Y);
+                        match self.mask {
+                            Mask::Freeze => continue,
+                            Mask::Black => [0, 0, 0, 0xFF],
+                            Mask::Color0 => backdrop,
+                            Mask::None => {
+                                let palette = self.attributes[(gy / 8) * ATTR_WIDTH + gx / 8] as usize;
+                                rgb(self.palettes[palette][shades[gy * GB_WIDTH + gx] as usize])
+                            }
+                        }
+                    },
+                    None => backdrop
+                };
+                self.frame[pixel..pixel + 4].copy_from_slice(&color);
+            }
+        }
+    }
+
+    /// The color of the border at a pixel, `None` where it is transparent.
+    fn border_pixel(&self, x: usize, y: usize) -> Option<[u8; 4]> {
+        let entry = self.border_map[(y / 8) * 32 + x / 8];
+        let tile = (entry & 0xFF) as usize;
+        let palette = ((entry >> 10) & 0x7) as usize;
+        let px = if entry & 0x4000 != 0 { 7 - x % 8 } else { x % 8 };
+        let py = if entry & 0x8000 != 0 { 7 - y % 8 } else { y % 8 };
+        let data = &self.border_tiles[tile * 32..tile * 32 + 32];
+        let bit = 7 - px;
+        let color = (data[py * 2] >> bit) & 1
+            | ((data[py * 2 + 1] >> bit) & 1) << 1
+            | ((data[16 + py * 2] >> bit) & 1) << 2
+            | ((data[16 + py * 2 + 1] >> bit) & 1) << 3;
+        if color == 0 || !(4..8).contains(&palette) {
+            None
+        } else {
+            Some(rgb(self.border_palettes[palette - 4][color as usize]))
+        }
+    }
+
+    /// The last composed 256x224 RGBA picture.
+    pub fn frame(&self) -> &[u8] {
+        &self.frame
+    }
+}
+
+/// Converts an RGB555 color to RGBA.
+fn rgb(color: u16) -> [u8; 4] {
+    let channel = |shift: u16| {
+        let c = ((color >> shift) & 0x1F) as u8;
+        (c << 3) | (c >> 2)
+    };
+    [channel(0), channel(5), channel(10), 0xFF]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn send(sgb: &mut Sgb, packet: &[u8; PACKET_SIZE]) {
+        sgb.write_joypad(0x00);
+        sgb.write_joypad(0x30);
+        for bit in 0..PACKET_SIZE * 8 {
+            let one = packet[bit / 8] & (1 << (bit % 8)) != 0;
+            sgb.write_joypad(if one { 0x10 } else { 0x20 });
+            sgb.write_joypad(0x30);
+        }
+        // Stop bit
+        sgb.write_joypad(0x20);
+        sgb.write_joypad(0x30);
+    }
+
+    #[test]
+    fn packets_set_palettes_and_attributes() {
+        let mut sgb = Sgb::new();
+        let mut pal01 = [0u8; PACKET_SIZE];
+        pal01[0] = (commands::PAL01 << 3) | 1;
+        pal01[1..3].copy_from_slice(&0x001Fu16.to_le_bytes());
+        pal01[9..11].copy_from_slice(&0x7C00u16.to_le_bytes());
+        send(&mut sgb, &pal01);
+        assert_eq!(sgb.palettes[0][0], 0x001F);
+        assert_eq!(sgb.palettes[3][0], 0x001F);
+        assert_eq!(sgb.palettes[1][1], 0x7C00);
+
+        // Right half of the screen on palette 1
+        let mut attr_div = [0u8; PACKET_SIZE];
+        attr_div[0] = (commands::ATTR_DIV << 3) | 1;
+        attr_div[1] = 0b01_00_01;
+        attr_div[2] = 10;
+        send(&mut sgb, &attr_div);
+        assert_eq!(sgb.attributes[9], 0);
+        assert_eq!(sgb.attributes[10], 1);
+        assert_eq!(sgb.attributes[ATTR_WIDTH * 17 + 19], 1);
+
+        let shades = [1u8; GB_WIDTH * GB_HEIGHT];
+        sgb.render(&shades);
+        let pixel = (GB_Y * SGB_WIDTH + GB_X + 100) * 4;
+        assert_eq!(sgb.frame()[pixel..pixel + 4], [0, 0, 0xFF, 0xFF]);
+        assert_eq!(sgb.frame()[..4], [0xFF, 0, 0, 0xFF]);
+    }
+
+    #[test]
+    fn mlt_req_cycles_players() {
+        let mut sgb = Sgb::new();
+        let mut mlt_req = [0u8; PACKET_SIZE];
+        mlt_req[0] = (commands::MLT_REQ << 3) | 1;
+        mlt_req[1] = 1;
+        send(&mut sgb, &mlt_req);
+        assert!(sgb.multiplayer());
+        assert_eq!(sgb.current_player(), 0);
+        sgb.write_joypad(0x10);
+        sgb.write_joypad(0x30);
+        assert_eq!(sgb.current_player(), 1);
+    }
+}
@@ -0,0 +1,149 @@
+// Copyright Antonio Porsia 2025. Licensed under the EUPL-1.2 or later.
+
+//! Prints the cartridge header, checksums and emulator support status of Game Boy ROMs.
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use clap::{Arg, ArgAction, Command};
+use serde::Serialize;
+use ohboi_core::memory::cartridge::info::{CartridgeInfo, CgbSupport, Destination};
+use ohboi_core::utils::{crc32, sha1};
+
+#[derive(Serialize)]
+struct RomReport {
+    path: PathBuf,
+    title: String,
+    manufacturer_code: String,
+    cgb: &'static str,
+    sgb: bool,
+    mapper: String,
+    mapper_code: u8,
+    mapper_supported: bool,
+    rom_size: usize,
+    file_size: usize,
+    ram_size: usize,
+    destination: &'static str,
+    licensee: &'static str,
+    version: u8,
+    crc32: String,
+    sha1: String,
+    header_checksum_valid: bool,
+    global_checksum_valid: bool,
+    logo_valid: bool,
+    /// Size of the `.sav` next to the ROM, if there is one.
+    save_size: Option<u64>,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Report {
+    Rom(Box<RomReport>),
+    Error { path: PathBuf, error: String },
+}
+
+fn inspect(path: PathBuf) -> Report {
+    let rom = match fs::read(&path) {
+        Ok(rom) => rom,
+        Err(e) => return Report::Error { path, error: e.to_string() },
+    };
+    let Some(info) = CartridgeInfo::from_rom(&rom) else {
+        return Report::Error { path, error: String::from("File too small to be a Game Boy ROM") };
+    };
+    let save_size = fs::metadata(path.with_extension("sav")).ok().map(|m| m.len());
+    Report::Rom(Box::new(RomReport {
+        title: info.title.clone(),
+        manufacturer_code: info.manufacturer_code.clone(),
+        cgb: match info.cgb {
+            CgbSupport::None => "no",
+            CgbSupport::Supported => "supported",
+            CgbSupport::Only => "only",
+        },
+        sgb: info.sgb,
+        mapper: format!("{:?}", info.cart_type),
+        mapper_code: info.cart_type_code,
+        mapper_supported: info.mapper_supported(),
+        rom_size: info.rom_size,
+        file_size: rom.len(),
+        ram_size: info.ram_size,
+        destination: match info.destination {
+            Destination::Japan => "Japan",
+            Destination::Overseas => "Overseas",
+        },
+        licensee: info.licensee(),
+        version: info.version,
+        crc32: format!("{:08x}", crc32(&rom)),
+        sha1: sha1(&rom).iter().map(|b| format!("{:02x}", b)).collect(),
+        header_checksum_valid: info.header_checksum_valid,
+        global_checksum_valid: info.global_checksum_valid,
+        logo_valid: info.logo_valid,
+        save_size,
+        path,
+    }))
+}
+
+fn print_report(report: &Report) {
+    let ok = |valid: bool| if valid { "ok" } else { "BAD" };
+    match report {
+        Report::Error { path, error } => println!("{}: {}\n", path.display(), error),
+        Report::Rom(r) => {
+            println!("{}", r.path.display());
+            println!("  Title:            {}", r.title);
+            if !r.manufacturer_code.is_empty() {
+                println!("  Manufacturer:     {}", r.manufacturer_code);
+            }
+            println!("  Licensee:         {}", r.licensee);
+            println!("  Version:          {}", r.version);
+            println!("  Destination:      {}", r.destination);
+            println!("  CGB:              {}", r.cgb);
+            println!("  SGB:              {}", if r.sgb { "yes" } else { "no" });
+            println!("  Mapper:           {} (0x{:02X}), {}", r.mapper, r.mapper_code,
+                     if r.mapper_supported { "supported" } else { "UNSUPPORTED" });
+            println!("  ROM size:         {} KiB (file {} KiB)", r.rom_size / 1024, r.file_size / 1024);
+            println!("  RAM size:         {} KiB", r.ram_size / 1024);
+            println!("  CRC32:            {}", r.crc32);
+            println!("  SHA-1:            {}", r.sha1);
+            println!("  Header checksum:  {}", ok(r.header_checksum_valid));
+            println!("  Global checksum:  {}", ok(r.global_checksum_valid));
+            println!("  Logo:             {}", ok(r.logo_valid));
+            match r.save_size {
+                Some(size) => println!("  Save file:        {} bytes", size),
+                None => println!("  Save file:        none"),
+            }
+            println!();
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let matches = Command::new("ohboi-info")
+        .about("Prints the cartridge header, checksums and support status of Game Boy ROMs")
+        .arg(Arg::new("json").long("json").action(ArgAction::SetTrue).help("Print a JSON array instead of text"))
+        .arg(Arg::new("roms").required(true).num_args(1..).value_parser(clap::value_parser!(PathBuf)).help("ROM files"))
+        .get_matches();
+
+    let reports: Vec<Report> = matches.get_many::<PathBuf>("roms")
+        .into_iter()
+        .flatten()
+        .cloned()
+        .map(inspect)
+        .collect();
+
+    if matches.get_flag("json") {
+        match serde_json::to_string_pretty(&reports) {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                eprintln!("Failed to serialize report: {}", e);
+                return ExitCode::FAILURE;
+            }
+        }
+    } else {
+        reports.iter().for_each(print_report);
+    }
+
+    if reports.iter().any(|r| matches!(r, Report::Error { .. })) {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
@@ -0,0 +1,49 @@
+// Copyright Antonio Porsia 2025. Licensed under the EUPL-1.2 or later.
+
+//! Runs a Game Boy ROM without a window for a number of frames and prints where the cycles went.
+//!
+//! The game isn't saved afterward, so profiling leaves the `.sav` next to the ROM untouched.
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+use clap::{Arg, Command};
+use ohboi_core::GameBoy;
+
+/// T-cycles in a frame at normal speed.
+const CYCLES_PER_FRAME: u64 = 70224;
+
+fn main() -> ExitCode {
+    let matches = Command::new("ohboi-profile")
+        .about("Runs a ROM headless for a number of frames and prints the hottest routines and addresses")
+        .arg(Arg::new("frames").long("frames").short('f').default_value("600")
+            .value_parser(clap::value_parser!(u64)).help("Number of frames to run"))
+        .arg(Arg::new("top").long("top").short('n').default_value("20")
+            .value_parser(clap::value_parser!(usize)).help("Number of routines and addresses to list"))
+        .arg(Arg::new("symbols").long("symbols").short('s')
+            .value_parser(clap::value_parser!(PathBuf)).help("A .sym or .map file, instead of the one next to the ROM"))
+        .arg(Arg::new("rom").required(true).value_parser(clap::value_parser!(PathBuf)).help("ROM file"))
+        .get_matches();
+
+    let rom = matches.get_one::<PathBuf>("rom").unwrap().clone();
+    let mut gb = match GameBoy::new(rom.clone()) {
+        Ok(gb) => gb,
+        Err(e) => {
+            eprintln!("{}: {}", rom.display(), e);
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Some(path) = matches.get_one::<PathBuf>("symbols") {
+        if let Err(e) = gb.load_symbols(path) {
+            eprintln!("{}: {}", path.display(), e);
+            return ExitCode::FAILURE;
+        }
+    }
+
+    let frames = *matches.get_one::<u64>("frames").unwrap();
+    gb.start_profiling();
+    while gb.cycle_counter() < frames * CYCLES_PER_FRAME {
+        gb.clock();
+    }
+    print!("{}", gb.profile_report(*matches.get_one::<usize>("top").unwrap()).unwrap_or_default());
+    ExitCode::SUCCESS
+}
@@ -5,15 +5,26 @@ mod ui;
 
 use std::collections::VecDeque;
 use std::error::Error;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::thread;
-use log::{info};
-use ohboi_core::ohboi::GameBoy;
+use log::{info, warn};
+use ohboi_core::ohboi::{GameBoy, Model};
+use ohboi_core::ppu::DmgPaletteId;
 use crate::logging::setup_logger;
 use crate::ui::{OhBoiUi};
 use crate::ui::GameWindowEvent::*;
 
+/// Opens a game, patched if a patch is given, on a new Game Boy, keeping the display settings of the current one.
+fn reopen(gb: &GameBoy, path: PathBuf, patch: Option<&Path>, model: Option<Model>) -> Result<GameBoy, Box<dyn Error>> {
+    let new_gb = GameBoy::with_model_and_patch(path, patch, model)?;
+    new_gb.set_color_correction(gb.color_correction());
+    for id in [DmgPaletteId::Bg, DmgPaletteId::Obj0, DmgPaletteId::Obj1] {
+        new_gb.set_dmg_palette(id, gb.dmg_palette(id));
+    }
+    Ok(new_gb)
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
     let log_buffer = Arc::new(Mutex::new(VecDeque::new()));
     setup_logger(1, 0, Arc::clone(&log_buffer))?;
@@ -59,22 +70,46 @@ fn main() -> Result<(), Box<dyn Error>> {
                 None => {}
             }
             if !rendered && gb.is_in_vblank() {
-                ui.draw_game_screen(&gb.screen());
+                ui.draw_game_screen(&gb.screen(), gb.screen_size());
                 #[cfg(feature = "debug_ui")]
                 ui.draw_tiles(&gb.tiles());
                 rendered = true;
             }
         }
         gb.reset_cycle_counter();
+        ui.set_rumble(gb.rumble());
+        ui.set_tone(gb.tone());
         match ui.show(&mut gb, None, (&ch1_queue, &ch2_queue, &ch3_queue, &ch4_queue))? {
             Open(path) => {
                 gb.close_game();
-                gb = GameBoy::new(path)?;
+                gb = reopen(&gb, path, None, None)?;
+            },
+            // Only the current game runs on the chosen model, the next one opened picks its own
+            SetModel(model) => {
+                let path = gb.rom_path();
+                let patch = gb.patch_path();
+                gb.close_game();
+                gb = reopen(&gb, path, patch.as_deref(), model)?;
+            },
+            SetCompatPalettes(palettes) => gb.set_compat_palettes(&palettes),
+            OpenPatched(rom, patch) => {
+                // Saving doesn't stop the current game, it keeps running if the patch doesn't apply
+                gb.close_game();
+                match reopen(&gb, rom, Some(&patch), None) {
+                    Ok(new_gb) => gb = new_gb,
+                    Err(e) => warn!("Failed to apply patch {:?}: {}", patch, e),
+                }
             },
             Close => {
                 gb.close_game();
                 break 'main;
             },
+            #[cfg(feature = "debug_ui")]
+            LoadSymbols(path) => {
+                if let Err(e) = gb.load_symbols(&path) {
+                    warn!("Failed to load symbols from {:?}: {}", path, e);
+                }
+            },
             _ => {}
         }
         let elapsed = current_time.elapsed();
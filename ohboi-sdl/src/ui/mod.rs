@@ -7,37 +7,87 @@ use std::error::Error;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 use cfg_if::cfg_if;
+use log::{debug, warn};
 
 use imgui_glow_renderer::glow::{NativeTexture, PixelUnpackData};
 use imgui::{Condition, StyleVar, TextureId, Textures, Ui};
 use imgui_glow_renderer::Renderer;
 use imgui_glow_renderer::glow::{Context, HasContext, Texture};
 use imgui_sdl2_support::SdlPlatform;
-use sdl2::Sdl;
+use sdl2::{GameControllerSubsystem, Sdl};
+use sdl2::controller::{Axis, GameController};
+use sdl2::mouse::MouseButton;
 use sdl2::video::{GLContext, GLProfile, Window};
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
 use ohboi_core::GameBoy;
+use ohboi_core::ohboi::Model;
+use ohboi_core::ppu::compat::CompatPalettes;
+use ohboi_core::ppu::{ColorCorrection, DmgPaletteId, DmgPalettePreset};
 use ohboi_core::joypad::Key;
+use ohboi_core::memory::cartridge::AccelerometerInput;
 use crate::logging::ImguiLogString;
 use crate::ui::GameWindowEvent::{Close, Nothing, Open, ToggleWaveform};
 
+/// How long a rumble request lasts. It is renewed every frame while the cartridge keeps the motor on.
+const RUMBLE_DURATION_MS: u32 = 100;
+/// The cartridge speaker beeps as a square wave of this many samples at 44.1 kHz, about 1 kHz.
+const TONE_PERIOD: usize = 44;
+const TONE_VOLUME: f32 = 0.1;
+
 #[cfg(feature = "debug_ui")]
 use crate::ui::widgets::DisassemblyView;
 #[cfg(feature = "debug_ui")]
 use widgets::hexview::HexView;
+#[cfg(feature = "debug_ui")]
+use widgets::profiler::ProfilerWindow;
+#[cfg(feature = "debug_ui")]
+use widgets::memory_search::MemorySearchWindow;
 use crate::ui::widgets::hexview::calc_hex_view_width;
+use crate::ui::widgets::palette::DmgPaletteWindow;
 
 const GB_SCREEN_WIDTH: usize = 160;
 const GB_SCREEN_HEIGHT: usize = 144;
+/// The game texture is large enough for the Super Game Boy picture with its border.
+const SCREEN_TEXTURE_WIDTH: usize = 256;
+const SCREEN_TEXTURE_HEIGHT: usize = 224;
+
+/// The button combinations of the CGB boot ROM and the colors they select.
+const COMPAT_PALETTE_COMBOS: [(&str, Key, Option<Key>); 12] = [
+    ("Up: brown", Key::Up, None),
+    ("Up + A: red", Key::Up, Some(Key::A)),
+    ("Up + B: dark brown", Key::Up, Some(Key::B)),
+    ("Left: blue", Key::Left, None),
+    ("Left + A: dark blue", Key::Left, Some(Key::A)),
+    ("Left + B: grayscale", Key::Left, Some(Key::B)),
+    ("Down: pastel", Key::Down, None),
+    ("Down + A: orange", Key::Down, Some(Key::A)),
+    ("Down + B: yellow", Key::Down, Some(Key::B)),
+    ("Right: green", Key::Right, None),
+    ("Right + A: dark green", Key::Right, Some(Key::A)),
+    ("Right + B: inverted", Key::Right, Some(Key::B)),
+];
 
 #[derive(Debug, Clone)]
 pub enum GameWindowEvent {
     Close,
     Open(PathBuf),
+    /// A ROM and the patch to apply to it.
+    OpenPatched(PathBuf, PathBuf),
+    #[cfg(feature = "debug_ui")]
+    LoadSymbols(PathBuf),
     //KeyPress(Keycode),
     Nothing,
-    ToggleWaveform
+    ToggleWaveform,
+    /// Reload the game on another model, or on the one it is meant for if `None`.
+    SetModel(Option<Model>),
+    /// Palettes for a DMG game running on a CGB.
+    SetCompatPalettes(CompatPalettes),
+    ToggleDmgPalettes,
+    #[cfg(feature = "debug_ui")]
+    ToggleProfiler,
+    #[cfg(feature = "debug_ui")]
+    ToggleMemorySearch
 }
 
 fn new_texture(w: usize, h: usize, gl: &Context, textures: &mut Textures<NativeTexture>) -> Result<TextureId, Box<dyn Error>> {
@@ -107,6 +157,7 @@ pub struct OhBoiUi {
     sdl_window: Window,
     renderer: Renderer,
     game_window: GameWindow,
+    dmg_palette_window: DmgPaletteWindow,
     #[cfg(feature = "debug_ui")]
     tile_window: TileWindow,
     #[cfg(feature = "debug_ui")]
@@ -117,8 +168,20 @@ pub struct OhBoiUi {
     ext_ram_window: HexView,
     #[cfg(feature = "debug_ui")]
     disasm_window: DisassemblyView,
+    #[cfg(feature = "debug_ui")]
+    profiler_window: ProfilerWindow,
+    #[cfg(feature = "debug_ui")]
+    memory_search_window: MemorySearchWindow,
     textures: Textures<Texture>,
     audio_device: sdl2::audio::AudioQueue<f32>,
+    game_controller: GameControllerSubsystem,
+    controllers: Vec<GameController>,
+    rumbling: bool,
+    /// Whether the cartridge speaker is on, and where in its square wave the tone is.
+    tone: bool,
+    tone_phase: usize,
+    /// Tilt fed to cartridges with an accelerometer, from the left stick or by dragging with the right mouse button.
+    tilt: AccelerometerInput,
     #[cfg(feature = "debug_ui")]
     log_buffer: Arc<Mutex<VecDeque<ImguiLogString>>>
 }
@@ -156,7 +219,7 @@ impl OhBoiUi {
         };
 
         let mut textures = Textures::<Texture>::new();
-        let gb_screen_texture = new_texture(GB_SCREEN_WIDTH, GB_SCREEN_HEIGHT, &gl, &mut textures)?;
+        let gb_screen_texture = new_texture(SCREEN_TEXTURE_WIDTH, SCREEN_TEXTURE_HEIGHT, &gl, &mut textures)?;
 
         let tile_texture = new_texture(32 * 8, 24 * 8, &gl, &mut textures)?;
         
@@ -165,6 +228,7 @@ impl OhBoiUi {
 
         let renderer = Renderer::new(&gl, &mut imgui, &mut textures, false)?;
         let game_window = GameWindow::new(gb_screen_texture);
+        let dmg_palette_window = DmgPaletteWindow::new();
 
         let audio_context = sdl.audio().unwrap();
         let spec = sdl2::audio::AudioSpecDesired {
@@ -174,6 +238,8 @@ impl OhBoiUi {
         };
         let audio_device = audio_context.open_queue::<f32, _>(None, &spec).unwrap();
         audio_device.resume();
+        // Controllers connected at startup are reported through ControllerDeviceAdded events as well
+        let game_controller = sdl.game_controller()?;
         cfg_if!{
             if #[cfg(feature = "debug_ui")] {
                 let tile_window = TileWindow::new(tile_texture);
@@ -181,10 +247,12 @@ impl OhBoiUi {
                 let rom_window = HexView::new("ROM".to_string());
                 let ext_ram_window = HexView::new("External RAM".to_string());
                 let disasm_window = DisassemblyView::new("Disassembly".to_string());
+                let profiler_window = ProfilerWindow::new();
+                let memory_search_window = MemorySearchWindow::new();
                 let log_buffer = log_buffer.unwrap_or(Arc::new(Mutex::new(VecDeque::new())));
-                Ok(Self { sdl, gl, gl_context, imgui, platform, sdl_window, renderer, game_window, tile_window, waveform_window, rom_window, ext_ram_window, disasm_window, textures, audio_device, log_buffer })
+                Ok(Self { sdl, gl, gl_context, imgui, platform, sdl_window, renderer, game_window, dmg_palette_window, tile_window, waveform_window, rom_window, ext_ram_window, disasm_window, profiler_window, memory_search_window, textures, audio_device, game_controller, controllers: Vec::new(), rumbling: false, tone: false, tone_phase: 0, tilt: AccelerometerInput::default(), log_buffer })
             } else {
-                Ok(Self { sdl, gl, gl_context, imgui, platform, sdl_window, renderer, game_window, textures, audio_device })
+                Ok(Self { sdl, gl, gl_context, imgui, platform, sdl_window, renderer, game_window, dmg_palette_window, textures, audio_device, game_controller, controllers: Vec::new(), rumbling: false, tone: false, tone_phase: 0, tilt: AccelerometerInput::default() })
             }
         }
     }
@@ -192,16 +260,33 @@ impl OhBoiUi {
     #[inline]
     fn process_sdl_events(&mut self, gb: &mut GameBoy) -> Result<bool, Box<dyn Error>> {
         let mut quit = false;
+        let (width, height) = self.sdl_window.size();
         self.sdl.event_pump()?.poll_iter().for_each(|event| {
             self.platform.handle_event(&mut self.imgui, &event);
+            match event {
+                Event::ControllerDeviceAdded { which, .. } => match self.game_controller.open(which) {
+                    Ok(controller) => self.controllers.push(controller),
+                    Err(e) => warn!("Failed to open game controller {}: {}", which, e)
+                },
+                Event::ControllerDeviceRemoved { which, .. } => self.controllers.retain(|c| c.instance_id() != which),
+                Event::ControllerAxisMotion { axis: Axis::LeftX, value, .. } => self.tilt.x = value as f32 / i16::MAX as f32,
+                Event::ControllerAxisMotion { axis: Axis::LeftY, value, .. } => self.tilt.y = -(value as f32) / i16::MAX as f32,
+                Event::MouseMotion { x, y, mousestate, .. } if mousestate.right() => {
+                    self.tilt.x = 2.0 * x as f32 / width as f32 - 1.0;
+                    self.tilt.y = 1.0 - 2.0 * y as f32 / height as f32;
+                },
+                Event::MouseButtonUp { mouse_btn: MouseButton::Right, .. } => self.tilt = AccelerometerInput::default(),
+                _ => {}
+            }
             quit = quit || sdl_event_handler(&event, gb).expect("SDL Event Handler error");
         });
+        gb.set_accelerometer(self.tilt);
 
         Ok(quit)
     }
 
     #[inline]
-    fn main_menu_bar(ui: &mut Ui) -> GameWindowEvent {
+    fn main_menu_bar(ui: &mut Ui, gb: &GameBoy) -> GameWindowEvent {
         if let Some(menubar) = ui.begin_main_menu_bar() {
             if let Some(menu) = ui.begin_menu("File") {
                 if ui.menu_item_config("Open").shortcut("Ctrl+O").build() {
@@ -213,15 +298,94 @@ impl OhBoiUi {
                         return Open(PathBuf::from(path));
                     }
                 }
+                if ui.menu_item("Open with patch") {
+                    let rom = tinyfiledialogs::open_file_dialog("Open ROM",
+                                                                "./",
+                                                                Some((&["*.gb", "*.gbc"], "Gameboy ROMs")));
+                    if let Some(rom) = rom {
+                        if let Some(patch) =
+                            tinyfiledialogs::open_file_dialog("Open patch",
+                                                              &rom,
+                                                              Some((&["*.ips", "*.ups", "*.bps"], "ROM patches")))
+                        {
+                            return GameWindowEvent::OpenPatched(PathBuf::from(rom), PathBuf::from(patch));
+                        }
+                    }
+                }
+                #[cfg(feature = "debug_ui")]
+                if ui.menu_item("Load symbols") {
+                    if let Some(path) =
+                        tinyfiledialogs::open_file_dialog("Load symbols",
+                                                          "./",
+                                                          Some((&["*.sym", "*.map"], "RGBDS symbol files")))
+                    {
+                        return GameWindowEvent::LoadSymbols(PathBuf::from(path));
+                    }
+                }
                 if ui.menu_item_config("Close").shortcut("Alt+F4").build() {
                     return Close;
                 }
                 menu.end();
             }
+            if let Some(menu) = ui.begin_menu("Hardware") {
+                if ui.menu_item("Automatic") {
+                    return GameWindowEvent::SetModel(None);
+                }
+                for (label, model) in [("Game Boy", Model::Dmg), ("Super Game Boy", Model::Sgb), ("Game Boy Color", Model::Cgb)] {
+                    if ui.menu_item_config(label).selected(gb.model() == model).build() {
+                        return GameWindowEvent::SetModel(Some(model));
+                    }
+                }
+                ui.separator();
+                if let Some(palettes) = ui.begin_menu_with_enabled("DMG game colors", gb.is_dmg_compat()) {
+                    for (label, direction, button) in COMPAT_PALETTE_COMBOS {
+                        if ui.menu_item(label) {
+                            if let Some(p) = CompatPalettes::for_keys(direction, button) {
+                                return GameWindowEvent::SetCompatPalettes(p);
+                            }
+                        }
+                    }
+                    palettes.end();
+                }
+                menu.end();
+            }
+            if let Some(menu) = ui.begin_menu("Settings") {
+                if let Some(palettes) = ui.begin_menu("DMG palette") {
+                    for preset in DmgPalettePreset::ALL {
+                        if ui.menu_item(preset.name()) {
+                            for id in [DmgPaletteId::Bg, DmgPaletteId::Obj0, DmgPaletteId::Obj1] {
+                                gb.set_dmg_palette(id, preset.shades());
+                            }
+                        }
+                    }
+                    ui.separator();
+                    if ui.menu_item("Custom...") {
+                        return GameWindowEvent::ToggleDmgPalettes;
+                    }
+                    palettes.end();
+                }
+                if let Some(corrections) = ui.begin_menu("Color correction") {
+                    for correction in ColorCorrection::ALL {
+                        if ui.menu_item_config(correction.name()).selected(gb.color_correction() == correction).build() {
+                            gb.set_color_correction(correction);
+                        }
+                    }
+                    corrections.end();
+                }
+                menu.end();
+            }
             if let Some(menu) = ui.begin_menu("Windows") {
                 if ui.menu_item_config("Waveform").selected(false).build() {
                     return ToggleWaveform;
                 }
+                #[cfg(feature = "debug_ui")]
+                if ui.menu_item("Profiler") {
+                    return GameWindowEvent::ToggleProfiler;
+                }
+                #[cfg(feature = "debug_ui")]
+                if ui.menu_item("Memory search") {
+                    return GameWindowEvent::ToggleMemorySearch;
+                }
                 menu.end();
             }
             menubar.end();
@@ -230,8 +394,41 @@ impl OhBoiUi {
         Nothing
     }
 
+    /// Forwards the strength of the cartridge's rumble motor, from 0.0 to 1.0, to the connected controllers.
+    pub fn set_rumble(&mut self, strength: f32) {
+        let on = strength > 0.0;
+        if !on && !self.rumbling {
+            return;
+        }
+        let intensity = (strength.clamp(0.0, 1.0) * 0xFFFF as f32) as u16;
+        let duration = if on { RUMBLE_DURATION_MS } else { 0 };
+        for controller in self.controllers.iter_mut() {
+            if let Err(e) = controller.set_rumble(intensity, intensity, duration) {
+                debug!("Failed to set rumble on {}: {}", controller.name(), e);
+            }
+        }
+        self.rumbling = on;
+    }
+
+    /// Turns the beep of the cartridge speaker on or off. It is mixed into the queued audio.
+    pub fn set_tone(&mut self, on: bool) {
+        self.tone = on;
+    }
+
     pub fn audio_callback(&mut self, audio: &[f32]) {
-        self.audio_device.queue_audio(audio);
+        let queued = if self.tone {
+            let mixed: Vec<f32> = audio.chunks(2).flat_map(|frame| {
+                self.tone_phase = (self.tone_phase + 1) % TONE_PERIOD;
+                let beep = if self.tone_phase < TONE_PERIOD / 2 { TONE_VOLUME } else { -TONE_VOLUME };
+                frame.iter().map(move |sample| sample + beep)
+            }).collect();
+            self.audio_device.queue_audio(&mixed)
+        } else {
+            self.audio_device.queue_audio(audio)
+        };
+        if let Err(e) = queued {
+            warn!("Failed to queue audio: {}", e);
+        }
     }
     pub fn show(&mut self, gb: &mut GameBoy, text: Option<String>, sample: (&[f32], &[f32], &[f32], &[f32])) -> Result<GameWindowEvent, Box<dyn Error>> {
         let quit = self.process_sdl_events(gb)?;
@@ -243,21 +440,32 @@ impl OhBoiUi {
         self.platform.prepare_frame(&mut self.imgui, &self.sdl_window, &event_pump);
         let ui = self.imgui.new_frame();
 
-        let menu_event = Self::main_menu_bar(ui);
+        let menu_event = Self::main_menu_bar(ui, gb);
         let window_size = self.sdl_window.size();
         self.game_window.show(ui, window_size, text);
+        if let GameWindowEvent::ToggleDmgPalettes = menu_event { self.dmg_palette_window.toggle() }
+        self.dmg_palette_window.show(ui, gb);
 
         cfg_if!{ if #[cfg(feature = "debug_ui")] {
             let hex_view_width = calc_hex_view_width(ui, 16);
             let rom_pos = [330.0, 20.0];
             self.tile_window.show(ui);
-            self.rom_window.show(ui, &gb.rom(), rom_pos, Some(0x4000));
+            let rom_labels = |offset: usize| {
+                let addr = if offset < 0x4000 { offset } else { 0x4000 + offset % 0x4000 };
+                gb.symbols().describe(offset / 0x4000, addr as u16)
+            };
+            self.rom_window.show(ui, &gb.rom(), rom_pos, Some(0x4000), Some(&rom_labels));
             let ext_ram_pos = [330.0, 20.0 + 300.0 + 20.0];
-            if let Some(ram) = gb.ext_ram() { self.ext_ram_window.show(ui, ram, ext_ram_pos, Some(0x2000)) }
+            let ext_ram_labels = |offset: usize| gb.symbols().describe(0, 0xA000 + (offset % 0x2000) as u16);
+            if let Some(ram) = gb.ext_ram() { self.ext_ram_window.show(ui, ram, ext_ram_pos, Some(0x2000), Some(&ext_ram_labels)) }
             let waveform_pos = [ui.item_rect_size()[0] + ui.cursor_pos()[0], 20.0];
             self.waveform_window.show(gb, ui, waveform_pos, sample);
             if let ToggleWaveform = menu_event.clone() { self.waveform_window.toggle() }
             self.disasm_window.show(ui, gb, [ui.item_rect_size()[0] + ui.cursor_pos()[0], 20.0 + 300.0 + 20.0]);
+            if let GameWindowEvent::ToggleProfiler = menu_event { self.profiler_window.toggle() }
+            self.profiler_window.show(ui, gb, [330.0, 20.0]);
+            if let GameWindowEvent::ToggleMemorySearch = menu_event { self.memory_search_window.toggle() }
+            self.memory_search_window.show(ui, gb, [330.0, 20.0]);
             widgets::log_window(ui, "Log", Arc::clone(&self.log_buffer));
         }}
 
@@ -270,7 +478,8 @@ impl OhBoiUi {
         Ok(menu_event)
     }
 
-    pub fn draw_game_screen(&mut self, screen: &[u8]) {
+    pub fn draw_game_screen(&mut self, screen: &[u8], (width, height): (usize, usize)) {
+        self.game_window.screen_size = (width, height);
         let &texture = self.textures.get(self.game_window.texture_id()).unwrap();
         unsafe {
             self.gl.active_texture(texture.0.into());
@@ -279,8 +488,8 @@ impl OhBoiUi {
                 0,
                 0 as _,
                 0 as _,
-                GB_SCREEN_WIDTH as _,
-                GB_SCREEN_HEIGHT as _,
+                width as _,
+                height as _,
                 glow::RGBA as _,
                 glow::UNSIGNED_BYTE,
                 PixelUnpackData::Slice(screen)
@@ -383,11 +592,13 @@ impl WaveformWindow {
 
 pub struct GameWindow {
     texture: TextureId,
+    /// Size of the last frame drawn in the texture.
+    screen_size: (usize, usize),
 }
 
 impl GameWindow {
     pub fn new(texture: TextureId) -> Self {
-        Self { texture }
+        Self { texture, screen_size: (GB_SCREEN_WIDTH, GB_SCREEN_HEIGHT) }
     }
 
     fn game_screen(&self, ui: &mut Ui, screen_pos: [f32; 2], mut screen_size: [f32; 2], text: Option<String>) {
@@ -409,14 +620,16 @@ impl GameWindow {
                     ui.text(t);
                     screen_size[1] -= ui.text_line_height_with_spacing();
                 }
-                imgui::Image::new(self.texture, ui.content_region_avail()).build(ui);
+                let uv = [self.screen_size.0 as f32 / SCREEN_TEXTURE_WIDTH as f32,
+                          self.screen_size.1 as f32 / SCREEN_TEXTURE_HEIGHT as f32];
+                imgui::Image::new(self.texture, ui.content_region_avail()).uv1(uv).build(ui);
             }).unwrap();
     }
 
     pub fn show(&self, ui: &mut Ui, sdl_window_size: (u32, u32), text: Option<String>) {
         let [_, imgui_menu_height] = ui.item_rect_size();
         let game_screen_size = if cfg!(feature = "debug_ui") {
-            [(GB_SCREEN_WIDTH * 2) as f32, (GB_SCREEN_HEIGHT * 2) as f32]
+            [(self.screen_size.0 * 2) as f32, (self.screen_size.1 * 2) as f32]
         } else {
             [sdl_window_size.0 as f32, sdl_window_size.1 as f32 - imgui_menu_height]
         };
@@ -2,7 +2,7 @@
 
 use std::cmp::{max_by, min};
 use imgui::{Condition, Ui};
-pub fn hex_view(ui: &Ui, bytes_per_row: usize, data: &[u8], split_threshold: Option<usize>, selected: &mut usize, id: Option<&str>) {
+pub fn hex_view(ui: &Ui, bytes_per_row: usize, data: &[u8], split_threshold: Option<usize>, selected: &mut usize, id: Option<&str>, labels: Option<&dyn Fn(usize) -> Option<String>>) {
     let spacing = ui.clone_style().item_spacing;
     let offset_width = max_by(ui.calc_text_size("0".repeat(6))[0], ui.calc_text_size("Offset")[0], |v1,v2| v1.partial_cmp(v2).unwrap()) + spacing[0];
     let hex_width = (ui.calc_text_size("00")[0] + spacing[0]) * bytes_per_row as f32;
@@ -26,10 +26,18 @@ pub fn hex_view(ui: &Ui, bytes_per_row: usize, data: &[u8], split_threshold: Opt
     ui.text("ASCII");
     ui.next_column();
     for (i, chunk) in data[(bank_size * *selected)..min(bank_size * (*selected + 1), data.len())].chunks(bytes_per_row).enumerate() {
-        ui.text(format!("{:06X}", bank_size * *selected + i * bytes_per_row));
+        let row_offset = bank_size * *selected + i * bytes_per_row;
+        ui.text(format!("{:06X}", row_offset));
         ui.next_column();
-        for byte in chunk.iter() {
+        for (j, byte) in chunk.iter().enumerate() {
             ui.text(format!("{:02X}", byte));
+            if let Some(labels) = labels {
+                if ui.is_item_hovered() {
+                    if let Some(label) = labels(row_offset + j) {
+                        ui.tooltip_text(label);
+                    }
+                }
+            }
             ui.same_line();
         }
         ui.next_column();
@@ -61,7 +69,7 @@ impl HexView {
         }
     }
 
-    pub fn show(&mut self, ui: &mut Ui, data: &[u8], position: [f32; 2], split_threshold: Option<usize>) {
+    pub fn show(&mut self, ui: &mut Ui, data: &[u8], position: [f32; 2], split_threshold: Option<usize>, labels: Option<&dyn Fn(usize) -> Option<String>>) {
         if !self.toggle {
             return;
         }
@@ -70,7 +78,7 @@ impl HexView {
             .position(position, Condition::FirstUseEver)
             .size([width, 300.0], Condition::FirstUseEver)
             .build(|| {
-                hex_view(ui, 16, data, split_threshold, &mut self.current_bank, None);
+                hex_view(ui, 16, data, split_threshold, &mut self.current_bank, None, labels);
             });
     }
 
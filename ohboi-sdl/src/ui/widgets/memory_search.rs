@@ -0,0 +1,136 @@
+// Copyright Antonio Porsia 2025. Licensed under the EUPL-1.2 or later.
+
+use imgui::{Condition, TableFlags, Ui};
+use ohboi_core::GameBoy;
+use ohboi_core::memory::search::{Comparison, MemorySearch, SearchTarget, ValueSize};
+
+/// Only list candidates once the search has been narrowed down this far.
+const MAX_ROWS: usize = 500;
+
+const SIZES: [(&str, ValueSize); 3] = [
+    ("8-bit", ValueSize::U8),
+    ("16-bit little endian", ValueSize::U16Le),
+    ("16-bit big endian", ValueSize::U16Be),
+];
+const COMPARISONS: [(&str, Comparison); 4] = [
+    ("==", Comparison::Equal),
+    ("!=", Comparison::NotEqual),
+    (">", Comparison::Greater),
+    ("<", Comparison::Less),
+];
+
+/// Parses a decimal value, or a hexadecimal one prefixed with `$` or `0x`.
+fn parse_value(text: &str) -> Option<u16> {
+    let text = text.trim();
+    match text.strip_prefix('$').or_else(|| text.strip_prefix("0x")) {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok()
+    }
+}
+
+pub struct MemorySearchWindow {
+    toggle: bool,
+    search: Option<MemorySearch>,
+    size: usize,
+    comparison: usize,
+    compare_to_value: bool,
+    value: String,
+}
+
+impl MemorySearchWindow {
+    pub fn new() -> Self {
+        Self { toggle: false, search: None, size: 0, comparison: 0, compare_to_value: false, value: String::new() }
+    }
+
+    fn filter(&mut self, gb: &GameBoy, comparison: Comparison, target: SearchTarget) {
+        if let Some(ref mut search) = self.search {
+            search.filter(gb.snapshot_memory(), comparison, target);
+        }
+    }
+
+    pub fn show(&mut self, ui: &Ui, gb: &mut GameBoy, position: [f32; 2]) {
+        if !self.toggle {
+            return;
+        }
+        let mut opened = self.toggle;
+        ui.window("Memory search")
+            .position(position, Condition::FirstUseEver)
+            .size([360.0, 400.0], Condition::FirstUseEver)
+            .opened(&mut opened)
+            .build(|| {
+                ui.combo_simple_string("Size", &mut self.size, &SIZES.map(|(name, _)| name));
+                if ui.button("New search") {
+                    self.search = Some(MemorySearch::new(SIZES[self.size].1, gb.snapshot_memory()));
+                }
+                if self.search.is_none() {
+                    return;
+                }
+
+                ui.separator();
+                ui.set_next_item_width(60.0);
+                ui.combo_simple_string("##comparison", &mut self.comparison, &COMPARISONS.map(|(name, _)| name));
+                ui.same_line();
+                ui.radio_button("Previous", &mut self.compare_to_value, false);
+                ui.same_line();
+                ui.radio_button("Value", &mut self.compare_to_value, true);
+                ui.same_line();
+                ui.set_next_item_width(80.0);
+                ui.input_text("##value", &mut self.value).build();
+                let target = if self.compare_to_value {
+                    parse_value(&self.value).map(SearchTarget::Value)
+                } else {
+                    Some(SearchTarget::Previous)
+                };
+                ui.disabled(target.is_none(), || {
+                    if ui.button("Filter") {
+                        if let Some(target) = target {
+                            self.filter(gb, COMPARISONS[self.comparison].1, target);
+                        }
+                    }
+                });
+                ui.same_line();
+                if ui.button("Changed") {
+                    self.filter(gb, Comparison::NotEqual, SearchTarget::Previous);
+                }
+                ui.same_line();
+                if ui.button("Unchanged") {
+                    self.filter(gb, Comparison::Equal, SearchTarget::Previous);
+                }
+
+                let Some(ref search) = self.search else {
+                    return;
+                };
+                let candidates = search.candidates();
+                ui.text(format!("{} candidates", candidates.len()));
+                if candidates.len() > MAX_ROWS {
+                    return;
+                }
+                let current = gb.snapshot_memory();
+                let flags = TableFlags::RESIZABLE | TableFlags::ROW_BG | TableFlags::BORDERS | TableFlags::SCROLL_Y;
+                if let Some(_table) = ui.begin_table_with_flags("##memory_search_candidates", 4, flags) {
+                    ui.table_setup_scroll_freeze(0, 1);
+                    for header in ["Address", "Previous", "Current", "Location"] {
+                        ui.table_setup_column(header);
+                    }
+                    ui.table_headers_row();
+                    for &(bank, addr) in candidates {
+                        let format = |value: Option<u16>| value.map(|v| format!("{} (${:X})", v, v)).unwrap_or_default();
+                        ui.table_next_row();
+                        ui.table_next_column();
+                        ui.text(format!("{:02X}:{:04X}", bank, addr));
+                        ui.table_next_column();
+                        ui.text(format(search.value(bank, addr)));
+                        ui.table_next_column();
+                        ui.text(format(current.value(bank, addr, search.size())));
+                        ui.table_next_column();
+                        ui.text(gb.describe_address(addr).unwrap_or_default());
+                    }
+                }
+            });
+        self.toggle = opened;
+    }
+
+    pub fn toggle(&mut self) {
+        self.toggle = !self.toggle;
+    }
+}
@@ -1,12 +1,25 @@
 // Copyright Antonio Porsia 2025. Licensed under the EUPL-1.2 or later.
 
 pub mod hexview;
+pub mod palette;
+#[cfg(feature = "debug_ui")]
+pub mod memory_search;
+#[cfg(feature = "debug_ui")]
+pub mod profiler;
 
 use std::collections::VecDeque;
 use std::sync::{Arc, Mutex};
 use imgui::{Condition, StyleColor, Ui};
+#[cfg(feature = "debug_ui")]
+use imgui::{MouseButton, SelectableFlags};
 use ohboi_core::cpu::Register16;
 use ohboi_core::GameBoy;
+#[cfg(feature = "debug_ui")]
+use std::path::Path;
+#[cfg(feature = "debug_ui")]
+use log::error;
+#[cfg(feature = "debug_ui")]
+use ohboi_core::debug::trace::{TraceFilter, Tracer};
 use crate::logging::ImguiLogString;
 
 pub fn log_window(ui: &Ui, title: &str, log: Arc<Mutex<VecDeque<ImguiLogString>>>) {
@@ -39,7 +52,8 @@ pub fn log_window(ui: &Ui, title: &str, log: Arc<Mutex<VecDeque<ImguiLogString>>
 pub(super) struct DisassemblyView {
     // ...
     toggle: bool,
-    title: String
+    title: String,
+    cursor: Option<u16>
     // ...
 }
 
@@ -50,7 +64,8 @@ impl DisassemblyView {
     pub fn new(title: String) -> Self {
         DisassemblyView {
             toggle: true,
-            title
+            title,
+            cursor: None
         }
     }
 
@@ -75,6 +90,59 @@ impl DisassemblyView {
                 if ui.button("Step") {
                     gb.debug_step();
                 }
+                ui.same_line();
+                if ui.button("Step over") {
+                    gb.debug_step_over();
+                }
+                ui.same_line();
+                if ui.button("Step out") {
+                    gb.debug_step_out();
+                }
+                ui.same_line();
+                ui.disabled(self.cursor.is_none(), || {
+                    if ui.button("Run to cursor") {
+                        if let Some(addr) = self.cursor {
+                            gb.debug_run_to(addr);
+                        }
+                    }
+                });
+                if ui.button("Scanline") {
+                    gb.debug_step_scanline();
+                }
+                ui.same_line();
+                if ui.button("Frame") {
+                    gb.debug_step_frame();
+                }
+                ui.same_line();
+                if gb.is_tracing() {
+                    if ui.button("Stop trace") {
+                        gb.stop_trace();
+                    }
+                } else if ui.button("Trace") {
+                    if let Some(path) = tinyfiledialogs::save_file_dialog("Save trace", "trace.log") {
+                        match Tracer::to_file(Path::new(&path), TraceFilter::default()) {
+                            Ok(tracer) => gb.start_trace(tracer),
+                            Err(e) => error!("Failed to create trace file {}: {}", path, e)
+                        }
+                    }
+                }
+                ui.same_line();
+                if gb.is_logging_code_data() {
+                    if ui.button("Save CDL") {
+                        if let Err(e) = gb.save_code_data_log() {
+                            error!("Failed to save code/data log: {}", e);
+                        }
+                    }
+                    ui.same_line();
+                    if ui.button("Stop CDL") {
+                        if let Err(e) = gb.save_code_data_log() {
+                            error!("Failed to save code/data log: {}", e);
+                        }
+                        gb.stop_code_data_log();
+                    }
+                } else if ui.button("Log code/data") {
+                    gb.start_code_data_log();
+                }
                 ui.columns(2, "##disassembly_view_columns_ao", false);
                 ui.set_column_width(0, 300.0);
                 ui.set_column_width(1, 100.0);
@@ -92,15 +160,35 @@ impl DisassemblyView {
                         ui.next_column();
                         ui.text("Instruction");
                         ui.next_column();
-                        for (addr, instruction) in instructions.iter() {
-                            let color = if *addr == cur_pc {
+                        for instruction in instructions.iter() {
+                            if let Some(label) = gb.symbols().label(instruction.bank, instruction.addr) {
+                                ui.next_column();
+                                ui.text_colored([1.0, 1.0, 0.0, 1.0], format!("{}:", label));
+                                ui.next_column();
+                            }
+                            let color = if instruction.addr as usize == cur_pc {
                                 ui.push_style_color(StyleColor::Text, [1.0, 0.0, 0.0, 1.0])
                             } else {
                                 ui.push_style_color(StyleColor::Text, [1.0, 1.0, 1.0, 1.0])
                             };
-                            ui.text(format!("{:04X}", addr));
+                            let breakpoint = if gb.breakpoints().contains(&instruction.addr) { "*" } else { " " };
+                            let address = if (0x4000..0x8000).contains(&instruction.addr) {
+                                format!("{}{:02X}:{:04X}", breakpoint, instruction.bank, instruction.addr)
+                            } else {
+                                format!("{}{:04X}", breakpoint, instruction.addr)
+                            };
+                            // Click to place the cursor, double click to toggle a breakpoint
+                            if ui.selectable_config(&address)
+                                .selected(self.cursor == Some(instruction.addr))
+                                .flags(SelectableFlags::ALLOW_DOUBLE_CLICK)
+                                .build() {
+                                self.cursor = Some(instruction.addr);
+                                if ui.is_mouse_double_clicked(MouseButton::Left) {
+                                    gb.toggle_breakpoint(instruction.addr);
+                                }
+                            }
                             ui.next_column();
-                            ui.text(instruction);
+                            ui.text(&instruction.text);
                             ui.next_column();
                             color.pop();
                         }
@@ -111,6 +199,23 @@ impl DisassemblyView {
                 ui.text(format!("BC: {:04X}", registers.get_reg16(Register16::BC)));
                 ui.text(format!("DE: {:04X}", registers.get_reg16(Register16::DE)));
                 ui.text(format!("HL: {:04X}", registers.get_reg16(Register16::HL)));
+                ui.separator();
+                ui.text("Call stack");
+                for frame in gb.call_stack().iter().rev() {
+                    let name = gb.symbols().describe(frame.bank, frame.target)
+                        .unwrap_or_else(|| format!("${:04X}", frame.target));
+                    ui.text(if frame.interrupt { format!("{} (int)", name) } else { name });
+                }
+                if !gb.breakpoints().is_empty() {
+                    ui.separator();
+                    ui.text("Breakpoints");
+                    for addr in gb.breakpoints().iter() {
+                        match gb.describe_address(*addr) {
+                            Some(label) => ui.text(format!("{:04X} {}", addr, label)),
+                            None => ui.text(format!("{:04X}", addr))
+                        }
+                    }
+                }
             });
     }
 
@@ -0,0 +1,64 @@
+// Copyright Antonio Porsia 2025. Licensed under the EUPL-1.2 or later.
+
+use imgui::{Condition, Ui};
+use ohboi_core::GameBoy;
+use ohboi_core::ppu::{DmgPaletteId, DmgPalettePreset};
+
+const PALETTES: [(&str, DmgPaletteId); 3] = [
+    ("Background", DmgPaletteId::Bg),
+    ("Objects 0", DmgPaletteId::Obj0),
+    ("Objects 1", DmgPaletteId::Obj1),
+];
+
+/// Edits the shades of the BG, OBJ0 and OBJ1 palettes of DMG games.
+pub struct DmgPaletteWindow {
+    toggle: bool,
+}
+
+impl DmgPaletteWindow {
+    pub fn new() -> Self {
+        Self { toggle: false }
+    }
+
+    pub fn toggle(&mut self) {
+        self.toggle = !self.toggle;
+    }
+
+    pub fn show(&mut self, ui: &Ui, gb: &GameBoy) {
+        if !self.toggle {
+            return;
+        }
+        ui.window("DMG palettes")
+            .size([340.0, 300.0], Condition::FirstUseEver)
+            .opened(&mut self.toggle)
+            .build(|| {
+                for (label, id) in PALETTES {
+                    let _id = ui.push_id(label);
+                    ui.text(label);
+                    let mut shades = gb.dmg_palette(id);
+                    let mut changed = false;
+                    for (i, shade) in shades.iter_mut().enumerate() {
+                        let mut color = shade.map(|c| c as f32 / 255.0);
+                        if ui.color_edit4_config(format!("Color {}", i), &mut color).alpha(false).inputs(false).build() {
+                            *shade = color.map(|c| (c * 255.0).round() as u8);
+                            changed = true;
+                        }
+                        ui.same_line();
+                    }
+                    ui.new_line();
+                    if let Some(_combo) = ui.begin_combo("Preset", "Select a preset") {
+                        for preset in DmgPalettePreset::ALL {
+                            if ui.selectable(preset.name()) {
+                                shades = preset.shades();
+                                changed = true;
+                            }
+                        }
+                    }
+                    if changed {
+                        gb.set_dmg_palette(id, shades);
+                    }
+                    ui.separator();
+                }
+            });
+    }
+}
@@ -0,0 +1,130 @@
+// Copyright Antonio Porsia 2025. Licensed under the EUPL-1.2 or later.
+
+use std::fs;
+use imgui::{Condition, TableColumnSetup, TableFlags, TableSortDirection, Ui};
+use log::error;
+use ohboi_core::GameBoy;
+
+/// Only the hottest entries are listed, building thousands of rows every frame is too slow.
+const MAX_ROWS: usize = 500;
+
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum SortKey {
+    Number(u64),
+    Text(String),
+}
+
+struct Row {
+    cells: Vec<String>,
+    keys: Vec<SortKey>,
+}
+
+fn percent(cycles: u64, total: u64) -> String {
+    format!("{:.2}%", if total == 0 { 0.0 } else { cycles as f64 * 100.0 / total as f64 })
+}
+
+fn sortable_table(ui: &Ui, id: &str, headers: &[&str], mut rows: Vec<Row>) {
+    let flags = TableFlags::SORTABLE | TableFlags::RESIZABLE | TableFlags::ROW_BG | TableFlags::BORDERS | TableFlags::SCROLL_Y;
+    if let Some(_table) = ui.begin_table_with_flags(id, headers.len(), flags) {
+        ui.table_setup_scroll_freeze(0, 1);
+        for header in headers {
+            ui.table_setup_column_with(TableColumnSetup::new(*header));
+        }
+        ui.table_headers_row();
+
+        if let Some(specs) = ui.table_sort_specs_mut() {
+            if let Some(spec) = specs.specs().iter().next() {
+                let column = spec.column_idx();
+                rows.sort_by(|a, b| a.keys[column].cmp(&b.keys[column]));
+                if matches!(spec.sort_direction(), Some(TableSortDirection::Descending)) {
+                    rows.reverse();
+                }
+            }
+        }
+        for row in rows {
+            ui.table_next_row();
+            for cell in row.cells {
+                ui.table_next_column();
+                ui.text(cell);
+            }
+        }
+    }
+}
+
+pub struct ProfilerWindow {
+    toggle: bool,
+}
+
+impl ProfilerWindow {
+    pub fn new() -> Self {
+        Self { toggle: false }
+    }
+
+    pub fn show(&mut self, ui: &Ui, gb: &mut GameBoy, position: [f32; 2]) {
+        if !self.toggle {
+            return;
+        }
+        ui.window("Profiler")
+            .position(position, Condition::FirstUseEver)
+            .size([560.0, 400.0], Condition::FirstUseEver)
+            .opened(&mut self.toggle)
+            .build(|| {
+                if gb.profiler().is_some() {
+                    if ui.button("Stop") {
+                        gb.stop_profiling();
+                    }
+                    ui.same_line();
+                    if ui.button("Reset") {
+                        gb.start_profiling();
+                    }
+                    ui.same_line();
+                    if ui.button("Save report") {
+                        if let Some(path) = tinyfiledialogs::save_file_dialog("Save profile", "profile.txt") {
+                            let report = gb.profile_report(usize::MAX).unwrap_or_default();
+                            if let Err(e) = fs::write(&path, report) {
+                                error!("Failed to save profile to {}: {}", path, e);
+                            }
+                        }
+                    }
+                } else if ui.button("Start") {
+                    gb.start_profiling();
+                }
+
+                let Some(profiler) = gb.profiler() else {
+                    return;
+                };
+                let total = profiler.total_cycles();
+                ui.text(format!("Total: {} M-cycles", total));
+                ui.text(format!("Halted: {} ({})", profiler.halted_cycles(), percent(profiler.halted_cycles(), total)));
+                ui.text(format!("In interrupts: {} ({})", profiler.interrupt_cycles(), percent(profiler.interrupt_cycles(), total)));
+
+                let name = |bank: usize, addr: u16| gb.symbols().describe(bank, addr).unwrap_or_default();
+                if let Some(_tabs) = ui.tab_bar("##profiler_tabs") {
+                    if let Some(_tab) = ui.tab_item("Routines") {
+                        let rows = profiler.functions().into_iter().take(MAX_ROWS).map(|f| Row {
+                            cells: vec![format!("{:02X}:{:04X}", f.bank, f.addr), name(f.bank, f.addr),
+                                        f.total_cycles.to_string(), percent(f.total_cycles, total),
+                                        f.self_cycles.to_string(), percent(f.self_cycles, total)],
+                            keys: vec![SortKey::Number(((f.bank as u64) << 16) | f.addr as u64), SortKey::Text(name(f.bank, f.addr)),
+                                       SortKey::Number(f.total_cycles), SortKey::Number(f.total_cycles),
+                                       SortKey::Number(f.self_cycles), SortKey::Number(f.self_cycles)],
+                        }).collect();
+                        sortable_table(ui, "##profiler_routines", &["Address", "Name", "Total", "Total %", "Self", "Self %"], rows);
+                    }
+                    if let Some(_tab) = ui.tab_item("Addresses") {
+                        let rows = profiler.hotspots().into_iter().take(MAX_ROWS).map(|((bank, addr), cycles)| Row {
+                            cells: vec![format!("{:02X}:{:04X}", bank, addr), name(bank, addr),
+                                        cycles.to_string(), percent(cycles, total)],
+                            keys: vec![SortKey::Number(((bank as u64) << 16) | addr as u64), SortKey::Text(name(bank, addr)),
+                                       SortKey::Number(cycles), SortKey::Number(cycles)],
+                        }).collect();
+                        sortable_table(ui, "##profiler_addresses", &["Address", "Location", "Cycles", "%"], rows);
+                    }
+                }
+            });
+    }
+
+    pub fn toggle(&mut self) {
+        self.toggle = !self.toggle;
+    }
+}